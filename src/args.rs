@@ -1,172 +1,1363 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand};
 
-use crate::mirror::{TargetDb, DEFAULT_SOURCE_URL};
+use crate::{
+    clock::DEFAULT_CLOCK_SKEW_TOLERANCE,
+    color::ColorChoice,
+    concurrency::Threads,
+    geo::Coordinates,
+    hook::Operation,
+    http::IpFamily,
+    logging::{LogFormat, LogTarget},
+    manjaro::{Branch, MANJARO_SOURCE_URL},
+    mirror::{
+        Flavor, FollowRedirects, Layout, OutputFormat, SortBy, StatsFormat, StatusFormat, TargetDb,
+        Verify, DEFAULT_ARCH, DEFAULT_MAX_REDIRECTS, DEFAULT_SOURCE_URL,
+    },
+    probe::Probe,
+    report::ReportFormat,
+    samples::Aggregate,
+    tls::TlsVersion,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Arguments {
-    /// Arch Linux mirrors status's data source
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arch Linux mirrors status's data source, URL or local file (path or `file://` URL), `-` for STDIN.
+    /// Can be given multiple times, each source is tried in order until one succeeds.
     #[arg(
         short = 'S',
         long,
         value_name = "URL",
-        default_value = DEFAULT_SOURCE_URL
+        default_value = DEFAULT_SOURCE_URL,
+        default_value_if("flavor", "manjaro", MANJARO_SOURCE_URL),
+        default_value_if("status_format", "manjaro", MANJARO_SOURCE_URL)
     )]
-    pub source_url: String,
+    pub source_url: Vec<String>,
+
+    /// Fetch every `--source-url` and merge them instead of failing over,
+    /// mirrors of later sources override those with the same URL
+    #[arg(long, conflicts_with_all = ["pipeline", "input_mirrorlist", "urls_from"])]
+    pub merge_sources: bool,
 
-    /// Choose speed test target database file
+    /// Rank servers of an existing pacman mirrorlist instead of mirrors status
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["pipeline", "no_benchmark"]
+    )]
+    pub input_mirrorlist: Option<PathBuf>,
+
+    /// Rank base URLs listed in file (one per line) instead of mirrors status.
+    /// Repositories must have Arch Linux mirror layout `<URL>$repo/os/$arch`.
     #[arg(
-        short = 't',
         long,
+        value_name = "FILE",
+        conflicts_with_all = ["input_mirrorlist", "pipeline", "no_benchmark"]
+    )]
+    pub urls_from: Option<PathBuf>,
+
+    /// Choose speed test target database file, several comma-separated databases are
+    /// all measured and their transfer rates combined
+    #[arg(
+        short = 't',
+        long = "target-db",
         value_name = "REPO-NAME",
         ignore_case = true,
         default_value = "Extra",
+        value_delimiter = ',',
         value_enum
     )]
-    pub target_db: TargetDb,
+    pub target_dbs: Vec<TargetDb>,
+
+    /// How transfer rates of several target databases are combined, `min` for the worst case
+    #[arg(
+        long,
+        value_name = "METHOD",
+        ignore_case = true,
+        default_value = "mean",
+        value_enum
+    )]
+    pub target_db_aggregate: Aggregate,
+
+    /// Distribution whose mirrors are ranked
+    #[arg(long, value_enum, value_name = "FLAVOR", default_value_t)]
+    pub flavor: Flavor,
+
+    /// Format of mirrors status, the one of `--flavor` by default
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub status_format: Option<StatusFormat>,
+
+    /// Path of repositories relative to mirror's URL, with `$repo` and `$arch` like pacman
+    /// mirror list (e.g. `$repo/os/$arch`), the one of `--flavor` by default
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_repo_path)]
+    pub repo_path_template: Option<String>,
+
+    /// Server line of mirror list with placeholders of `--template`,
+    /// e.g. `Server = {url}$repo/$arch`
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "template", value_parser = parse_line_template)]
+    pub mirrorlist_line_template: Option<String>,
+
+    /// Branch of Manjaro mirrors, with `--flavor manjaro`
+    #[arg(long, value_enum, value_name = "BRANCH", default_value_t)]
+    pub branch: Branch,
+
+    /// Architecture of benchmarked database file, e.g. `aarch64`.
+    /// Mirror list keeps `$arch`, which pacman replaces with its `Architecture`.
+    #[arg(long, value_name = "ARCH", value_parser = parse_arch, default_value = DEFAULT_ARCH)]
+    pub arch: String,
 
     /// Mirror list output file
     #[arg(short = 'o', long, value_name = "OUTPUT-FILE")]
     pub output_file: Option<PathBuf>,
 
-    /// Statistics output file
+    /// Replace existing output file atomically, keeping the old one as `<OUTPUT-FILE>.bak`
+    #[arg(long, requires = "output_file")]
+    pub in_place: bool,
+
+    /// Print the new mirror list and ask before writing `--output-file`
+    #[arg(long, requires = "output_file", conflicts_with = "daemon")]
+    pub confirm: bool,
+
+    /// Keep the last N replaced output files as `<OUTPUT-FILE>.1` (newest) to `<OUTPUT-FILE>.N`, instead of `.bak`
+    #[arg(long, value_name = "N", requires = "output_file")]
+    pub backup: Option<usize>,
+
+    /// Keep existing mirror list unless the new one is faster by at least PERCENT, e.g. `20%`.
+    /// Servers of existing mirror list are benchmarked too.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_percent,
+        conflicts_with = "no_benchmark"
+    )]
+    pub min_improvement: Option<f64>,
+
+    /// Check that every selected mirror serves database file, then drop (default) or fail on those that do not
+    #[arg(
+        long,
+        value_name = "MODE",
+        ignore_case = true,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "drop"
+    )]
+    pub verify: Option<Verify>,
+
+    /// Skip mirrors not serving signatures (`.db.sig`) of target databases,
+    /// which pacman with `DatabaseRequired` fails on
+    #[arg(long, conflicts_with = "no_benchmark")]
+    pub require_sig: bool,
+
+    /// Skip mirrors not serving files databases (`.files`) of target databases,
+    /// which `pacman -F` needs
+    #[arg(long, conflicts_with = "no_benchmark")]
+    pub require_files_db: bool,
+
+    /// Record in statistics file whether mirrors honor range request, which resuming downloads needs
+    #[arg(long, conflicts_with = "no_benchmark")]
+    pub probe_range: bool,
+
+    /// Skip mirrors not honoring range request
+    #[arg(long, conflicts_with = "no_benchmark")]
+    pub require_range: bool,
+
+    /// Record in statistics file what HTTPS mirrors negotiate, e.g. `alpn` is `h2` for HTTP/2
+    #[arg(long, conflicts_with = "no_benchmark")]
+    pub probe_tls: bool,
+
+    /// Skip mirrors whose HTTPS endpoint cannot negotiate at least VERSION,
+    /// and mirrors without HTTPS
+    #[arg(
+        long,
+        value_enum,
+        value_name = "VERSION",
+        conflicts_with = "no_benchmark"
+    )]
+    pub min_tls: Option<TlsVersion>,
+
+    /// Skip mirrors whose certificate is invalid or expires within DAYS
+    #[arg(long, value_name = "DAYS", conflicts_with = "no_benchmark")]
+    pub min_cert_days: Option<u32>,
+
+    /// Measure transfer rate over IPv4 or IPv6 only, the family pacman uses on this host;
+    /// mirrors without an address of FAMILY are skipped
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FAMILY",
+        conflicts_with = "no_benchmark"
+    )]
+    pub resolve: Option<IpFamily>,
+
+    /// Also measure transfer rate over IPv4 and IPv6 separately, recording both in statistics file
+    /// with the faster family and whether IPv6 is broken
+    #[arg(long, conflicts_with_all = ["no_benchmark", "resolve"])]
+    pub dual_stack: bool,
+
+    /// Drop mirrors whose target database is older than the one of `--content-reference`
+    /// by more than DURATION (e.g. `2h`), catching mirrors serving stale files
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with = "no_benchmark"
+    )]
+    pub max_content_lag: Option<Duration>,
+
+    /// Mirror whose target database is the reference of `--max-content-lag`,
+    /// the fastest benchmarked mirror by default
+    #[arg(long, value_name = "URL", requires = "max_content_lag")]
+    pub content_reference: Option<String>,
+
+    /// Keep running and update mirror list every `--interval`
+    #[arg(long, conflicts_with = "dry_run")]
+    pub daemon: bool,
+
+    /// Time between updates of `--daemon` (e.g. `12h`, `1d`), a random delay of up to 10% is added
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "12h",
+        requires = "daemon"
+    )]
+    pub interval: Duration,
+
+    /// Show more log messages, `-v` for debug messages, `-vv` for debug messages of libraries too.
+    /// `RUST_LOG` environment variable overrides it.
+    #[arg(short = 'v', long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Show only warnings and errors
+    #[arg(short = 'q', long, global = true)]
+    pub quiet: bool,
+
+    /// Format of log messages
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true,
+        value_enum
+    )]
+    pub log_format: LogFormat,
+
+    /// When to color summary and ranking table printed to terminal
+    #[arg(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        global = true,
+        value_enum
+    )]
+    pub color: ColorChoice,
+
+    /// Destination of log messages, `journald` ignores `--log-format`
+    #[arg(
+        long,
+        value_name = "TARGET",
+        default_value = "stderr",
+        global = true,
+        value_enum
+    )]
+    pub log_target: LogTarget,
+
+    /// Fetch, filter, and benchmark mirrors, but print files that would be written instead of writing them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Overwrite existing output, statistics, and report files
+    #[arg(short = 'f', long, visible_alias = "overwrite")]
+    pub force: bool,
+
+    /// Format of mirror list
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        ignore_case = true,
+        default_value = "pacman",
+        value_enum
+    )]
+    pub format: OutputFormat,
+
+    /// Replace only the block generated by pacman-mirrorup in existing mirrorlist, keeping custom entries
+    #[arg(long, value_name = "PATH", conflicts_with = "output_file")]
+    pub merge_into: Option<PathBuf>,
+
+    /// Comment each server in mirror list with its country, transfer rate, and weighted score
+    #[arg(long)]
+    pub verbose_output: bool,
+
+    /// Group servers in mirror list under `## Country` headers, ordered by each country's best mirror
+    #[arg(long)]
+    pub group_by_country: bool,
+
+    /// Append N more ranked mirrors as commented out servers, ready to be uncommented as fallback
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub commented_extra: u32,
+
+    /// Mirror list template, its first line with `{url}` is written for each mirror
+    #[arg(long, value_name = "FILE")]
+    pub template: Option<PathBuf>,
+
+    /// Always place mirror URL first in mirror list, before ranked mirrors (can be repeated)
+    #[arg(long, value_name = "URL")]
+    pub pin: Vec<String>,
+
+    /// Read pinned mirror URLs from FILE, one per line, placed after those of --pin
+    #[arg(long, value_name = "FILE")]
+    pub pin_from: Option<PathBuf>,
+
+    /// Statistics output file, `-` for STDOUT
     #[arg(short = 's', long, value_name = "STATS-FILE")]
     pub stats_file: Option<PathBuf>,
 
-    /// Maximum number of synced mirrors to check,
-    #[arg(short = 'c', long, value_name = "NUMBER", default_value = "100")]
-    pub max_check: u32,
+    /// Append measurements of every run to SQLite database at PATH
+    /// [default: $XDG_STATE_HOME/pacman-mirrorup/history.db, /var/lib/pacman-mirrorup/history.db for root]
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    pub history_db: Option<Option<PathBuf>>,
+
+    /// Format of statistics file, inferred from its extension if not given
+    #[arg(long, value_name = "FORMAT", ignore_case = true, value_enum)]
+    pub stats_format: Option<StatsFormat>,
+
+    /// Resolve mirrors and record their addresses and AS number in statistics file
+    #[arg(long)]
+    pub stats_network: bool,
+
+    /// Look up AS numbers from IP-to-ASN database (iptoasn TSV, optionally gzip-compressed)
+    /// instead of RIPEstat
+    #[arg(long, value_name = "PATH")]
+    pub asn_db: Option<PathBuf>,
+
+    /// Human-readable report of selected mirrors, `-` for STDOUT
+    #[arg(long, value_name = "PATH")]
+    pub report_file: Option<PathBuf>,
+
+    /// Format of report, inferred from its extension if not given
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        ignore_case = true,
+        value_enum,
+        requires = "report_file"
+    )]
+    pub report_format: Option<ReportFormat>,
+
+    /// Maximum number of synced mirrors to check,
+    #[arg(short = 'c', long, value_name = "NUMBER", default_value = "100")]
+    pub max_check: u32,
+
+    /// Probe every mirror to check cheaply, and measure transfer rate of only the `--probe-keep`
+    /// fastest ones: `tcp` measures TCP connect time
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PROBE",
+        conflicts_with_all = ["no_benchmark", "pipeline"]
+    )]
+    pub probe: Option<Probe>,
+
+    /// Number of fastest probed mirrors to measure transfer rate of
+    #[arg(long, value_name = "K", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    pub probe_keep: u32,
+
+    /// Limit the list to the n mirrors with the highest score.
+    #[arg(short = 'm', long, value_name = "NUMBER", default_value = "10")]
+    pub mirrors: u32,
+
+    /// The maximum number of threads to use when measure transfer rate,
+    /// `auto` to pick from measured link capacity
+    #[arg(short = 'T', long, value_name = "NUMBER", default_value = "5")]
+    pub threads: Threads,
+
+    /// Show live benchmark progress in terminal, then sort and toggle mirrors before writing the list.
+    /// Enabled mirrors are written in displayed order.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "pipeline", "daemon", "input_mirrorlist", "urls_from", "offline", "no_benchmark",
+            "min_improvement", "merge_into", "cache_ttl"
+        ]
+    )]
+    pub interactive: bool,
+
+    /// Start with `--threads` concurrent transfers and halve them whenever they contend for local link
+    #[arg(long, conflicts_with = "pipeline")]
+    pub adaptive_threads: bool,
+
+    /// Minimum time between benchmark requests of each thread, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub request_delay: u64,
+
+    /// Exclude a mirror
+    #[arg(long, value_name = "DOMAIN-NAME")]
+    pub exclude: Option<Vec<String>>,
+
+    /// Read exclude mirrors from FILE
+    #[arg(long, value_name = "FILE")]
+    pub exclude_from: Option<PathBuf>,
+
+    /// Only use mirrors matching allowlist in FILE, same syntax as --exclude-from
+    #[arg(long, value_name = "FILE")]
+    pub include_from: Option<PathBuf>,
+
+    /// Only use mirrors in COUNTRY (can be repeated)
+    #[arg(long, value_name = "COUNTRY")]
+    pub country: Vec<String>,
+
+    /// Only use mirrors in country with CODE (can be repeated)
+    #[arg(long, value_name = "CODE")]
+    pub country_code: Vec<String>,
+
+    /// Download at most BYTES of database file when measure transfer rate
+    #[arg(long, value_name = "BYTES")]
+    pub transfer_limit: Option<u64>,
+
+    /// Measure transfer rate with a package of about SIZE (e.g. `50M`) picked from target
+    /// database, instead of database file
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with = "no_benchmark"
+    )]
+    pub benchmark_package: Option<u64>,
+
+    /// Number of times database file is downloaded from each mirror
+    #[arg(long, value_name = "NUMBER", default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: u32,
+
+    /// How transfer rate samples of each mirror are combined
+    #[arg(
+        long,
+        value_name = "METHOD",
+        ignore_case = true,
+        default_value = "mean",
+        value_enum
+    )]
+    pub aggregate: Aggregate,
+
+    /// Discard transfer rate samples more than N median absolute deviations from the median
+    /// before combining them, with `--samples` of 3 or more
+    #[arg(long, value_name = "N", value_parser = parse_positive)]
+    pub reject_outliers: Option<f64>,
+
+    /// Weight of measured transfer rate in weighted score
+    #[arg(long, value_name = "WEIGHT", default_value = "1")]
+    pub weight_rate: f64,
+
+    /// Weight of mirror score (from mirrors status) in weighted score
+    #[arg(long, value_name = "WEIGHT", default_value = "1")]
+    pub weight_score: f64,
+
+    /// Weight of mirror delay (from mirrors status) in weighted score
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_delay: f64,
+
+    /// Weight of time since last sync in weighted score, favoring freshly synced mirrors
+    #[arg(
+        long,
+        visible_alias = "weight-freshness",
+        value_name = "WEIGHT",
+        default_value = "0"
+    )]
+    pub weight_age: f64,
+
+    /// Weight of duration stddev (from mirrors status) in weighted score, 0 to ignore jitter
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_stability: f64,
+
+    /// Weight of time to first byte in weighted score, 0 to ignore latency
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_ttfb: f64,
+
+    /// Weight of TLS handshake time in weighted score, 0 to ignore it
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_tls: f64,
+
+    /// Weight of HTTP/2 support in weighted score, found by TLS handshake with each mirror
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_http2: f64,
+
+    /// Weight of ICMP round-trip time in weighted score, implies `--ping`
+    #[cfg(feature = "ping")]
+    #[arg(long, value_name = "WEIGHT", default_value = "0")]
+    pub weight_ping: f64,
+
+    /// Record ICMP round-trip time of mirrors in statistics file as `ping_time`,
+    /// needs unprivileged ICMP sockets (`net.ipv4.ping_group_range`)
+    #[cfg(feature = "ping")]
+    #[arg(long)]
+    pub ping: bool,
+
+    /// Weight of distance from --location in weighted score
+    #[arg(
+        long,
+        value_name = "WEIGHT",
+        default_value = "0",
+        requires = "location"
+    )]
+    pub weight_distance: f64,
+
+    /// User's location as `LAT,LON` in degrees, or a country code
+    #[arg(long, value_name = "LOCATION")]
+    pub location: Option<Coordinates>,
+
+    /// Reuse transfer rates measured by previous runs within DURATION (e.g. `6h`, `30m`, `1d`)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with_all = ["pipeline", "no_benchmark"]
+    )]
+    pub cache_ttl: Option<Duration>,
+
+    /// Cache file of `--cache-ttl` and `--offline`, mirrors status is cached next to it
+    /// [default: benchmark.json in cache directory, e.g. ~/.cache/pacman-mirrorup]
+    #[arg(long, value_name = "FILE")]
+    pub cache_file: Option<PathBuf>,
+
+    /// Never access network, rank mirrors from mirrors status and measurements cached by `--cache-ttl`
+    #[arg(
+        long,
+        conflicts_with_all = ["pipeline", "merge_sources", "verify", "min_improvement"]
+    )]
+    pub offline: bool,
+
+    /// Start measuring transfer rate while mirrors status is still being downloaded.
+    /// Mirrors are checked in the order they appear in mirrors status instead of by delay.
+    #[arg(long)]
+    pub pipeline: bool,
+
+    /// Rank mirrors from mirrors status only (score, delay, duration), without measuring transfer rate
+    #[arg(long, conflicts_with = "pipeline")]
+    pub no_benchmark: bool,
+
+    /// Trust CA certificates of PEM file in addition to bundled ones, e.g. of a TLS-intercepting proxy
+    #[arg(long, value_name = "PATH")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Accept any TLS certificate, DANGEROUS: only for networks whose middleboxes break certificates
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// User-Agent of mirrors status and benchmark requests [default: pacman-mirrorup/VERSION (HOMEPAGE)]
+    #[arg(long, value_name = "STRING")]
+    pub user_agent: Option<String>,
+
+    /// Extra header of mirrors status and benchmark requests, e.g. `X-Requested-By: foo`, can be repeated
+    #[arg(long = "header", value_name = "'NAME: VALUE'", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Maximum number of redirects to follow when measure transfer rate, 0 to disallow redirects
+    #[arg(long, value_name = "NUMBER", default_value_t = DEFAULT_MAX_REDIRECTS)]
+    pub max_redirects: u32,
+
+    /// How to handle redirected mirrors
+    #[arg(
+        long,
+        value_name = "MODE",
+        ignore_case = true,
+        default_value = "follow",
+        value_enum
+    )]
+    pub follow_redirects: FollowRedirects,
+
+    /// Order of servers in mirror list, the default is by weighted score
+    #[arg(long, value_name = "KEY", ignore_case = true, value_enum)]
+    pub sort: Option<SortBy>,
+
+    /// Tolerated difference between local clock and mirrors status's clock
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = DEFAULT_CLOCK_SKEW_TOLERANCE
+    )]
+    pub clock_skew_tolerance: u64,
+
+    /// Only use mirrors synced within the last HOURS
+    #[arg(long, value_name = "HOURS", value_parser = parse_hours)]
+    pub age: Option<Duration>,
+
+    /// Only use mirrors supporting IPv4
+    #[arg(long)]
+    pub ipv4: bool,
+
+    /// Only use mirrors supporting IPv6
+    #[arg(long)]
+    pub ipv6: bool,
+
+    /// Only use mirrors hosting ISO images
+    #[arg(long)]
+    pub isos: bool,
+
+    /// Resolve mirrors before benchmark and keep only one of mirrors sharing the same addresses,
+    /// preferring HTTPS
+    #[arg(long)]
+    pub dedupe_by_ip: bool,
+
+    /// Prefer mirrors from distinct networks (ASN) when selecting the best mirrors
+    #[arg(long)]
+    pub spread_asn: bool,
+
+    /// Select at most N mirrors served by the same CDN, detected from CNAME of mirrors
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_per_cdn: Option<u32>,
+
+    /// Check N randomly sampled synced mirrors instead of `--max-check` mirrors with the lowest delay.
+    /// Sampling is reproducible with `--seed`.
+    #[arg(long, value_name = "N", conflicts_with = "pipeline")]
+    pub sample: Option<u32>,
+
+    /// Sample mirrors from each country in turn
+    #[arg(long, requires = "sample")]
+    pub sample_by_country: bool,
+
+    /// Break ties between mirrors with equal weighted score pseudo-randomly from SEED
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+}
+
+/// Parse a non-negative, possibly fractional, number of hours
+fn parse_hours(s: &str) -> Result<Duration, String> {
+    let hours: f64 = s.parse().map_err(|err| format!("{err}"))?;
+    Duration::try_from_secs_f64(hours * 3600.0).map_err(|_| "expected a non-negative number".into())
+}
+
+/// Parse an architecture name such as `x86_64` or `aarch64`, as substituted for `$arch`
+fn parse_arch(s: &str) -> Result<String, String> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid architecture `{s}`"));
+    }
+    Ok(s.to_string())
+}
+
+fn parse_repo_path(s: &str) -> Result<String, String> {
+    if !s.contains("$repo") {
+        return Err(format!("`{s}` has no `$repo`"));
+    }
+    Ok(s.trim_start_matches('/').to_string())
+}
+
+fn parse_line_template(s: &str) -> Result<String, String> {
+    if !s.contains("{url}") || s.contains('\n') {
+        return Err(format!("`{s}` is not a single line with `{{url}}`"));
+    }
+    Ok(s.to_string())
+}
+
+/// Parse `Name: value` of HTTP header
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("`{s}` is not `Name: value`"))?;
+    let name: &str = name.trim();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    {
+        return Err(format!("invalid header name `{name}`"));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number.parse().map_err(|_| format!("`{s}` is not a size"))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown unit of `{s}`, use K, M, or G")),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("`{s}` is too large"))
+}
+
+/// Parse a duration with unit `s`, `m`, `h`, or `d`, e.g. `90s` or `6h`. Plain number is in seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s: &str = s.trim();
+    let (value, unit): (&str, u64) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 3600),
+        Some((i, 'd')) => (&s[..i], 86400),
+        _ => (s, 1),
+    };
+    let value: f64 = value.trim().parse().map_err(|err| format!("{err}"))?;
+    Duration::try_from_secs_f64(value * unit as f64)
+        .map_err(|_| "expected a non-negative duration".into())
+}
+
+/// Parse a finite number greater than zero
+fn parse_positive(s: &str) -> Result<f64, String> {
+    let value: f64 = s.trim().parse().map_err(|err| format!("{err}"))?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err("expected a positive number".into());
+    }
+    Ok(value)
+}
+
+/// Parse a non-negative percentage, with or without `%`
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|err| format!("{err}"))?;
+    if !percent.is_finite() || percent < 0.0 {
+        return Err("expected a non-negative percentage".into());
+    }
+    Ok(percent)
+}
+
+impl Arguments {
+    /// Where database file is under mirror's URL
+    pub fn layout(&self) -> Layout {
+        Layout {
+            repo_path: self
+                .repo_path_template
+                .clone()
+                .unwrap_or_else(|| self.flavor.repo_path().to_string()),
+            arch: self.arch.clone(),
+        }
+    }
+
+    /// First target database, which verification and redirects are checked against
+    pub fn target_db(&self) -> TargetDb {
+        self.target_dbs[0]
+    }
+
+    /// Weight of ICMP round-trip time, always 0 without `ping` feature
+    pub fn weight_ping(&self) -> f64 {
+        #[cfg(feature = "ping")]
+        return self.weight_ping;
+        #[cfg(not(feature = "ping"))]
+        0.0
+    }
+
+    pub fn status_format(&self) -> StatusFormat {
+        self.status_format
+            .unwrap_or_else(|| self.flavor.status_format())
+    }
+
+    /// Tracing filter of `--verbose` and `--quiet`
+    pub fn log_filter(&self) -> &'static str {
+        match (self.quiet, self.verbose) {
+            (true, _) => "pacman_mirrorup=warn",
+            (false, 0) => "pacman_mirrorup=info",
+            (false, 1) => "pacman_mirrorup=debug",
+            (false, _) => "debug",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Report per-mirror transfer rate trends from recorded runs
+    Trends(TrendsArgs),
+
+    /// Write systemd service and timer units running pacman-mirrorup with the options given before this command
+    InstallUnits(InstallUnitsArgs),
+
+    /// Write a pacman hook running pacman-mirrorup with the options given before this command
+    /// after `pacman-mirrorlist` is upgraded
+    InstallHook(InstallHookArgs),
+
+    /// Print everything known about mirrors of a domain from mirrors status, with a one-off benchmark
+    Show(ShowArgs),
+
+    /// Probe every active mirror for target database, report broken, redirecting, stale,
+    /// or misconfigured mirrors
+    CheckAll(CheckAllArgs),
+
+    /// Benchmark mirrors of `--input-mirrorlist` or `--urls-from` every interval and publish
+    /// latest metrics in Prometheus format
+    Monitor(MonitorArgs),
+
+    /// Check connectivity to mirrors status, DNS, IPv6, clock, output path, and exclude files
+    /// of the options given before this command
+    Doctor,
+}
+
+#[derive(Args, Debug)]
+pub struct InstallUnitsArgs {
+    /// When the timer runs, as systemd calendar event (e.g. `daily`, `weekly`, `Mon *-*-* 03:00`)
+    #[arg(long, value_name = "CALENDAR", default_value = "weekly")]
+    pub interval: String,
+
+    /// Directory of unit files
+    #[arg(long, value_name = "DIR", default_value = "/etc/systemd/system")]
+    pub dir: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct InstallHookArgs {
+    /// Package triggering the hook
+    #[arg(long, value_name = "PACKAGE", default_value = "pacman-mirrorlist")]
+    pub trigger: Vec<String>,
+
+    /// Package operation triggering the hook
+    #[arg(long, value_enum, value_name = "OPERATION", default_value = "upgrade")]
+    pub operation: Vec<Operation>,
+
+    /// Stop ranking after DURATION, so it never blocks pacman for long
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "5m")]
+    pub timeout: Duration,
+
+    /// Directory of pacman hooks
+    #[arg(long, value_name = "DIR", default_value = "/usr/share/libalpm/hooks")]
+    pub dir: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ShowArgs {
+    /// Domain of mirror, also matches its subdomains
+    #[arg(value_name = "DOMAIN")]
+    pub domain: String,
+
+    /// Do not benchmark the mirror
+    #[arg(long)]
+    pub no_benchmark: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckAllArgs {
+    /// Report file, `-` for stdout
+    #[arg(long, value_name = "PATH", default_value = "-")]
+    pub output: PathBuf,
+
+    /// Format of report, guessed from extension of `--output` by default
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub format: Option<StatsFormat>,
+
+    /// Also report mirrors without problems
+    #[arg(long)]
+    pub all: bool,
+
+    /// Number of mirrors probed at the same time
+    #[arg(long, value_name = "NUMBER", default_value_t = 20)]
+    pub threads: usize,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("metrics").required(true).multiple(true).args(["listen", "textfile"])))]
+pub struct MonitorArgs {
+    /// Time between benchmarks, e.g. `30m`, `1h`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "15m")]
+    pub interval: Duration,
+
+    /// Serve metrics on `http://ADDR/metrics`
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+
+    /// Write metrics to textfile of node exporter textfile collector, e.g.
+    /// `/var/lib/node_exporter/textfile_collector/pacman-mirrorup.prom`
+    #[arg(long, value_name = "PATH")]
+    pub textfile: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct TrendsArgs {
+    /// SQLite history database written by --history-db, its default if PATH is not given
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        conflicts_with = "stats_dir"
+    )]
+    pub history_db: Option<Option<PathBuf>>,
+
+    /// Directory of statistics CSV files, one file per run ordered by file name
+    #[arg(long, value_name = "DIR", required_unless_present = "history_db")]
+    pub stats_dir: Option<PathBuf>,
+
+    /// Number of latest runs compared against earlier runs
+    #[arg(long, value_name = "RUNS", default_value_t = 5)]
+    pub recent: usize,
+
+    /// Report a regression when recent mean transfer rate drops by more than PERCENT
+    #[arg(long, value_name = "PERCENT", default_value_t = 20.0)]
+    pub threshold: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    /// Arguments parsed from `args` following program name
+    fn parse(args: &[&str]) -> Arguments {
+        Arguments::from_arg_matches(
+            &Arguments::command().get_matches_from([env!("CARGO_CRATE_NAME")].iter().chain(args)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn default_args() {
+        let args = Arguments::from_arg_matches(
+            &Arguments::command().get_matches_from(vec![env!("CARGO_CRATE_NAME")]),
+        )
+        .unwrap();
+
+        assert!(args.command.is_none());
+        assert_eq!(args.source_url, vec![DEFAULT_SOURCE_URL.to_owned()]);
+        assert_eq!(args.input_mirrorlist, None);
+        assert_eq!(args.urls_from, None);
+        assert!(!args.merge_sources);
+        assert_eq!(args.cache_ttl, None);
+        assert_eq!(args.cache_file, None);
+        assert!(!args.offline);
+        assert!(!args.daemon);
+        assert_eq!(args.request_delay, 0);
+        assert!(!args.adaptive_threads);
+        assert!(!args.interactive);
+        assert!(!args.confirm);
+        assert_eq!(args.interval, Duration::from_secs(12 * 3600));
+        assert_eq!(args.target_dbs, vec![TargetDb::Extra]);
+        assert_eq!(args.arch, "x86_64");
+        assert_eq!(args.flavor, Flavor::Arch);
+        assert_eq!(args.branch, Branch::Stable);
+        assert_eq!(args.output_file, None);
+        assert!(!args.in_place);
+        assert!(!args.force);
+        assert!(!args.dry_run);
+        assert_eq!(args.verify, None);
+        assert!(!args.require_sig);
+        assert!(!args.require_files_db);
+        assert!(!args.probe_range);
+        assert!(!args.require_range);
+        assert!(!args.probe_tls);
+        assert_eq!(args.min_tls, None);
+        assert_eq!(args.min_cert_days, None);
+        assert_eq!(args.resolve, None);
+        assert!(!args.dual_stack);
+        assert_eq!(args.ca_cert, None);
+        assert!(!args.insecure);
+        assert_eq!(args.user_agent, None);
+        assert!(args.headers.is_empty());
+        assert_eq!(args.max_content_lag, None);
+        assert_eq!(args.content_reference, None);
+        assert_eq!(args.backup, None);
+        assert_eq!(args.min_improvement, None);
+        assert_eq!(args.format, OutputFormat::Pacman);
+        assert_eq!(args.merge_into, None);
+        assert!(!args.verbose_output);
+        assert!(!args.group_by_country);
+        assert_eq!(args.commented_extra, 0);
+        assert_eq!(args.template, None);
+        assert!(args.pin.is_empty());
+        assert_eq!(args.pin_from, None);
+        assert_eq!(args.stats_file, None);
+        assert!(!args.stats_network);
+        assert_eq!(args.probe, None);
+        assert_eq!(args.probe_keep, 20);
+        assert_eq!(args.asn_db, None);
+        assert_eq!(args.stats_format, None);
+        assert_eq!(args.history_db, None);
+        assert_eq!(args.report_file, None);
+        assert_eq!(args.report_format, None);
+        assert_eq!(args.max_check, 100);
+        assert_eq!(args.mirrors, 10);
+        assert_eq!(args.threads, Threads::Fixed(5));
+        assert_eq!(args.exclude, None);
+        assert_eq!(args.exclude_from, None);
+        assert_eq!(args.include_from, None);
+        assert!(args.country.is_empty());
+        assert!(args.country_code.is_empty());
+        assert_eq!(args.clock_skew_tolerance, DEFAULT_CLOCK_SKEW_TOLERANCE);
+        assert_eq!(args.transfer_limit, None);
+        assert_eq!(args.age, None);
+        assert!(!args.ipv4);
+        assert!(!args.ipv6);
+        assert!(!args.isos);
+        assert!(!args.dedupe_by_ip);
+        assert!(!args.spread_asn);
+        assert_eq!(args.max_per_cdn, None);
+        assert_eq!(args.seed, None);
+        assert_eq!(args.sample, None);
+        assert!(!args.sample_by_country);
+        assert_eq!(args.samples, 1);
+        assert_eq!(args.aggregate, Aggregate::Mean);
+        assert_eq!(args.reject_outliers, None);
+        assert_eq!(args.weight_rate, 1.0);
+        assert_eq!(args.weight_score, 1.0);
+        assert_eq!(args.weight_delay, 0.0);
+        assert_eq!(args.weight_age, 0.0);
+        assert_eq!(args.weight_ttfb, 0.0);
+        assert_eq!(args.weight_tls, 0.0);
+        assert_eq!(args.weight_stability, 0.0);
+        assert_eq!(args.weight_http2, 0.0);
+        assert_eq!(args.weight_ping(), 0.0);
+        assert_eq!(args.weight_distance, 0.0);
+        assert_eq!(args.location, None);
+        assert!(!args.pipeline);
+        assert!(!args.no_benchmark);
+        assert_eq!(args.max_redirects, DEFAULT_MAX_REDIRECTS);
+        assert_eq!(args.follow_redirects, FollowRedirects::Follow);
+        assert_eq!(args.sort, None);
+    }
+
+    #[test]
+    fn long_args() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--source-url",
+            DEFAULT_SOURCE_URL,
+            "--target-db",
+            "extra",
+            "--output-file",
+            "/tmp/mirrorlist",
+            "--stats-file",
+            "/tmp/stats",
+            "--max-check",
+            "200",
+            "--mirrors",
+            "20",
+            "--threads",
+            "20",
+        ]))
+        .unwrap();
+
+        assert_eq!(args.source_url, vec![DEFAULT_SOURCE_URL.to_owned()]);
+        assert_eq!(args.target_dbs, vec![TargetDb::Extra]);
+        assert_eq!(args.output_file, Some(PathBuf::from("/tmp/mirrorlist")));
+        assert_eq!(args.stats_file, Some(PathBuf::from("/tmp/stats")));
+        assert_eq!(args.max_check, 200);
+        assert_eq!(args.mirrors, 20);
+        assert_eq!(args.threads, Threads::Fixed(20));
+    }
+
+    #[test]
+    fn short_args() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "-S",
+            DEFAULT_SOURCE_URL,
+            "-t",
+            "extra",
+            "-o",
+            "/tmp/mirrorlist",
+            "-s",
+            "/tmp/stats",
+            "-c",
+            "200",
+            "-m",
+            "20",
+            "-T",
+            "20",
+        ]))
+        .unwrap();
+
+        assert_eq!(args.source_url, vec![DEFAULT_SOURCE_URL.to_owned()]);
+        assert_eq!(args.target_dbs, vec![TargetDb::Extra]);
+        assert_eq!(args.output_file, Some(PathBuf::from("/tmp/mirrorlist")));
+        assert_eq!(args.stats_file, Some(PathBuf::from("/tmp/stats")));
+        assert_eq!(args.max_check, 200);
+        assert_eq!(args.mirrors, 20);
+        assert_eq!(args.threads, Threads::Fixed(20));
+    }
+
+    #[test]
+    fn auto_threads() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--threads",
+            "auto",
+        ]))
+        .unwrap();
+
+        assert_eq!(args.threads, Threads::Auto);
+    }
+
+    #[test]
+    fn exclude_mirror() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--exclude",
+            "ban.this.mirror",
+        ]))
+        .unwrap();
+
+        assert_eq!(args.exclude.unwrap(), vec!["ban.this.mirror"]);
+    }
+
+    #[test]
+    fn exclude_mirrors() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--exclude",
+            "ban.this.mirror",
+            "--exclude",
+            "ban.this-mirror.also",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            args.exclude.unwrap(),
+            vec!["ban.this.mirror", "ban.this-mirror.also"]
+        );
+    }
+
+    #[test]
+    fn weight_distance() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--weight-distance",
+            "0.5",
+            "--location",
+            "13.75,100.5",
+        ]))
+        .unwrap();
+        assert_eq!(args.weight_distance, 0.5);
+        assert_eq!(
+            args.location,
+            Some(Coordinates {
+                latitude: 13.75,
+                longitude: 100.5
+            })
+        );
+
+        // Location is required
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![env!("CARGO_CRATE_NAME"), "--weight-distance", "0.5"])
+            .is_err());
+    }
+
+    #[test]
+    fn stats_to_stdout() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--output-file",
+            "/tmp/mirrorlist",
+            "--stats-file",
+            "-",
+            "--stats-format",
+            "json",
+        ]))
+        .unwrap();
+
+        assert_eq!(args.stats_file, Some(PathBuf::from("-")));
+        assert_eq!(args.stats_format, Some(StatsFormat::Json));
+    }
+
+    #[test]
+    fn report() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--report-file",
+            "/srv/www/mirrors.txt",
+            "--report-format",
+            "html",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            args.report_file,
+            Some(PathBuf::from("/srv/www/mirrors.txt"))
+        );
+        assert_eq!(args.report_format, Some(ReportFormat::Html));
 
-    /// Limit the list to the n mirrors with the highest score.
-    #[arg(short = 'm', long, value_name = "NUMBER", default_value = "10")]
-    pub mirrors: u32,
+        // Format without file
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![
+                env!("CARGO_CRATE_NAME"),
+                "--report-format",
+                "markdown"
+            ])
+            .is_err());
+    }
 
-    /// The maximum number of threads to use when measure transfer rate
-    #[arg(short = 'T', long, value_name = "NUMBER", default_value = "5")]
-    pub threads: usize,
+    #[test]
+    fn trends_command() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "trends",
+            "--history-db",
+            "/tmp/history.db",
+            "--recent",
+            "3",
+        ]))
+        .unwrap();
 
-    /// Exclude a mirror
-    #[arg(long, value_name = "DOMAIN-NAME")]
-    pub exclude: Option<Vec<String>>,
+        let Some(Command::Trends(trends)) = args.command else {
+            panic!("Expected trends command");
+        };
+        assert_eq!(
+            trends.history_db,
+            Some(Some(PathBuf::from("/tmp/history.db")))
+        );
+        assert_eq!(trends.stats_dir, None);
+        assert_eq!(trends.recent, 3);
+        assert_eq!(trends.threshold, 20.0);
 
-    /// Read exclude mirrors from FILE
-    #[arg(long, value_name = "FILE")]
-    pub exclude_from: Option<PathBuf>,
-}
+        // Source of runs is required
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![env!("CARGO_CRATE_NAME"), "trends"])
+            .is_err());
+    }
 
-#[cfg(test)]
-mod tests {
-    use clap::{CommandFactory, FromArgMatches};
+    #[test]
+    fn install_units_command() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--output-file",
+            "/etc/pacman.d/mirrorlist",
+            "install-units",
+            "--interval",
+            "daily",
+        ]))
+        .unwrap();
 
-    use super::*;
+        assert_eq!(
+            args.output_file,
+            Some(PathBuf::from("/etc/pacman.d/mirrorlist"))
+        );
+        let Some(Command::InstallUnits(units)) = args.command else {
+            panic!("Expected install-units command");
+        };
+        assert_eq!(units.interval, "daily");
+        assert_eq!(units.dir, PathBuf::from("/etc/systemd/system"));
+    }
 
     #[test]
-    fn default_args() {
-        let args = Arguments::from_arg_matches(
-            &Arguments::command().get_matches_from(vec![env!("CARGO_CRATE_NAME")]),
-        )
+    fn include_countries() {
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--country",
+            "United States",
+            "--country",
+            "Canada",
+            "--country-code",
+            "MX",
+        ]))
         .unwrap();
 
-        assert_eq!(args.source_url, DEFAULT_SOURCE_URL.to_owned());
-        assert_eq!(args.target_db, TargetDb::Extra);
-        assert_eq!(args.output_file, None);
-        assert_eq!(args.stats_file, None);
-        assert_eq!(args.max_check, 100);
-        assert_eq!(args.mirrors, 10);
-        assert_eq!(args.threads, 5);
-        assert_eq!(args.exclude, None);
-        assert_eq!(args.exclude_from, None);
+        assert_eq!(args.country, vec!["United States", "Canada"]);
+        assert_eq!(args.country_code, vec!["MX"]);
     }
 
     #[test]
-    fn long_args() {
+    fn merge_into() {
         let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
             env!("CARGO_CRATE_NAME"),
-            "--source-url",
-            DEFAULT_SOURCE_URL,
-            "--target-db",
-            "extra",
-            "--output-file",
-            "/tmp/mirrorlist",
-            "--stats-file",
-            "/tmp/stats",
-            "--max-check",
-            "200",
-            "--mirrors",
-            "20",
-            "--threads",
-            "20",
+            "--merge-into",
+            "/etc/pacman.d/mirrorlist",
         ]))
         .unwrap();
+        assert_eq!(
+            args.merge_into,
+            Some(PathBuf::from("/etc/pacman.d/mirrorlist"))
+        );
 
-        assert_eq!(args.source_url, DEFAULT_SOURCE_URL.to_owned());
-        assert_eq!(args.target_db, TargetDb::Extra);
-        assert_eq!(args.output_file, Some(PathBuf::from("/tmp/mirrorlist")));
-        assert_eq!(args.stats_file, Some(PathBuf::from("/tmp/stats")));
-        assert_eq!(args.max_check, 200);
-        assert_eq!(args.mirrors, 20);
-        assert_eq!(args.threads, 20);
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![
+                env!("CARGO_CRATE_NAME"),
+                "--merge-into",
+                "/etc/pacman.d/mirrorlist",
+                "--output-file",
+                "/tmp/mirrorlist",
+            ])
+            .is_err());
     }
 
     #[test]
-    fn short_args() {
+    fn in_place() {
         let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
             env!("CARGO_CRATE_NAME"),
-            "-S",
-            DEFAULT_SOURCE_URL,
-            "-t",
-            "extra",
-            "-o",
-            "/tmp/mirrorlist",
-            "-s",
-            "/tmp/stats",
-            "-c",
-            "200",
-            "-m",
-            "20",
-            "-T",
-            "20",
+            "--output-file",
+            "/etc/pacman.d/mirrorlist",
+            "--in-place",
         ]))
         .unwrap();
+        assert!(args.in_place);
 
-        assert_eq!(args.source_url, DEFAULT_SOURCE_URL.to_owned());
-        assert_eq!(args.target_db, TargetDb::Extra);
-        assert_eq!(args.output_file, Some(PathBuf::from("/tmp/mirrorlist")));
-        assert_eq!(args.stats_file, Some(PathBuf::from("/tmp/stats")));
-        assert_eq!(args.max_check, 200);
-        assert_eq!(args.mirrors, 20);
-        assert_eq!(args.threads, 20);
+        let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
+            env!("CARGO_CRATE_NAME"),
+            "--output-file",
+            "/etc/pacman.d/mirrorlist",
+            "--overwrite",
+            "--backup",
+            "3",
+            "--min-improvement",
+            "20%",
+            "--dry-run",
+            "--verify",
+        ]))
+        .unwrap();
+        assert!(args.force);
+        assert_eq!(args.backup, Some(3));
+        assert_eq!(args.min_improvement, Some(20.0));
+        assert!(args.dry_run);
+        assert_eq!(args.verify, Some(Verify::Drop));
+        assert_eq!(parse_percent("7.5"), Ok(7.5));
+        assert!(parse_percent("-5%").is_err());
+
+        // Nothing to replace without output file
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![env!("CARGO_CRATE_NAME"), "--in-place"])
+            .is_err());
     }
 
     #[test]
-    fn exclude_mirror() {
+    fn pin() {
         let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
             env!("CARGO_CRATE_NAME"),
-            "--exclude",
-            "ban.this.mirror",
+            "--pin",
+            "https://internal.example/archlinux/",
+            "--pin",
+            "http://10.0.0.1/archlinux/",
+            "--pin-from",
+            "/etc/pacman-mirrorup/pinned",
         ]))
         .unwrap();
 
-        assert_eq!(args.exclude.unwrap(), vec!["ban.this.mirror"]);
+        assert_eq!(
+            args.pin,
+            vec![
+                "https://internal.example/archlinux/".to_string(),
+                "http://10.0.0.1/archlinux/".to_string()
+            ]
+        );
+        assert_eq!(
+            args.pin_from,
+            Some(PathBuf::from("/etc/pacman-mirrorup/pinned"))
+        );
     }
 
     #[test]
-    fn exclude_mirrors() {
+    fn cache() {
         let args = Arguments::from_arg_matches(&Arguments::command().get_matches_from(vec![
             env!("CARGO_CRATE_NAME"),
-            "--exclude",
-            "ban.this.mirror",
-            "--exclude",
-            "ban.this-mirror.also",
+            "--cache-ttl",
+            "6h",
+            "--cache-file",
+            "/var/cache/pacman-mirrorup/benchmark.json",
         ]))
         .unwrap();
-
+        assert_eq!(args.cache_ttl, Some(Duration::from_secs(6 * 3600)));
         assert_eq!(
-            args.exclude.unwrap(),
-            vec!["ban.this.mirror", "ban.this-mirror.also"]
+            args.cache_file,
+            Some(PathBuf::from("/var/cache/pacman-mirrorup/benchmark.json"))
         );
+
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("-1h").is_err());
+        assert!(parse_duration("h").is_err());
     }
 
     #[test]
@@ -183,4 +1374,355 @@ mod tests {
             PathBuf::from("/path/to/excluded-mirror.conf")
         );
     }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(parse(&[]).log_filter(), "pacman_mirrorup=info");
+        assert_eq!(parse(&["-v"]).log_filter(), "pacman_mirrorup=debug");
+        assert_eq!(parse(&["-vv"]).log_filter(), "debug");
+        assert_eq!(parse(&["-q"]).log_filter(), "pacman_mirrorup=warn");
+        assert_eq!(parse(&["trends", "--stats-dir", ".", "-v"]).verbose, 1);
+        assert!(Arguments::command()
+            .try_get_matches_from([env!("CARGO_CRATE_NAME"), "-v", "-q"])
+            .is_err());
+    }
+
+    #[test]
+    fn log_format() {
+        assert_eq!(parse(&[]).log_format, LogFormat::Text);
+        assert_eq!(parse(&["--log-format", "json"]).log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn log_target() {
+        assert_eq!(parse(&[]).log_target, LogTarget::Stderr);
+        assert_eq!(
+            parse(&["--log-target", "journald"]).log_target,
+            LogTarget::Journald
+        );
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(parse(&[]).color, ColorChoice::Auto);
+        assert_eq!(parse(&["--color", "never"]).color, ColorChoice::Never);
+    }
+
+    #[test]
+    fn doctor_command() {
+        assert!(matches!(
+            parse(&["doctor", "-q"]).command,
+            Some(Command::Doctor)
+        ));
+    }
+
+    #[test]
+    fn show_command() {
+        let Some(Command::Show(show_args)) = parse(&["show", "mirror.example"]).command else {
+            panic!("Expect show command");
+        };
+        assert_eq!(show_args.domain, "mirror.example");
+        assert!(!show_args.no_benchmark);
+    }
+
+    #[test]
+    fn check_all_command() {
+        let Some(Command::CheckAll(check_args)) = parse(&["check-all", "--format", "json"]).command
+        else {
+            panic!("Expect check-all command");
+        };
+        assert_eq!(check_args.output, PathBuf::from("-"));
+        assert_eq!(check_args.format, Some(StatsFormat::Json));
+        assert!(!check_args.all);
+    }
+
+    #[test]
+    fn monitor_command() {
+        let Some(Command::Monitor(monitor_args)) = parse(&[
+            "--urls-from",
+            "urls",
+            "monitor",
+            "--listen",
+            "127.0.0.1:9417",
+        ])
+        .command
+        else {
+            panic!("Expect monitor command");
+        };
+        assert_eq!(monitor_args.interval, Duration::from_secs(15 * 60));
+        assert_eq!(monitor_args.listen, Some("127.0.0.1:9417".parse().unwrap()));
+
+        // Mirrors to monitor are required
+        assert!(Arguments::command()
+            .try_get_matches_from([env!("CARGO_CRATE_NAME"), "monitor"])
+            .is_err());
+    }
+
+    #[test]
+    fn install_hook_command() {
+        let Some(Command::InstallHook(hook_args)) = parse(&[
+            "install-hook",
+            "--trigger",
+            "pacman-mirrorlist",
+            "--trigger",
+            "pacman",
+        ])
+        .command
+        else {
+            panic!("Expect install-hook command");
+        };
+        assert_eq!(hook_args.trigger, vec!["pacman-mirrorlist", "pacman"]);
+        assert_eq!(hook_args.operation, vec![Operation::Upgrade]);
+        assert_eq!(hook_args.timeout, Duration::from_secs(300));
+        assert_eq!(hook_args.dir, PathBuf::from("/usr/share/libalpm/hooks"));
+    }
+
+    #[test]
+    fn arch() {
+        assert_eq!(parse(&["--arch", "aarch64"]).arch, "aarch64");
+        assert!(Arguments::command()
+            .try_get_matches_from([env!("CARGO_CRATE_NAME"), "--arch", "../x86_64"])
+            .is_err());
+    }
+
+    #[test]
+    fn flavor() {
+        let manjaro = parse(&["--flavor", "manjaro", "--branch", "testing"]);
+        assert_eq!(manjaro.source_url, vec![MANJARO_SOURCE_URL]);
+        assert_eq!(manjaro.branch, Branch::Testing);
+        assert_eq!(manjaro.layout().repo_path, "$repo/$arch");
+        assert_eq!(manjaro.status_format(), StatusFormat::Manjaro);
+    }
+
+    #[test]
+    fn derivative_layout() {
+        let derivative = parse(&[
+            "--repo-path-template",
+            "/$repo/os/$arch/",
+            "--mirrorlist-line-template",
+            "Server = {url}$repo/os/$arch",
+        ]);
+        assert_eq!(derivative.layout().repo_path, "$repo/os/$arch/");
+        assert_eq!(derivative.status_format(), StatusFormat::Arch);
+        assert_eq!(derivative.source_url, vec![DEFAULT_SOURCE_URL]);
+        assert_eq!(
+            parse(&["--status-format", "manjaro"]).source_url,
+            vec![MANJARO_SOURCE_URL]
+        );
+        for invalid in [
+            ["--repo-path-template", "os/$arch"],
+            ["--mirrorlist-line-template", "Server = $repo"],
+        ] {
+            assert!(Arguments::command()
+                .try_get_matches_from([env!("CARGO_CRATE_NAME"), invalid[0], invalid[1]])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn target_db() {
+        assert_eq!(
+            parse(&["--target-db", "core-testing"]).target_db(),
+            TargetDb::CoreTesting
+        );
+    }
+
+    #[test]
+    fn target_dbs() {
+        let multiple = parse(&["-t", "core,multilib", "--target-db-aggregate", "min"]);
+        assert_eq!(
+            multiple.target_dbs,
+            vec![TargetDb::Core, TargetDb::Multilib]
+        );
+        assert_eq!(multiple.target_db_aggregate, Aggregate::Min);
+    }
+
+    #[test]
+    fn benchmark_package() {
+        assert_eq!(parse(&[]).benchmark_package, None);
+        assert_eq!(
+            parse(&["--benchmark-package", "50M"]).benchmark_package,
+            Some(50 * 1024 * 1024)
+        );
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("2k").unwrap(), 2048);
+        assert!(parse_size("5T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn max_redirects() {
+        assert_eq!(parse(&["--max-redirects", "0"]).max_redirects, 0);
+    }
+
+    #[test]
+    fn min_tls() {
+        assert_eq!(
+            parse(&["--min-tls", "1.3"]).min_tls,
+            Some(TlsVersion::Tls13)
+        );
+    }
+
+    #[test]
+    fn request_headers() {
+        let headers = parse(&[
+            "--user-agent",
+            "curl/8.0.0",
+            "--header",
+            "X-Requested-By: campus-mirror",
+            "--header",
+            "Authorization:Bearer a:b",
+        ]);
+        assert_eq!(headers.user_agent.as_deref(), Some("curl/8.0.0"));
+        assert_eq!(
+            headers.headers,
+            vec![
+                ("X-Requested-By".to_string(), "campus-mirror".to_string()),
+                ("Authorization".to_string(), "Bearer a:b".to_string()),
+            ]
+        );
+        assert!(parse_header("no colon").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+        assert!(parse_header(": value").is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        assert_eq!(parse(&["--resolve", "6"]).resolve, Some(IpFamily::V6));
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![env!("CARGO_CRATE_NAME"), "--resolve", "5"])
+            .is_err());
+    }
+
+    #[test]
+    fn dual_stack() {
+        assert!(parse(&["--dual-stack"]).dual_stack);
+        assert!(Arguments::command()
+            .try_get_matches_from(vec![
+                env!("CARGO_CRATE_NAME"),
+                "--dual-stack",
+                "--resolve",
+                "4"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn dedupe_by_ip() {
+        assert!(parse(&["--dedupe-by-ip"]).dedupe_by_ip);
+    }
+
+    #[test]
+    fn max_per_cdn() {
+        assert_eq!(parse(&["--max-per-cdn", "2"]).max_per_cdn, Some(2));
+    }
+
+    #[test]
+    fn stats_network() {
+        assert!(parse(&["--stats-network"]).stats_network);
+        assert_eq!(
+            parse(&["--asn-db", "/var/lib/ip2asn-combined.tsv.gz"]).asn_db,
+            Some(PathBuf::from("/var/lib/ip2asn-combined.tsv.gz"))
+        );
+    }
+
+    #[test]
+    fn probe() {
+        let probe = parse(&["--max-check", "0", "--probe", "tcp", "--probe-keep", "30"]);
+        assert_eq!(probe.probe, Some(Probe::Tcp));
+        assert_eq!(probe.probe_keep, 30);
+    }
+
+    #[cfg(feature = "ping")]
+    #[test]
+    fn ping() {
+        let ping = parse(&["--ping", "--weight-ping", "0.5"]);
+        assert!(ping.ping);
+        assert_eq!(ping.weight_ping(), 0.5);
+    }
+
+    #[test]
+    fn reject_outliers() {
+        assert_eq!(
+            parse(&["--samples", "5", "--reject-outliers", "3"]).reject_outliers,
+            Some(3.0)
+        );
+        assert!(parse_positive("0").is_err());
+        assert!(parse_positive("inf").is_err());
+    }
+
+    #[test]
+    fn weight_freshness() {
+        assert_eq!(parse(&["--weight-freshness", "2"]).weight_age, 2.0);
+    }
+
+    #[test]
+    fn clock_skew_tolerance() {
+        assert_eq!(
+            parse(&["--clock-skew-tolerance", "600"]).clock_skew_tolerance,
+            600
+        );
+    }
+
+    #[test]
+    fn follow_redirects() {
+        let args = parse(&["--max-redirects", "2", "--follow-redirects", "rewrite"]);
+        assert_eq!(args.max_redirects, 2);
+        assert_eq!(args.follow_redirects, FollowRedirects::Rewrite);
+    }
+
+    #[test]
+    fn samples() {
+        let args = parse(&["--samples", "3", "--aggregate", "median"]);
+        assert_eq!(args.samples, 3);
+        assert_eq!(args.aggregate, Aggregate::Median);
+    }
+
+    #[test]
+    fn sort() {
+        assert_eq!(parse(&["--sort", "country"]).sort, Some(SortBy::Country));
+    }
+
+    #[test]
+    fn age() {
+        assert_eq!(
+            parse(&["--age", "1.5"]).age,
+            Some(Duration::from_secs(5400))
+        );
+    }
+
+    #[test]
+    fn ipv6() {
+        let args = parse(&["--ipv6"]);
+        assert!(!args.ipv4);
+        assert!(args.ipv6);
+    }
+
+    #[test]
+    fn isos() {
+        assert!(parse(&["--isos"]).isos);
+    }
+
+    #[test]
+    fn verbose_output() {
+        assert!(parse(&["--verbose-output"]).verbose_output);
+    }
+
+    #[test]
+    fn group_by_country() {
+        assert!(parse(&["--group-by-country"]).group_by_country);
+    }
+
+    #[test]
+    fn commented_extra() {
+        assert_eq!(parse(&["--commented-extra", "3"]).commented_extra, 3);
+    }
+
+    #[test]
+    fn template() {
+        assert_eq!(
+            parse(&["--template", "/etc/pacman-mirrorup/mirrorlist.template"]).template,
+            Some(PathBuf::from("/etc/pacman-mirrorup/mirrorlist.template"))
+        );
+    }
 }
@@ -0,0 +1,265 @@
+use std::{io, path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::info;
+use url::Url;
+
+use crate::{
+    args::{Arguments, CheckAllArgs},
+    clock::ReferenceClock,
+    exit::Failure,
+    http,
+    mirror::{Mirror, MirrorsStatus, StatsFormat},
+    output::{self, Overwrite},
+};
+
+/// Timeout of a probe request
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Problem found on a mirror
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    Ok,
+
+    /// No response, or an error status
+    Broken,
+
+    /// Database file is redirected elsewhere
+    Redirecting,
+
+    /// Fails sync conditions of mirrors status
+    Stale,
+
+    /// Responds with success, but not with a database file
+    Misconfigured,
+}
+
+/// Row of audit report
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Finding {
+    pub url: String,
+    pub country_code: String,
+    pub problem: Problem,
+    pub http_status: Option<u16>,
+    pub detail: String,
+}
+
+/// Run `check-all` subcommand
+pub fn run(arguments: &Arguments, args: &CheckAllArgs) -> Result<()> {
+    // Redirects are reported, not followed
+    let agent: ureq::Agent = http::agent(0);
+    let (status, source) =
+        MirrorsStatus::from_sources(&agent, &arguments.source_url).context(Failure::StatusFetch)?;
+    let clock: ReferenceClock =
+        status.reference_clock(Duration::from_secs(arguments.clock_skew_tolerance));
+
+    let mirrors: Vec<&Mirror> = status.mirrors().iter().filter(|m| m.is_active()).collect();
+    info!("Check {} active mirrors of `{source}`", mirrors.len());
+    let target_db: String = arguments.target_db().path(&arguments.layout());
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .context("Failed to build thread pool")?;
+    let findings: Vec<Finding> = pool.install(|| {
+        mirrors
+            .par_iter()
+            .map(|mirror| check(mirror, &agent, &target_db, &clock))
+            .collect()
+    });
+
+    let problems: usize = findings.iter().filter(|f| f.problem != Problem::Ok).count();
+    info!("Found {problems} mirrors with problems");
+
+    let findings: Vec<Finding> = if args.all {
+        findings
+    } else {
+        findings
+            .into_iter()
+            .filter(|f| f.problem != Problem::Ok)
+            .collect()
+    };
+    let format: StatsFormat = args
+        .format
+        .unwrap_or_else(|| StatsFormat::from_path(&args.output));
+    if args.output == Path::new("-") {
+        return write_report(io::stdout().lock(), &findings, format);
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    write_report(&mut buf, &findings, format)?;
+    let overwrite: Overwrite = if arguments.force {
+        Overwrite::Always
+    } else {
+        Overwrite::Never
+    };
+    output::save(&args.output, &buf, overwrite)
+        .with_context(|| format!("Failed to save report `{}`", args.output.display()))
+}
+
+/// Probe database file of a mirror
+fn check(mirror: &Mirror, agent: &ureq::Agent, target_db: &str, clock: &ReferenceClock) -> Finding {
+    let finding = |problem: Problem, http_status: Option<u16>, detail: String| Finding {
+        url: mirror.url.clone(),
+        country_code: mirror.country_code.clone(),
+        problem,
+        http_status,
+        detail,
+    };
+
+    let url: Url = match Url::parse(&mirror.url).and_then(|url| url.join(target_db)) {
+        Ok(url) => url,
+        Err(err) => return finding(Problem::Misconfigured, None, format!("Invalid URL: {err}")),
+    };
+    let response = match agent.head(url.as_str()).timeout(PROBE_TIMEOUT).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => {
+            return finding(Problem::Broken, Some(code), format!("`{url}`"))
+        }
+        Err(err) => return finding(Problem::Broken, None, err.to_string()),
+    };
+
+    let code: u16 = response.status();
+    if (300..400).contains(&code) {
+        let location: &str = response.header("location").unwrap_or_default();
+        return finding(Problem::Redirecting, Some(code), location.to_string());
+    }
+
+    // An HTML page is served in place of missing files by some web servers
+    let content_type: &str = response.content_type();
+    if content_type.starts_with("text/") {
+        return finding(
+            Problem::Misconfigured,
+            Some(code),
+            format!("Content type `{content_type}`"),
+        );
+    }
+    if response.header("content-length") == Some("0") {
+        return finding(Problem::Misconfigured, Some(code), "Empty file".to_string());
+    }
+
+    match mirror.stale_reason(clock) {
+        Some(reason) => finding(Problem::Stale, Some(code), reason),
+        None => finding(Problem::Ok, Some(code), String::new()),
+    }
+}
+
+fn write_report<W: io::Write>(
+    mut writer: W,
+    findings: &[Finding],
+    format: StatsFormat,
+) -> Result<()> {
+    match format {
+        StatsFormat::Csv | StatsFormat::Tsv => {
+            let delimiter: u8 = if format == StatsFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(writer);
+            for finding in findings {
+                wtr.serialize(finding)?;
+            }
+            wtr.flush()?;
+        }
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, findings)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    use crate::mirror::{Layout, TargetDb};
+
+    /// Answer `requests` HEAD requests: `/ok/` with a database file, `/moved/` with a redirect,
+    /// `/html/` with an HTML page, and anything else with 404
+    fn serve(requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                let path: &str = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let response: String = if path.starts_with("/ok/") {
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: 10\r\n\r\n".to_string()
+                } else if path.starts_with("/moved/") {
+                    "HTTP/1.1 301 Moved Permanently\r\nLocation: https://elsewhere.example/\r\nContent-Length: 0\r\n\r\n".to_string()
+                } else if path.starts_with("/html/") {
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 10\r\n\r\n"
+                        .to_string()
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn test_check() {
+        let base: String = serve(4);
+        let agent: ureq::Agent = http::agent(0);
+        let clock = ReferenceClock::from_status(None, 3600, Duration::from_secs(300), false);
+        let probe = |path: &str| {
+            let mirror = Mirror::from_url(&format!("{base}/{path}/"));
+            check(
+                &mirror,
+                &agent,
+                &TargetDb::Extra.path(&Layout::default()),
+                &clock,
+            )
+        };
+
+        // Mirror known only by URL has never synced
+        let ok = probe("ok");
+        assert_eq!(ok.problem, Problem::Stale);
+        assert_eq!(ok.http_status, Some(200));
+
+        let moved = probe("moved");
+        assert_eq!(moved.problem, Problem::Redirecting);
+        assert_eq!(moved.http_status, Some(301));
+        assert_eq!(moved.detail, "https://elsewhere.example/");
+
+        assert_eq!(probe("html").problem, Problem::Misconfigured);
+
+        let broken = probe("missing");
+        assert_eq!(broken.problem, Problem::Broken);
+        assert_eq!(broken.http_status, Some(404));
+    }
+
+    #[test]
+    fn test_write_report() {
+        let findings = vec![Finding {
+            url: "https://mirror.example/".to_string(),
+            country_code: "TH".to_string(),
+            problem: Problem::Broken,
+            http_status: Some(404),
+            detail: String::new(),
+        }];
+        let mut csv: Vec<u8> = Vec::new();
+        write_report(&mut csv, &findings, StatsFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "url,country_code,problem,http_status,detail\nhttps://mirror.example/,TH,broken,404,\n"
+        );
+    }
+}
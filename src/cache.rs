@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    mirror::{BenchmarkOptions, Evaluation, Mirror, Mirrors, MirrorsStatus},
+    output, paths,
+};
+
+/// Measurements of a mirror from a previous run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    /// Unix time of measurement
+    time: i64,
+
+    /// Database file downloaded, see [`crate::mirror::TargetDb::path`]
+    target_db: String,
+
+    transfer_rate: f64,
+    transfer_rate_stddev: Option<f64>,
+    dns_time: Option<f64>,
+    connect_time: Option<f64>,
+    tls_time: Option<f64>,
+    ttfb: Option<f64>,
+    transfer_time: Option<f64>,
+}
+
+/// Transfer rates of mirrors keyed by URL, reused by later runs until they are older than TTL
+#[derive(Debug)]
+pub struct BenchmarkCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+/// Default cache file of `--cache-ttl`
+pub fn default_path() -> PathBuf {
+    paths::cache_dir().join("benchmark.json")
+}
+
+/// Mirrors status of the last run, next to cache file
+pub fn status_path(cache_file: &Path) -> PathBuf {
+    cache_file.with_file_name("mirrors_status.json")
+}
+
+/// Read mirrors status saved by [`save_status`]
+pub fn load_status(path: &Path) -> Result<MirrorsStatus> {
+    let content: Vec<u8> = fs::read(path).with_context(|| {
+        format!(
+            "No cached mirrors status `{}`, run with `--cache-ttl` while online first",
+            path.display()
+        )
+    })?;
+    serde_json::from_slice(&content)
+        .with_context(|| format!("Invalid cached mirrors status `{}`", path.display()))
+}
+
+/// Save mirrors status for later offline runs
+pub fn save_status(path: &Path, status: &MirrorsStatus) -> Result<()> {
+    create_parent_dir(path)?;
+    output::write_file(path, &serde_json::to_vec(status)?)
+        .with_context(|| format!("Could not save mirrors status `{}`", path.display()))
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create directory `{}`", dir.display()))?;
+    }
+    Ok(())
+}
+
+impl BenchmarkCache {
+    /// Load cache file, an unreadable cache is ignored
+    pub fn open(path: &Path, ttl: Duration) -> Result<Self> {
+        let entries: HashMap<String, Entry> = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                warn!("Ignore invalid cache `{}`: {err}", path.display());
+                HashMap::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Could not read `{}`", path.display()))
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            ttl,
+            entries,
+        })
+    }
+
+    /// Fill measurements of mirror from cache, returns false if there is no fresh entry
+    fn fill(&self, mirror: &mut Mirror, target_db: &str, now: i64) -> bool {
+        let Some(entry) = self.entries.get(&mirror.url_key()) else {
+            return false;
+        };
+        let age: u64 = now
+            .saturating_sub(entry.time)
+            .try_into()
+            .unwrap_or(u64::MAX);
+        if entry.target_db != target_db || age > self.ttl.as_secs() {
+            return false;
+        }
+
+        mirror.transfer_rate = Some(entry.transfer_rate);
+        mirror.transfer_rate_stddev = entry.transfer_rate_stddev;
+        mirror.dns_time = entry.dns_time;
+        mirror.connect_time = entry.connect_time;
+        mirror.tls_time = entry.tls_time;
+        mirror.ttfb = entry.ttfb;
+        mirror.transfer_time = entry.transfer_time;
+        true
+    }
+
+    /// Remember successful measurements, failed ones are measured again next run
+    fn update(&mut self, mirrors: &[Mirror], target_db: &str, now: i64) {
+        for mirror in mirrors {
+            let Some(transfer_rate) = mirror.transfer_rate else {
+                continue;
+            };
+            self.entries.insert(
+                mirror.url_key(),
+                Entry {
+                    time: now,
+                    target_db: target_db.to_string(),
+                    transfer_rate,
+                    transfer_rate_stddev: mirror.transfer_rate_stddev,
+                    dns_time: mirror.dns_time,
+                    connect_time: mirror.connect_time,
+                    tls_time: mirror.tls_time,
+                    ttfb: mirror.ttfb,
+                    transfer_time: mirror.transfer_time,
+                },
+            );
+        }
+    }
+
+    /// Benchmark mirrors without fresh cached measurements, in the same order as given
+    pub fn benchmark(&mut self, mirrors: &Mirrors, options: &BenchmarkOptions) -> Mirrors {
+        let now: i64 = Utc::now().timestamp();
+        let target_db: &str = &options.target();
+
+        let mut all: Vec<Mirror> = mirrors.to_vec();
+        let stale: Vec<usize> = all
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, mirror)| (!self.fill(mirror, target_db, now)).then_some(i))
+            .collect();
+        info!(
+            "Reuse cached measurements of {} mirrors",
+            all.len() - stale.len()
+        );
+
+        let measured: Mirrors = stale
+            .iter()
+            .map(|&i| all[i].clone())
+            .collect::<Mirrors>()
+            .benchmark(options);
+        self.update(&measured, target_db, now);
+        for (i, mirror) in stale.into_iter().zip(measured.iter()) {
+            all[i] = mirror.clone();
+        }
+        all.into_iter().collect()
+    }
+
+    /// Mirrors with cached measurements only, without any network access
+    pub fn cached(&self, mirrors: &Mirrors, target_db: &str) -> Result<Mirrors> {
+        let now: i64 = Utc::now().timestamp();
+        let mut cached: usize = 0;
+        let mirrors: Mirrors = mirrors
+            .iter()
+            .cloned()
+            .map(|mut mirror| {
+                if self.fill(&mut mirror, target_db, now) {
+                    cached += 1;
+                }
+                mirror
+            })
+            .collect();
+        if cached == 0 {
+            bail!(
+                "No cached measurements in `{}`, run with `--cache-ttl` while online first",
+                self.path.display()
+            );
+        }
+        info!("Use cached measurements of {cached} mirrors");
+        Ok(mirrors)
+    }
+
+    /// Write cache file, dropping expired entries
+    pub fn save(&mut self) -> Result<()> {
+        let now: i64 = Utc::now().timestamp();
+        let ttl: i64 = self.ttl.as_secs().try_into().unwrap_or(i64::MAX);
+        self.entries
+            .retain(|_, entry| now.saturating_sub(entry.time) <= ttl);
+
+        create_parent_dir(&self.path)?;
+        output::write_file(&self.path, &serde_json::to_vec(&self.entries)?)
+            .with_context(|| format!("Could not save cache `{}`", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTRA: &str = "extra/os/x86_64/extra.db";
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join("benchmark.json");
+        let ttl = Duration::from_secs(3600);
+
+        let now: i64 = Utc::now().timestamp();
+
+        let mut cache = BenchmarkCache::open(&path, ttl).unwrap();
+        cache.update(
+            &[
+                Mirror::with_transfer_rate("https://a.example/", Some(1000.0)),
+                Mirror::from_url("https://failed.example/"),
+            ],
+            EXTRA,
+            now,
+        );
+        cache.save().unwrap();
+
+        let cache = BenchmarkCache::open(&path, ttl).unwrap();
+        assert_eq!(cache.entries.len(), 1);
+
+        let mut a = Mirror::from_url("https://a.example");
+        assert!(cache.fill(&mut a, EXTRA, now));
+        assert_eq!(a.transfer_rate, Some(1000.0));
+
+        // Expired, or measured with another database file
+        assert!(!cache.fill(&mut a, EXTRA, now + 3601));
+        assert!(!cache.fill(&mut a, "core/os/x86_64/core.db", now));
+        assert!(!cache.fill(&mut Mirror::from_url("https://failed.example/"), EXTRA, now));
+    }
+
+    #[test]
+    fn test_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("benchmark.json");
+        let status_file = status_path(&cache_file);
+        assert_eq!(status_file, dir.path().join("mirrors_status.json"));
+        assert!(load_status(&status_file).is_err());
+
+        let status: MirrorsStatus = serde_json::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        )))
+        .unwrap();
+        save_status(&status_file, &status).unwrap();
+        let loaded = load_status(&status_file).unwrap();
+        assert_eq!(
+            serde_json::to_string(&loaded).unwrap(),
+            serde_json::to_string(&status).unwrap()
+        );
+
+        let mut cache = BenchmarkCache::open(&cache_file, Duration::MAX).unwrap();
+        let mirrors: Mirrors = [
+            Mirror::from_url("https://a.example/"),
+            Mirror::from_url("https://b.example/"),
+        ]
+        .into_iter()
+        .collect();
+        assert!(cache.cached(&mirrors, EXTRA).is_err());
+
+        cache.update(
+            &[Mirror::with_transfer_rate(
+                "https://a.example/",
+                Some(1000.0),
+            )],
+            EXTRA,
+            Utc::now().timestamp(),
+        );
+        let cached = cache.cached(&mirrors, EXTRA).unwrap();
+        assert_eq!(cached[0].transfer_rate, Some(1000.0));
+        assert_eq!(cached[1].transfer_rate, None);
+    }
+
+    #[test]
+    fn test_invalid_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("benchmark.json");
+        fs::write(&path, "not json").unwrap();
+        let cache = BenchmarkCache::open(&path, Duration::from_secs(60)).unwrap();
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use tracing::{debug, warn};
+
+/// Default tolerated difference (in seconds) between local clock and mirrors status's clock
+pub const DEFAULT_CLOCK_SKEW_TOLERANCE: u64 = 300;
+
+/// Parse RFC 3339 timestamp from mirrors status data
+pub fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>> {
+    let timestamp = DateTime::parse_from_rfc3339(timestamp)
+        .with_context(|| format!("Invalid timestamp `{timestamp}`"))?;
+    Ok(timestamp.with_timezone(&Utc))
+}
+
+/// A clock used as reference when comparing freshness of timestamps.
+///
+/// Local time is used as reference unless it is grossly different from the time
+/// mirrors status was generated, e.g. on machine with drifted RTC. In that case
+/// the mirrors status's time is used instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceClock {
+    now: DateTime<Utc>,
+    tolerance: TimeDelta,
+}
+
+impl ReferenceClock {
+    /// Create reference clock from local time and the time mirrors status was generated.
+    ///
+    /// Mirrors status is expected to be generated at most `max_status_age` before local time.
+    /// Only a `fresh` mirrors status, i.e. just downloaded, tells whether local clock is ahead;
+    /// an older one, e.g. cached or archived, is expected to be older than that.
+    pub fn new(
+        local_now: DateTime<Utc>,
+        status_time: Option<DateTime<Utc>>,
+        tolerance: TimeDelta,
+        max_status_age: TimeDelta,
+        fresh: bool,
+    ) -> Self {
+        let Some(status_time) = status_time else {
+            return Self {
+                now: local_now,
+                tolerance,
+            };
+        };
+
+        let skew: TimeDelta = local_now - status_time;
+        if skew < -tolerance {
+            warn!(
+                "Local clock is behind mirrors status by {}s, check system time. Use mirrors status's time as reference.",
+                -skew.num_seconds()
+            );
+            return Self {
+                now: status_time,
+                tolerance,
+            };
+        }
+
+        if skew > max_status_age + tolerance {
+            if fresh {
+                warn!(
+                    "Local clock is ahead of mirrors status by {}s, check system time. Use mirrors status's time as reference.",
+                    skew.num_seconds()
+                );
+            } else {
+                debug!(
+                    "Mirrors status is {}s old, use its time as reference",
+                    skew.num_seconds()
+                );
+            }
+            return Self {
+                now: status_time,
+                tolerance,
+            };
+        }
+
+        Self {
+            now: local_now,
+            tolerance,
+        }
+    }
+
+    /// Create reference clock from mirrors status's `last_check` and `cutoff`
+    pub fn from_status(
+        last_check: Option<&str>,
+        cutoff: u64,
+        tolerance: Duration,
+        fresh: bool,
+    ) -> Self {
+        let status_time = match last_check.map(parse_timestamp) {
+            Some(Ok(t)) => Some(t),
+            Some(Err(err)) => {
+                debug!("{err:#}");
+                None
+            }
+            None => None,
+        };
+        Self::new(
+            Utc::now(),
+            status_time,
+            TimeDelta::from_std(tolerance).unwrap_or(TimeDelta::MAX),
+            TimeDelta::seconds(cutoff.try_into().unwrap_or(i64::MAX)),
+            fresh,
+        )
+    }
+
+    /// Current time of reference clock
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now
+    }
+
+    /// Age of timestamp relative to reference clock.
+    /// A timestamp in the future is treated as zero age.
+    pub fn age(&self, timestamp: DateTime<Utc>) -> TimeDelta {
+        (self.now - timestamp).max(TimeDelta::zero())
+    }
+
+    /// Returns true if timestamp is later than reference clock beyond tolerance
+    pub fn is_ahead(&self, timestamp: DateTime<Utc>) -> bool {
+        timestamp - self.now > self.tolerance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_time() -> DateTime<Utc> {
+        parse_timestamp("2023-06-28T19:09:25.337Z").unwrap()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("2023-06-28T18:27:53Z").unwrap().timestamp(),
+            1687976873
+        );
+        assert!(parse_timestamp("28/06/2023").is_err());
+    }
+
+    #[test]
+    fn test_local_clock_is_sane() {
+        let local_now = status_time() + TimeDelta::seconds(120);
+        let clock = ReferenceClock::new(
+            local_now,
+            Some(status_time()),
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            true,
+        );
+        assert_eq!(clock.now(), local_now);
+
+        // Local clock slightly behind, but within tolerance
+        let local_now = status_time() - TimeDelta::seconds(60);
+        let clock = ReferenceClock::new(
+            local_now,
+            Some(status_time()),
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            true,
+        );
+        assert_eq!(clock.now(), local_now);
+    }
+
+    #[test]
+    fn test_local_clock_is_wrong() {
+        // Behind
+        let clock = ReferenceClock::new(
+            status_time() - TimeDelta::days(30),
+            Some(status_time()),
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            true,
+        );
+        assert_eq!(clock.now(), status_time());
+
+        // Ahead
+        let clock = ReferenceClock::new(
+            status_time() + TimeDelta::days(30),
+            Some(status_time()),
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            true,
+        );
+        assert_eq!(clock.now(), status_time());
+    }
+
+    #[test]
+    fn test_old_status() {
+        // Cached or archived mirrors status is still the reference for its own mirrors
+        let clock = ReferenceClock::new(
+            status_time() + TimeDelta::days(30),
+            Some(status_time()),
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            false,
+        );
+        assert_eq!(clock.now(), status_time());
+    }
+
+    #[test]
+    fn test_freshness_comparison() {
+        let clock = ReferenceClock::new(
+            status_time(),
+            None,
+            TimeDelta::seconds(300),
+            TimeDelta::days(1),
+            true,
+        );
+
+        let past = status_time() - TimeDelta::hours(2);
+        assert!(!clock.is_ahead(past));
+        assert_eq!(clock.age(past), TimeDelta::hours(2));
+
+        let near_future = status_time() + TimeDelta::seconds(200);
+        assert!(!clock.is_ahead(near_future));
+        assert_eq!(clock.age(near_future), TimeDelta::zero());
+
+        let far_future = status_time() + TimeDelta::seconds(600);
+        assert!(clock.is_ahead(far_future));
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::{bail, Result};
+
+/// Join `args` into a command line written to a configuration file read by `target`,
+/// e.g. a pacman hook or a systemd unit.
+///
+/// Neither file format has quoting shared with the shell, so arguments needing
+/// quotes are refused instead of being escaped.
+pub fn join(args: &[String], target: &str) -> Result<String> {
+    for arg in args {
+        if arg.is_empty() || arg.contains(char::is_whitespace) || arg.contains(['\'', '"', '\\']) {
+            bail!("Argument `{arg}` cannot be written to {target}");
+        }
+    }
+    Ok(args.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(
+            join(&args(&["--exclude", "bad.example"]), "pacman hook").unwrap(),
+            "--exclude bad.example"
+        );
+        assert_eq!(join(&[], "pacman hook").unwrap(), "");
+        assert!(join(&args(&["/etc/my mirrors"]), "pacman hook").is_err());
+        assert!(join(&args(&["it's"]), "pacman hook").is_err());
+        assert!(join(&args(&[""]), "pacman hook").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::env;
+
+/// When to color terminal output
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ColorChoice {
+    /// Color when output is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output to a stream should be colored
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+}
+
+impl Color {
+    fn code(self) -> u8 {
+        match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+        }
+    }
+}
+
+/// Wrap `s` in ANSI color escape codes, unchanged if `enabled` is false
+pub fn paint(s: &str, color: Color, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{s}\x1b[0m", color.code())
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(paint("10", Color::Green, true), "\x1b[32m10\x1b[0m");
+        assert_eq!(paint("10", Color::Green, false), "10");
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    str::FromStr,
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use tracing::{debug, info};
+
+use crate::{
+    mirror::{Benchmark, BenchmarkOptions, Mirror, Mirrors},
+    samples, schedule,
+};
+
+/// Upper bound of automatically chosen number of threads
+pub const MAX_AUTO_THREADS: usize = 16;
+
+/// Number of mirrors used to probe link capacity
+const PROBES: usize = 3;
+
+/// Concurrent transfers are considered not saturating the link
+/// when their total rate reaches this ratio of their sequential rates.
+const UNSATURATED_RATIO: f64 = 0.8;
+
+/// Rates of concurrent transfers limited by local link are about equal,
+/// their coefficient of variation is below this
+const EVEN_SHARE_CV: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    Auto,
+    Fixed(usize),
+}
+
+impl FromStr for Threads {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Threads::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) => bail!("number of threads must be greater than 0"),
+            Ok(n) => Ok(Threads::Fixed(n)),
+            Err(_) => bail!("expected a number or `auto`"),
+        }
+    }
+}
+
+/// Estimate local downlink capacity by probing a few mirrors, first one at a time
+/// then all at once, and pick number of threads from it.
+pub fn auto_threads(mirrors: &Mirrors, options: &BenchmarkOptions) -> usize {
+    let probes: Vec<Mirror> = schedule::interleave(mirrors)
+        .into_iter()
+        .take(PROBES)
+        .map(|i| mirrors[i].clone())
+        .collect();
+
+    // Sequential transfer rate of each probe
+    let single_rates: Vec<f64> = probes
+        .iter()
+        .cloned()
+        .filter_map(|mut mirror| {
+            mirror.measure_duration(options).ok()?;
+            mirror.transfer_rate()
+        })
+        .collect();
+
+    // Total transfer rate of all probes running concurrently
+    let start = Instant::now();
+    let aggregate_rate: f64 = std::thread::scope(|scope| {
+        let handles: Vec<_> = probes
+            .iter()
+            .cloned()
+            .map(|mut mirror| {
+                scope.spawn(move || {
+                    mirror.measure_duration(options).ok()?;
+                    mirror.transfer_rate()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|h| h.join().ok().flatten())
+            .sum()
+    });
+    debug!(
+        "Probe rates: single = {single_rates:?}, aggregate = {aggregate_rate} in {:?}",
+        start.elapsed()
+    );
+
+    let threads = pick_concurrency(&single_rates, aggregate_rate);
+    info!("Use {threads} threads to measure transfer rate");
+    threads
+}
+
+/// Pick number of concurrent transfers that do not starve each other.
+///
+/// Capacity of local link is estimated from aggregate rate of concurrent probes.
+/// Each transfer should get about the rate it gets when running alone.
+pub fn pick_concurrency(single_rates: &[f64], aggregate_rate: f64) -> usize {
+    let mut rates: Vec<f64> = single_rates
+        .iter()
+        .copied()
+        .filter(|r| r.is_finite() && *r > 0.0)
+        .collect();
+    if rates.is_empty() {
+        return 1;
+    }
+    rates.sort_by(f64::total_cmp);
+
+    let median: f64 = rates[rates.len() / 2];
+    let max: f64 = rates[rates.len() - 1];
+    let sum: f64 = rates.iter().sum();
+    let capacity: f64 = aggregate_rate.max(max);
+
+    let mut threads: usize = (capacity / median).floor() as usize;
+
+    // Probes did not saturate the link, capacity is only a lower bound.
+    if aggregate_rate >= UNSATURATED_RATIO * sum {
+        threads = threads.max(2 * rates.len());
+    }
+
+    threads.clamp(1, MAX_AUTO_THREADS)
+}
+
+/// Limit of concurrent transfers, halved when they contend for local link.
+///
+/// After every `limit` finished transfers, their rates are checked by [`is_contended`].
+#[derive(Debug)]
+pub struct AdaptiveLimit {
+    state: Mutex<LimitState>,
+    available: Condvar,
+}
+
+#[derive(Debug)]
+struct LimitState {
+    limit: usize,
+    running: usize,
+
+    /// Rates of transfers finished since last check
+    window: Vec<f64>,
+
+    /// Highest total rate of a window
+    peak: f64,
+}
+
+impl AdaptiveLimit {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(LimitState {
+                limit: limit.max(1),
+                running: 0,
+                window: Vec::new(),
+                peak: 0.0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Wait until another transfer may start
+    pub fn acquire(&self) {
+        let mut state = self.state.lock().expect("Lock adaptive limit");
+        while state.running >= state.limit {
+            state = self.available.wait(state).expect("Lock adaptive limit");
+        }
+        state.running += 1;
+    }
+
+    /// Transfer finished with `rate`, None if it failed
+    pub fn release(&self, rate: Option<f64>) {
+        let mut state = self.state.lock().expect("Lock adaptive limit");
+        state.running -= 1;
+        if let Some(rate) = rate {
+            state.window.push(rate);
+        }
+
+        if state.window.len() >= state.limit {
+            let total: f64 = state.window.iter().sum();
+            state.peak = state.peak.max(total);
+            if state.limit > 1 && is_contended(&state.window, state.peak) {
+                state.limit /= 2;
+                info!(
+                    "Concurrent transfers contend for local link, reduce to {} threads",
+                    state.limit
+                );
+            }
+            state.window.clear();
+        }
+        self.available.notify_all();
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().expect("Lock adaptive limit").limit
+    }
+}
+
+/// Concurrent transfers are limited by local link rather than by mirrors when they share it evenly,
+/// i.e. their rates are about equal, and their total rate is close to the highest seen.
+pub fn is_contended(rates: &[f64], peak: f64) -> bool {
+    if rates.len() < 2 || peak <= 0.0 {
+        return false;
+    }
+    let total: f64 = rates.iter().sum();
+    let mean: f64 = total / rates.len() as f64;
+    let cv: f64 = match samples::stddev(rates) {
+        Some(stddev) if mean > 0.0 => stddev / mean,
+        _ => return false,
+    };
+    cv < EVEN_SHARE_CV && total >= UNSATURATED_RATIO * peak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_threads() {
+        assert_eq!(Threads::from_str("auto").unwrap(), Threads::Auto);
+        assert_eq!(Threads::from_str("AUTO").unwrap(), Threads::Auto);
+        assert_eq!(Threads::from_str("8").unwrap(), Threads::Fixed(8));
+        assert!(Threads::from_str("0").is_err());
+        assert!(Threads::from_str("many").is_err());
+    }
+
+    #[test]
+    fn test_pick_concurrency() {
+        // No successful probe
+        assert_eq!(pick_concurrency(&[], 0.0), 1);
+
+        // Saturated link: 3 probes at 10 MB/s each, only 12 MB/s together
+        assert_eq!(pick_concurrency(&[10e6, 10e6, 10e6], 12e6), 1);
+
+        // Link shared well by 2 transfers
+        assert_eq!(pick_concurrency(&[10e6, 10e6, 10e6], 20e6), 2);
+
+        // Unsaturated link: concurrent probes get their full rates
+        assert_eq!(pick_concurrency(&[1e6, 2e6, 3e6], 6e6), 6);
+
+        // Never exceed upper bound
+        assert_eq!(pick_concurrency(&[1e3, 1e3, 1e3], 1e9), MAX_AUTO_THREADS);
+    }
+
+    #[test]
+    fn test_is_contended() {
+        // Link shared evenly at its capacity
+        assert!(is_contended(&[2.4e6, 2.5e6, 2.6e6, 2.5e6], 10e6));
+
+        // Mirrors of different speed, not limited by link
+        assert!(!is_contended(&[0.5e6, 8e6, 3e6, 1e6], 12.5e6));
+
+        // Evenly slow, but far below the highest total rate
+        assert!(!is_contended(&[1e6, 1e6, 1e6], 10e6));
+
+        assert!(!is_contended(&[10e6], 10e6));
+    }
+
+    #[test]
+    fn test_adaptive_limit() {
+        let limit = AdaptiveLimit::new(4);
+        for _ in 0..4 {
+            limit.acquire();
+        }
+        for _ in 0..4 {
+            limit.release(Some(2.5e6));
+        }
+        assert_eq!(limit.limit(), 2);
+
+        // Failed transfers do not count
+        limit.acquire();
+        limit.release(None);
+        assert_eq!(limit.limit(), 2);
+
+        for _ in 0..2 {
+            limit.acquire();
+            limit.release(Some(1e6));
+        }
+        assert_eq!(limit.limit(), 2);
+    }
+}
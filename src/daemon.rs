@@ -0,0 +1,61 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tracing::{error, info};
+
+use crate::{notify, schedule};
+
+/// Maximum random delay added to interval, as a fraction of it,
+/// so that many machines started at the same time do not hit mirrors together
+const MAX_JITTER: f64 = 0.1;
+
+/// Run `cycle` now and then every `interval` plus random jitter, forever.
+/// A failed cycle is logged and does not stop the next ones.
+pub fn run<F>(interval: Duration, mut cycle: F) -> !
+where
+    F: FnMut() -> Result<()>,
+{
+    let mut n: u64 = 1;
+    loop {
+        info!("Start update cycle {n}");
+        let started: Instant = Instant::now();
+        match cycle() {
+            Ok(()) => info!(
+                "Update cycle {n} finished in {:.1}s",
+                started.elapsed().as_secs_f64()
+            ),
+            Err(err) => error!("Update cycle {n} failed: {err:#}"),
+        }
+
+        let delay: Duration = interval + jitter(interval, schedule::random_seed());
+        info!("Next update cycle in {:.0}s", delay.as_secs_f64());
+        notify::status(&format!(
+            "Waiting {:.0}s for update cycle {}",
+            delay.as_secs_f64(),
+            n + 1
+        ));
+        thread::sleep(delay);
+        n += 1;
+    }
+}
+
+/// Random delay of up to `MAX_JITTER` of interval, from random number `r`
+fn jitter(interval: Duration, r: u64) -> Duration {
+    interval.mul_f64(MAX_JITTER * (r as f64 / u64::MAX as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter() {
+        let interval = Duration::from_secs(12 * 3600);
+        assert_eq!(jitter(interval, 0), Duration::ZERO);
+        assert_eq!(jitter(interval, u64::MAX), Duration::from_secs(4320));
+        assert!(jitter(interval, schedule::random_seed()) <= Duration::from_secs(4320));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+
+/// Timeout of DNS query
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Largest DNS message over UDP without EDNS
+const MAX_MESSAGE_LEN: usize = 512;
+
+/// Maximum number of labels and compression pointers followed in a name
+const MAX_NAME_PARTS: usize = 128;
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const CLASS_IN: u16 = 1;
+
+/// Names `host` is an alias of, in order of its CNAME chain,
+/// asked to the first nameserver of `/etc/resolv.conf`
+pub fn cname_chain(host: &str) -> Result<Vec<String>> {
+    let resolv_conf: String = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let server = SocketAddr::new(
+        nameserver(&resolv_conf).unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        53,
+    );
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    }
+    .parse()?;
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket
+        .connect(server)
+        .with_context(|| format!("Failed to connect nameserver `{server}`"))?;
+
+    let id: u16 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.subsec_nanos() as u16)
+        .unwrap_or_default();
+    socket.send(&query(id, host)?)?;
+    let mut response = [0u8; MAX_MESSAGE_LEN];
+    let len: usize = socket
+        .recv(&mut response)
+        .with_context(|| format!("No DNS response of `{host}` from `{server}`"))?;
+    cnames(id, &response[..len]).with_context(|| format!("Invalid DNS response of `{host}`"))
+}
+
+/// Address of first `nameserver` in resolv.conf
+fn nameserver(resolv_conf: &str) -> Option<IpAddr> {
+    resolv_conf.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        (fields.next() == Some("nameserver"))
+            .then(|| fields.next()?.parse().ok())
+            .flatten()
+    })
+}
+
+/// Recursive query of `A` records of `host`, whose answer includes its CNAME chain
+fn query(id: u16, host: &str) -> Result<Vec<u8>> {
+    let mut message: Vec<u8> = Vec::with_capacity(MAX_MESSAGE_LEN);
+    message.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("Invalid host name `{host}`");
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&TYPE_A.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Targets of CNAME records in answer of query `id`
+fn cnames(id: u16, message: &[u8]) -> Result<Vec<String>> {
+    if message.len() < 12 || be16(message, 0)? != id {
+        bail!("Response is not of the query");
+    }
+    // NXDOMAIN has no answer, but is not an error
+    let rcode: u8 = message[3] & 0x0f;
+    if rcode != 0 && rcode != 3 {
+        bail!("Response code {rcode}");
+    }
+
+    let mut pos: usize = 12;
+    for _ in 0..be16(message, 4)? {
+        pos = read_name(message, pos)?.1 + 4;
+    }
+    let mut chain: Vec<String> = Vec::new();
+    for _ in 0..be16(message, 6)? {
+        let (_, record) = read_name(message, pos)?;
+        let rdata: usize = record + 10;
+        if be16(message, record)? == TYPE_CNAME {
+            chain.push(read_name(message, rdata)?.0);
+        }
+        pos = rdata + be16(message, record + 8)? as usize;
+    }
+    Ok(chain)
+}
+
+/// Lowercase name at `pos`, and position after it
+fn read_name(message: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end: Option<usize> = None;
+    for _ in 0..MAX_NAME_PARTS {
+        let len: usize = *message.get(pos).context("Truncated name")? as usize;
+        match len {
+            0 => return Ok((labels.join(".").to_lowercase(), end.unwrap_or(pos + 1))),
+            // Compression pointer to earlier name
+            len if len & 0xc0 == 0xc0 => {
+                let low: usize = *message.get(pos + 1).context("Truncated name")? as usize;
+                end.get_or_insert(pos + 2);
+                pos = (len & 0x3f) << 8 | low;
+            }
+            len => {
+                let label: &[u8] = message
+                    .get(pos + 1..pos + 1 + len)
+                    .context("Truncated name")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+        }
+    }
+    bail!("Name has too many labels")
+}
+
+fn be16(message: &[u8], pos: usize) -> Result<u16> {
+    let bytes: &[u8] = message.get(pos..pos + 2).context("Truncated message")?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nameserver() {
+        let conf = "# Generated\nsearch example.org\nnameserver fe80::1%eth0\nnameserver 127.0.0.53\nnameserver 192.0.2.53\n";
+        assert_eq!(nameserver(conf), Some("127.0.0.53".parse().unwrap()));
+        assert_eq!(nameserver("search example.org\n"), None);
+    }
+
+    #[test]
+    fn test_cnames() {
+        let mut message: Vec<u8> = query(0x1234, "mirror.example.org").unwrap();
+        assert!(query(1, "mirror..example.org").is_err());
+
+        // Response with answer count of 3
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = 3;
+        // mirror.example.org CNAME mirror.cdn.example.net
+        message.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 24]);
+        message.extend_from_slice(b"\x06mirror\x03cdn\x07example\x03net\x00");
+        // mirror.cdn.example.net CNAME edge.Fastly.net, `net` is compressed
+        let alias: u8 = (message.len() - 24) as u8;
+        message.extend_from_slice(&[0xc0, alias, 0, 5, 0, 1, 0, 0, 0, 60, 0, 14]);
+        message.extend_from_slice(b"\x04edge\x06Fastly\xc0");
+        message.push(alias + 19);
+        // edge.fastly.net A 192.0.2.1
+        let edge: u8 = (message.len() - 14) as u8;
+        message.extend_from_slice(&[0xc0, edge, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+
+        assert_eq!(
+            cnames(0x1234, &message).unwrap(),
+            vec!["mirror.cdn.example.net", "edge.fastly.net"]
+        );
+        assert!(cnames(0x4321, &message).is_err());
+        assert!(cnames(0x1234, &message[..message.len() - 20]).is_err());
+    }
+}
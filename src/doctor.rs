@@ -0,0 +1,343 @@
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use url::Url;
+
+use crate::{args::Arguments, exclude::ExcludeKind, http, mirror::MirrorsStatus, source::Source};
+
+/// Timeout of TCP connection checks
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Result of a check, with a hint how to fix it unless it is ok
+#[derive(Debug, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status: &str = match self.status {
+            Status::Ok => " OK ",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{status}] {}: {}", self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `doctor` subcommand, checking the environment of options given before it
+pub fn run(arguments: &Arguments) -> Result<()> {
+    let agent: ureq::Agent = http::agent(arguments.max_redirects);
+    let mut checks: Vec<Check> = Vec::new();
+
+    for source in &arguments.source_url {
+        if let Ok(Source::Http(url)) = Source::parse(source) {
+            checks.extend(check_host(&url));
+        }
+
+        match MirrorsStatus::from_online_json(&agent, source) {
+            Ok(status) => {
+                checks.push(Check::ok(
+                    "Mirrors status",
+                    format!("`{source}` has {} mirrors", status.mirrors().len()),
+                ));
+                checks.push(match status.generated_at() {
+                    Ok(generated_at) => check_clock(
+                        Utc::now(),
+                        generated_at,
+                        status.cutoff(),
+                        arguments.clock_skew_tolerance,
+                    ),
+                    Err(err) => Check::warn(
+                        "Clock",
+                        format!("{err:#}"),
+                        "Mirrors status has no valid `last_check` to compare local clock with",
+                    ),
+                });
+            }
+            Err(err) => checks.push(Check::fail(
+                "Mirrors status",
+                format!("{err:#}"),
+                "Check network connection, proxy, or `--source-url`",
+            )),
+        }
+    }
+
+    if let Some(output_file) = &arguments.output_file {
+        checks.push(check_output(
+            output_file,
+            arguments.in_place || arguments.force || arguments.backup.is_some(),
+        ));
+    }
+    for (name, file) in [
+        ("Exclude file", &arguments.exclude_from),
+        ("Include file", &arguments.include_from),
+    ] {
+        if let Some(file) = file {
+            checks.push(check_exclude_file(name, file));
+        }
+    }
+
+    for check in &checks {
+        println!("{check}");
+    }
+
+    let failed: usize = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+/// DNS resolution and IPv6 reachability of host of `url`
+fn check_host(url: &str) -> Vec<Check> {
+    let Some((host, port)) = Url::parse(url)
+        .ok()
+        .and_then(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)))
+    else {
+        return Vec::new();
+    };
+
+    let addrs: Vec<SocketAddr> = match (host.as_str(), port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => {
+            return vec![Check::fail(
+                "DNS",
+                format!("Could not resolve `{host}`: {err}"),
+                "Check `/etc/resolv.conf` and network connection",
+            )]
+        }
+    };
+    let mut checks = vec![Check::ok(
+        "DNS",
+        format!("`{host}` resolves to {} address(es)", addrs.len()),
+    )];
+
+    let Some(v6) = addrs.iter().find(|addr| addr.is_ipv6()) else {
+        checks.push(Check::warn(
+            "IPv6",
+            format!("`{host}` has no IPv6 address"),
+            "IPv6 reachability is not checked",
+        ));
+        return checks;
+    };
+    checks.push(match TcpStream::connect_timeout(v6, CONNECT_TIMEOUT) {
+        Ok(_) => Check::ok("IPv6", format!("Connected to {v6}")),
+        Err(err) => Check::warn(
+            "IPv6",
+            format!("Could not connect to {v6}: {err}"),
+            "Mirrors are reached over IPv4 only, `--ipv4` ranks only mirrors supporting it",
+        ),
+    });
+    checks
+}
+
+/// Local clock compared with the time mirrors status was generated,
+/// which is expected to be at most `cutoff` seconds ago
+fn check_clock(
+    local: DateTime<Utc>,
+    generated_at: DateTime<Utc>,
+    cutoff: u64,
+    tolerance: u64,
+) -> Check {
+    let skew: TimeDelta = local - generated_at;
+    let tolerance = TimeDelta::seconds(tolerance.try_into().unwrap_or(i64::MAX));
+    let cutoff = TimeDelta::seconds(cutoff.try_into().unwrap_or(i64::MAX));
+    let hint = "Check system time, e.g. enable `systemd-timesyncd` with `timedatectl set-ntp true`";
+
+    if skew < -tolerance {
+        Check::fail(
+            "Clock",
+            format!(
+                "Local clock is {}s behind mirrors status",
+                -skew.num_seconds()
+            ),
+            hint,
+        )
+    } else if skew > cutoff + tolerance {
+        Check::warn(
+            "Clock",
+            format!(
+                "Local clock is {}s ahead of mirrors status",
+                skew.num_seconds()
+            ),
+            hint,
+        )
+    } else {
+        Check::ok("Clock", "Local clock agrees with mirrors status")
+    }
+}
+
+/// Output file can be written, and replaced if it exists
+fn check_output(path: &Path, replace: bool) -> Check {
+    const NAME: &str = "Output file";
+    let dir: &Path = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Check::fail(
+            NAME,
+            format!("Directory `{}` does not exist", dir.display()),
+            "Create it or choose another `--output-file`",
+        );
+    }
+
+    // Output is written to a temporary file in the same directory first
+    let probe = dir.join(format!(".pacman-mirrorup-doctor.{}", std::process::id()));
+    if let Err(err) = OpenOptions::new().write(true).create_new(true).open(&probe) {
+        return Check::fail(
+            NAME,
+            format!("Could not write to `{}`: {err}", dir.display()),
+            "Run as root, or choose another `--output-file`",
+        );
+    }
+    let _ = fs::remove_file(&probe);
+
+    if path.exists() && !replace {
+        return Check::warn(
+            NAME,
+            format!("`{}` exists", path.display()),
+            "Add `--in-place` or `--force` to replace it",
+        );
+    }
+    Check::ok(NAME, format!("`{}` is writable", path.display()))
+}
+
+/// Every line of exclude or include file is valid
+fn check_exclude_file(name: &str, path: &Path) -> Check {
+    let content: String = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Check::fail(
+                name,
+                format!("Could not read `{}`: {err}", path.display()),
+                "Check the path",
+            )
+        }
+    };
+
+    let errors: Vec<String> = content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            ExcludeKind::try_from(line)
+                .err()
+                .map(|err| format!("line {}: {err:#}", i + 1))
+        })
+        .collect();
+    if errors.is_empty() {
+        Check::ok(name, format!("`{}` is valid", path.display()))
+    } else {
+        Check::fail(
+            name,
+            format!("`{}` has invalid {}", path.display(), errors.join(", ")),
+            "Fix or remove the lines, see README for syntax",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_clock() {
+        let generated_at: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let at = |seconds: i64| generated_at + TimeDelta::seconds(seconds);
+
+        assert_eq!(
+            check_clock(at(60), generated_at, 3600, 300).status,
+            Status::Ok
+        );
+        assert_eq!(
+            check_clock(at(-600), generated_at, 3600, 300).status,
+            Status::Fail
+        );
+        assert_eq!(
+            check_clock(at(7200), generated_at, 3600, 300).status,
+            Status::Warn
+        );
+    }
+
+    #[test]
+    fn test_check_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mirrorlist");
+        assert_eq!(check_output(&path, false).status, Status::Ok);
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(check_output(&path, false).status, Status::Warn);
+        assert_eq!(check_output(&path, true).status, Status::Ok);
+
+        let missing = dir.path().join("missing/mirrorlist");
+        assert_eq!(check_output(&missing, true).status, Status::Fail);
+
+        // Probe file is removed
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_check_exclude_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exclude.conf");
+        fs::write(&path, "# comment\nmirror.example\ncountry_code = TH\n").unwrap();
+        assert_eq!(check_exclude_file("Exclude file", &path).status, Status::Ok);
+
+        fs::write(&path, "mirror.example\nasn = x\n").unwrap();
+        let check = check_exclude_file("Exclude file", &path);
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.detail.contains("line 2"));
+        assert!(check.to_string().starts_with("[FAIL] Exclude file: "));
+    }
+}
@@ -1,4 +1,6 @@
 use std::{
+    cell::OnceCell,
+    net::IpAddr,
     ops::{Deref, DerefMut},
     path::Path,
     sync::LazyLock,
@@ -8,7 +10,10 @@ use anyhow::{Context, Result};
 use regex::{Regex, RegexSet};
 use url::Url;
 
-use crate::mirror::Mirror;
+use crate::{
+    mirror::Mirror,
+    netinfo::{self, Cidr},
+};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ExcludeKind {
@@ -19,6 +24,10 @@ pub enum ExcludeKind {
     NegateCountry(String),
     CountryCode(String),
     NegateCountryCode(String),
+    Asn(u32),
+    NegateAsn(u32),
+    Cidr(Cidr),
+    NegateCidr(Cidr),
 }
 
 impl TryFrom<&str> for ExcludeKind {
@@ -48,6 +57,8 @@ impl TryFrom<&str> for ExcludeKind {
                 r"(?P<negate>!?)domain\s*=\s*(?P<domain>\S*)", // Domain
                 r"(?P<negate>!?)country\s*=\s*(?P<country>\S*)", // Country
                 r"(?P<negate>!?)country_code\s*=\s*(?P<country_code>\S*)", // Country Code
+                r"(?P<negate>!?)asn\s*=\s*(?:as)?(?P<asn>\S*)", // Autonomous System Number
+                r"(?P<negate>!?)cidr\s*=\s*(?P<cidr>\S*)",     // IP address range
             ])
             .expect("Create exclude regex set")
         });
@@ -63,6 +74,8 @@ impl TryFrom<&str> for ExcludeKind {
         const DOMAIN: usize = 0;
         const COUNTRY: usize = 1;
         const COUNTRY_CODE: usize = 2;
+        const ASN: usize = 3;
+        const CIDR: usize = 4;
 
         let matches = EXCLUDE_SET_RE.matches(&line);
 
@@ -89,6 +102,24 @@ impl TryFrom<&str> for ExcludeKind {
                     cap["country_code"].to_string(),
                 ));
             }
+        } else if matches.matched(ASN) {
+            let cap = EXCLUDE_CAPTURE_RE[ASN].captures(&line).unwrap();
+            let asn: u32 = cap["asn"]
+                .parse()
+                .with_context(|| format!("Invalid ASN `{}`", &cap["asn"]))?;
+            if cap["negate"].is_empty() {
+                return Ok(ExcludeKind::Asn(asn));
+            } else {
+                return Ok(ExcludeKind::NegateAsn(asn));
+            }
+        } else if matches.matched(CIDR) {
+            let cap = EXCLUDE_CAPTURE_RE[CIDR].captures(&line).unwrap();
+            let cidr: Cidr = cap["cidr"].parse()?;
+            if cap["negate"].is_empty() {
+                return Ok(ExcludeKind::Cidr(cidr));
+            } else {
+                return Ok(ExcludeKind::NegateCidr(cidr));
+            }
         }
 
         // When no keyword found, return domain as default
@@ -100,9 +131,26 @@ impl TryFrom<&str> for ExcludeKind {
     }
 }
 
+impl ExcludeKind {
+    /// Whether mirror's host must be resolved to match this pattern
+    fn needs_addresses(&self) -> bool {
+        matches!(
+            self,
+            ExcludeKind::Asn(_)
+                | ExcludeKind::NegateAsn(_)
+                | ExcludeKind::Cidr(_)
+                | ExcludeKind::NegateCidr(_)
+        )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct ExcludedMirrors(Vec<ExcludeKind>);
 
+/// Allowlist of mirrors, written in the same syntax as excluded mirrors.
+/// A mirror is included when the last pattern matching it is not negated.
+pub type IncludedMirrors = ExcludedMirrors;
+
 impl ExcludedMirrors {
     pub fn new() -> Self {
         Self(Vec::new())
@@ -126,24 +174,62 @@ impl ExcludedMirrors {
         Ok(())
     }
 
+    /// Whether any pattern matches mirrors by their resolved addresses
+    pub fn has_address_patterns(&self) -> bool {
+        self.iter().any(ExcludeKind::needs_addresses)
+    }
+
+    /// Whether any pattern matches mirrors by origin AS of their addresses
+    pub fn has_asn_patterns(&self) -> bool {
+        self.iter()
+            .any(|kind| matches!(kind, ExcludeKind::Asn(_) | ExcludeKind::NegateAsn(_)))
+    }
+
+    /// Remove patterns matching by resolved addresses, which cannot be evaluated
+    /// without network access. Returns number of removed patterns.
+    pub fn remove_address_patterns(&mut self) -> usize {
+        let total: usize = self.len();
+        self.retain(|kind| !kind.needs_addresses());
+        total - self.len()
+    }
+
     pub fn is_exclude(&self, mirror: &Mirror) -> bool {
+        self.matches(mirror)
+    }
+
+    pub fn is_include(&self, mirror: &Mirror) -> bool {
+        self.matches(mirror)
+    }
+
+    /// Returns true if the last pattern matching mirror is not negated
+    fn matches(&self, mirror: &Mirror) -> bool {
         let domain_name = Url::parse(&mirror.url)
-            .unwrap()
-            .domain()
-            .unwrap()
-            .to_lowercase();
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
         let country = mirror.country.to_lowercase();
         let country_code = mirror.country_code.to_lowercase();
 
+        // Only resolve mirror's host when an ASN or CIDR pattern is reached,
+        // usually answered from the cache filled by `FilterOptions::prefetch_addresses`
+        let addrs: OnceCell<Vec<IpAddr>> = OnceCell::new();
+        let addrs = || addrs.get_or_init(|| netinfo::resolve(&mirror.url));
+        let in_asn = |asn: &u32| addrs().iter().any(|ip| netinfo::asn(ip) == Some(*asn));
+        let in_cidr = |cidr: &Cidr| addrs().iter().any(|ip| cidr.contains(ip));
+
         for exclude_kind in self.iter().rev() {
             match exclude_kind {
                 ExcludeKind::Ignore => continue,
-                ExcludeKind::Domain(d) if d == &domain_name => return true,
-                ExcludeKind::NegateDomain(d) if d == &domain_name => return false,
+                ExcludeKind::Domain(d) if domain_matches(d, &domain_name) => return true,
+                ExcludeKind::NegateDomain(d) if domain_matches(d, &domain_name) => return false,
                 ExcludeKind::Country(c) if c == &country => return true,
                 ExcludeKind::NegateCountry(c) if c == &country => return false,
                 ExcludeKind::CountryCode(cc) if cc == &country_code => return true,
                 ExcludeKind::NegateCountryCode(cc) if cc == &country_code => return false,
+                ExcludeKind::Asn(asn) if in_asn(asn) => return true,
+                ExcludeKind::NegateAsn(asn) if in_asn(asn) => return false,
+                ExcludeKind::Cidr(cidr) if in_cidr(cidr) => return true,
+                ExcludeKind::NegateCidr(cidr) if in_cidr(cidr) => return false,
                 _ => continue,
             }
         }
@@ -152,6 +238,24 @@ impl ExcludedMirrors {
     }
 }
 
+/// Match domain name against a pattern.
+///
+/// `*.example.com` matches any subdomain of `example.com`,
+/// while `example.com` matches itself and any of its subdomains.
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(parent) => domain
+            .strip_suffix(parent)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => {
+            domain == pattern
+                || domain
+                    .strip_suffix(pattern)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        }
+    }
+}
+
 impl Deref for ExcludedMirrors {
     type Target = Vec<ExcludeKind>;
 
@@ -172,6 +276,14 @@ mod tests {
 
     use super::*;
 
+    fn mirror(url: &str, country: &str, country_code: &str) -> Mirror {
+        let mut mirror = Mirror::default();
+        mirror.url = url.to_string();
+        mirror.country = country.to_string();
+        mirror.country_code = country_code.to_string();
+        mirror
+    }
+
     #[test]
     fn test_parse_exclude_kind() {
         // Comment
@@ -243,6 +355,26 @@ mod tests {
             ExcludeKind::try_from("!ban.this.mirror").unwrap(),
             ExcludeKind::NegateDomain("ban.this.mirror".to_string())
         );
+
+        // Network
+        assert_eq!(
+            ExcludeKind::try_from("asn = 64496").unwrap(),
+            ExcludeKind::Asn(64496)
+        );
+        assert_eq!(
+            ExcludeKind::try_from("!asn = AS64496").unwrap(),
+            ExcludeKind::NegateAsn(64496)
+        );
+        assert_eq!(
+            ExcludeKind::try_from("cidr = 203.0.113.0/24").unwrap(),
+            ExcludeKind::Cidr("203.0.113.0/24".parse().unwrap())
+        );
+        assert_eq!(
+            ExcludeKind::try_from("!cidr = 2001:db8::/32").unwrap(),
+            ExcludeKind::NegateCidr("2001:db8::/32".parse().unwrap())
+        );
+        assert!(ExcludeKind::try_from("asn = example").is_err());
+        assert!(ExcludeKind::try_from("cidr = 203.0.113.0/64").is_err());
     }
 
     #[test]
@@ -307,4 +439,104 @@ mod tests {
         mirror2.country_code = "SC".to_string();
         assert!(!excluded_mirrors.is_exclude(&mirror2));
     }
+
+    #[test]
+    fn test_domain_matches() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("example.com", "mirror.example.com"));
+        assert!(domain_matches("example.com", "a.b.example.com"));
+        assert!(!domain_matches("example.com", "badexample.com"));
+        assert!(!domain_matches("mirror.example.com", "example.com"));
+
+        assert!(domain_matches("*.example.com", "mirror.example.com"));
+        assert!(domain_matches("*.example.com", "a.b.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn test_exclude_subdomains() {
+        let mut excluded_mirrors = ExcludedMirrors::new();
+        excluded_mirrors.add(ExcludeKind::try_from("domain = *.example.com").unwrap());
+        excluded_mirrors.add(ExcludeKind::try_from("!keep.example.com").unwrap());
+        excluded_mirrors.add(ExcludeKind::try_from("example.org").unwrap());
+
+        assert!(excluded_mirrors.is_exclude(&mirror("https://mirror.example.com/arch/", "", "")));
+        assert!(!excluded_mirrors.is_exclude(&mirror("https://example.com/arch/", "", "")));
+        assert!(!excluded_mirrors.is_exclude(&mirror("https://keep.example.com/arch/", "", "")));
+        assert!(excluded_mirrors.is_exclude(&mirror("https://example.org/arch/", "", "")));
+        assert!(excluded_mirrors.is_exclude(&mirror("https://mirror.example.org/arch/", "", "")));
+    }
+
+    #[test]
+    fn test_exclude_cidr() {
+        let mut excluded_mirrors = ExcludedMirrors::new();
+        excluded_mirrors.add(ExcludeKind::try_from("cidr = 203.0.113.0/24").unwrap());
+        excluded_mirrors.add(ExcludeKind::try_from("!cidr = 203.0.113.128/25").unwrap());
+
+        assert!(excluded_mirrors.is_exclude(&mirror("https://203.0.113.7/arch/", "", "")));
+        assert!(!excluded_mirrors.is_exclude(&mirror("https://203.0.113.200/arch/", "", "")));
+        assert!(!excluded_mirrors.is_exclude(&mirror("https://198.51.100.7/arch/", "", "")));
+    }
+
+    #[test]
+    fn test_remove_address_patterns() {
+        let mut excluded_mirrors = ExcludedMirrors::new();
+        excluded_mirrors.add(ExcludeKind::try_from("example.org").unwrap());
+        assert!(!excluded_mirrors.has_address_patterns());
+
+        excluded_mirrors.add(ExcludeKind::try_from("cidr = 203.0.113.0/24").unwrap());
+        assert!(excluded_mirrors.has_address_patterns());
+        assert!(!excluded_mirrors.has_asn_patterns());
+
+        excluded_mirrors.add(ExcludeKind::try_from("!asn = 64496").unwrap());
+        assert!(excluded_mirrors.has_asn_patterns());
+
+        assert_eq!(excluded_mirrors.remove_address_patterns(), 2);
+        assert_eq!(
+            *excluded_mirrors.deref(),
+            vec![ExcludeKind::Domain("example.org".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_is_include() {
+        let mut included_mirrors = IncludedMirrors::new();
+        included_mirrors
+            .add_from(Path::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/",
+                "tests/",
+                "included_mirrors"
+            )))
+            .unwrap();
+
+        // Allowed by domain
+        assert!(included_mirrors.is_include(&mirror(
+            "https://mirror.corp.example/archlinux/",
+            "Elsewhere",
+            "EW"
+        )));
+
+        // Allowed by country
+        assert!(included_mirrors.is_include(&mirror(
+            "https://mirror1.in.somecountry/",
+            "SomeCountry",
+            "SC"
+        )));
+
+        // Allowed country, but negated domain
+        assert!(!included_mirrors.is_include(&mirror(
+            "https://mirror2.in.somecountry/",
+            "SomeCountry",
+            "SC"
+        )));
+
+        // Not listed
+        assert!(!included_mirrors.is_include(&mirror(
+            "https://mirror.in.othercountry/",
+            "OtherCountry",
+            "OC"
+        )));
+    }
 }
@@ -0,0 +1,75 @@
+use std::{fmt, process::ExitCode};
+
+/// Class of failure with its own exit code, attached to an error as context.
+/// Other errors exit with 1, and invalid arguments with 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// No source of mirrors status works
+    StatusFetch,
+
+    /// No mirror is left after filtering
+    NoMirrors,
+
+    /// Every mirror failed benchmark
+    NoBenchmarkResults,
+
+    /// Mirror list, stats, or report cannot be written
+    OutputWrite,
+}
+
+impl Failure {
+    pub fn code(&self) -> u8 {
+        match self {
+            Failure::StatusFetch => 3,
+            Failure::NoMirrors => 4,
+            Failure::NoBenchmarkResults => 5,
+            Failure::OutputWrite => 6,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::StatusFetch => "Failed to fetch mirrors status",
+            Failure::NoMirrors => "No mirrors left after filtering",
+            Failure::NoBenchmarkResults => "No mirror could be benchmarked",
+            Failure::OutputWrite => "Failed to write output",
+        })
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code of an error, from the failure attached to it
+pub fn code(err: &anyhow::Error) -> ExitCode {
+    match err.downcast_ref::<Failure>() {
+        Some(failure) => ExitCode::from(failure.code()),
+        None => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, bail, Result};
+
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let err = anyhow!("connection refused")
+            .context(Failure::StatusFetch)
+            .context("Update failed");
+        assert_eq!(err.downcast_ref::<Failure>(), Some(&Failure::StatusFetch));
+        assert_eq!(code(&err), ExitCode::from(3));
+        assert_eq!(
+            format!("{err:#}"),
+            "Update failed: Failed to fetch mirrors status: connection refused"
+        );
+
+        let no_results = || -> Result<()> { bail!(Failure::NoBenchmarkResults) };
+        assert_eq!(code(&no_results().unwrap_err()), ExitCode::from(5));
+
+        assert_eq!(code(&anyhow!("other")), ExitCode::FAILURE);
+    }
+}
@@ -0,0 +1,189 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+/// Mean radius of the Earth in kilometers
+const EARTH_RADIUS: f64 = 6371.0;
+
+/// Approximate location of countries hosting mirrors, by ISO 3166-1 alpha-2 code.
+/// Mirrors status only gives a country, so distance is measured between countries.
+const COUNTRIES: &[(&str, f64, f64)] = &[
+    ("AE", 24.4, 54.4),
+    ("AL", 41.3, 19.8),
+    ("AM", 40.2, 44.5),
+    ("AR", -34.6, -58.4),
+    ("AT", 48.2, 16.4),
+    ("AU", -33.9, 151.2),
+    ("AZ", 40.4, 49.9),
+    ("BA", 43.9, 18.4),
+    ("BD", 23.8, 90.4),
+    ("BE", 50.8, 4.4),
+    ("BG", 42.7, 23.3),
+    ("BR", -23.5, -46.6),
+    ("BY", 53.9, 27.6),
+    ("CA", 43.7, -79.4),
+    ("CH", 47.4, 8.5),
+    ("CL", -33.4, -70.7),
+    ("CN", 31.2, 121.5),
+    ("CO", 4.7, -74.1),
+    ("CR", 9.9, -84.1),
+    ("CY", 35.2, 33.4),
+    ("CZ", 50.1, 14.4),
+    ("DE", 50.1, 8.7),
+    ("DK", 55.7, 12.6),
+    ("EC", -0.2, -78.5),
+    ("EE", 59.4, 24.7),
+    ("EG", 30.0, 31.2),
+    ("ES", 40.4, -3.7),
+    ("FI", 60.2, 24.9),
+    ("FR", 48.9, 2.4),
+    ("GB", 51.5, -0.1),
+    ("GE", 41.7, 44.8),
+    ("GR", 38.0, 23.7),
+    ("HK", 22.3, 114.2),
+    ("HR", 45.8, 16.0),
+    ("HU", 47.5, 19.0),
+    ("ID", -6.2, 106.8),
+    ("IE", 53.3, -6.3),
+    ("IL", 32.1, 34.8),
+    ("IN", 19.1, 72.9),
+    ("IR", 35.7, 51.4),
+    ("IS", 64.1, -21.9),
+    ("IT", 45.5, 9.2),
+    ("JP", 35.7, 139.7),
+    ("KE", -1.3, 36.8),
+    ("KG", 42.9, 74.6),
+    ("KH", 11.6, 104.9),
+    ("KR", 37.6, 127.0),
+    ("KZ", 43.2, 76.9),
+    ("LK", 6.9, 79.9),
+    ("LT", 54.7, 25.3),
+    ("LU", 49.6, 6.1),
+    ("LV", 56.9, 24.1),
+    ("MA", 33.6, -7.6),
+    ("MC", 43.7, 7.4),
+    ("MD", 47.0, 28.9),
+    ("MK", 42.0, 21.4),
+    ("MN", 47.9, 106.9),
+    ("MU", -20.2, 57.5),
+    ("MX", 19.4, -99.1),
+    ("MY", 3.1, 101.7),
+    ("NC", -22.3, 166.5),
+    ("NL", 52.4, 4.9),
+    ("NO", 59.9, 10.8),
+    ("NP", 27.7, 85.3),
+    ("NZ", -36.8, 174.8),
+    ("PE", -12.0, -77.0),
+    ("PH", 14.6, 121.0),
+    ("PK", 24.9, 67.0),
+    ("PL", 52.2, 21.0),
+    ("PT", 38.7, -9.1),
+    ("PY", -25.3, -57.6),
+    ("QA", 25.3, 51.5),
+    ("RE", -20.9, 55.5),
+    ("RO", 44.4, 26.1),
+    ("RS", 44.8, 20.5),
+    ("RU", 55.8, 37.6),
+    ("SA", 24.7, 46.7),
+    ("SE", 59.3, 18.1),
+    ("SG", 1.3, 103.8),
+    ("SI", 46.1, 14.5),
+    ("SK", 48.1, 17.1),
+    ("TH", 13.8, 100.5),
+    ("TR", 41.0, 29.0),
+    ("TW", 25.0, 121.5),
+    ("UA", 50.5, 30.5),
+    ("US", 39.8, -98.6),
+    ("UY", -34.9, -56.2),
+    ("UZ", 41.3, 69.2),
+    ("VN", 10.8, 106.7),
+    ("ZA", -26.2, 28.0),
+];
+
+/// Geographic position in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Approximate location of a country
+    pub fn of_country(country_code: &str) -> Option<Self> {
+        COUNTRIES
+            .iter()
+            .find(|(cc, _, _)| cc.eq_ignore_ascii_case(country_code))
+            .map(|&(_, latitude, longitude)| Self {
+                latitude,
+                longitude,
+            })
+    }
+
+    /// Great-circle distance in kilometers
+    pub fn distance(&self, other: &Coordinates) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+}
+
+impl FromStr for Coordinates {
+    type Err = anyhow::Error;
+
+    /// Parse `LAT,LON` in degrees, or a country code
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((latitude, longitude)) = s.split_once(',') else {
+            return Self::of_country(s.trim())
+                .with_context(|| format!("Unknown country code `{s}`"));
+        };
+
+        let latitude: f64 = latitude
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid latitude in `{s}`"))?;
+        let longitude: f64 = longitude
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid longitude in `{s}`"))?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            bail!("Coordinates `{s}` are out of range");
+        }
+        Ok(Self {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_coordinates() {
+        assert_eq!(
+            Coordinates::from_str("13.75, 100.5").unwrap(),
+            Coordinates {
+                latitude: 13.75,
+                longitude: 100.5
+            }
+        );
+        assert_eq!(
+            Coordinates::from_str("de").unwrap(),
+            Coordinates::of_country("DE").unwrap()
+        );
+        assert!(Coordinates::from_str("91,0").is_err());
+        assert!(Coordinates::from_str("XX").is_err());
+    }
+
+    #[test]
+    fn test_distance() {
+        let paris = Coordinates::of_country("FR").unwrap();
+        let london = Coordinates::of_country("GB").unwrap();
+        let d = paris.distance(&london);
+        assert!((330.0..360.0).contains(&d), "distance = {d}");
+        assert_eq!(paris.distance(&paris), 0.0);
+    }
+}
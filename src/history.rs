@@ -0,0 +1,183 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+
+use crate::mirror::Mirrors;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    target_db TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS measurements (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    url TEXT NOT NULL,
+    country_code TEXT NOT NULL,
+    score REAL,
+    delay INTEGER,
+    age INTEGER,
+    transfer_rate REAL,
+    transfer_rate_stddev REAL,
+    dns_time REAL,
+    connect_time REAL,
+    tls_time REAL,
+    ttfb REAL,
+    transfer_time REAL
+);
+
+CREATE INDEX IF NOT EXISTS measurements_url ON measurements(url);
+";
+
+/// History of benchmark runs stored in SQLite database
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Open history database, creating it and its directory if they do not exist
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Could not create directory `{}`", dir.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Could not open history database `{}`", path.display()))?;
+        Self::init(conn)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("Could not create history database schema")?;
+        Ok(Self { conn })
+    }
+
+    /// Append measurements of all mirrors of a run, returns id of the run
+    pub fn record(
+        &mut self,
+        time: DateTime<Utc>,
+        target_db: &str,
+        mirrors: &Mirrors,
+    ) -> Result<i64> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO runs (time, target_db) VALUES (?1, ?2)",
+            params![time.to_rfc3339(), target_db],
+        )?;
+        let run_id: i64 = tx.last_insert_rowid();
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO measurements (
+                    run_id, url, country_code, score, delay, age,
+                    transfer_rate, transfer_rate_stddev,
+                    dns_time, connect_time, tls_time, ttfb, transfer_time
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )?;
+            for m in mirrors.iter() {
+                stmt.execute(params![
+                    run_id,
+                    m.url,
+                    m.country_code,
+                    m.score,
+                    m.delay,
+                    m.age,
+                    m.transfer_rate,
+                    m.transfer_rate_stddev,
+                    m.dns_time,
+                    m.connect_time,
+                    m.tls_time,
+                    m.ttfb,
+                    m.transfer_time,
+                ])?;
+            }
+        }
+
+        tx.commit()
+            .context("Could not save run to history database")?;
+        Ok(run_id)
+    }
+
+    /// Transfer rate of every mirror of every run, oldest run first
+    pub fn transfer_rates(&self) -> Result<Vec<(String, Option<f64>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url, transfer_rate FROM measurements ORDER BY run_id")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, Option<f64>)>>>()
+            .context("Could not read history database")?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror::{Layout, Mirror, TargetDb};
+
+    #[test]
+    fn test_record() {
+        let mut history = History::init(Connection::open_in_memory().unwrap()).unwrap();
+        let mirrors: Mirrors = [
+            Mirror::with_transfer_rate("https://a.example/", Some(1000.0)),
+            Mirror::from_url("https://b.example/"),
+        ]
+        .into_iter()
+        .collect();
+
+        let first = history
+            .record(
+                Utc::now(),
+                &TargetDb::Extra.path(&Layout::default()),
+                &mirrors,
+            )
+            .unwrap();
+        let second = history
+            .record(
+                Utc::now(),
+                &TargetDb::Extra.path(&Layout::default()),
+                &mirrors,
+            )
+            .unwrap();
+        assert_ne!(first, second);
+        let runs: i64 = history
+            .conn
+            .query_row("SELECT COUNT(*) FROM runs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(runs, 2);
+
+        let rate: Option<f64> = history
+            .conn
+            .query_row(
+                "SELECT transfer_rate FROM measurements WHERE run_id = ?1 AND url = ?2",
+                params![second, "https://a.example/"],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rate, Some(1000.0));
+
+        let rate: Option<f64> = history
+            .conn
+            .query_row(
+                "SELECT transfer_rate FROM measurements WHERE run_id = ?1 AND url = ?2",
+                params![second, "https://b.example/"],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rate, None);
+
+        assert_eq!(
+            history.transfer_rates().unwrap(),
+            vec![
+                ("https://a.example/".to_string(), Some(1000.0)),
+                ("https://b.example/".to_string(), None),
+                ("https://a.example/".to_string(), Some(1000.0)),
+                ("https://b.example/".to_string(), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::{fmt::Write, path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use tracing::info;
+
+use crate::{
+    args::InstallHookArgs,
+    cmdline,
+    output::{self, Overwrite},
+};
+
+const HOOK_NAME: &str = "pacman-mirrorup.hook";
+
+/// Options of the shipped service, used when none is given
+const DEFAULT_ARGS: &str = "--output-file /etc/pacman.d/mirrorlist --in-place";
+
+/// Package operation triggering the hook
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Operation {
+    Install,
+    Upgrade,
+    Remove,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Install => "Install",
+            Operation::Upgrade => "Upgrade",
+            Operation::Remove => "Remove",
+        }
+    }
+}
+
+/// Run `install-hook` subcommand, `mirrorup_args` are options of the ranked refresh
+pub fn run(args: &InstallHookArgs, mirrorup_args: &[String], force: bool) -> Result<()> {
+    let path: PathBuf = args.dir.join(HOOK_NAME);
+    let overwrite: Overwrite = if force {
+        Overwrite::Always
+    } else {
+        if path.exists() {
+            bail!("`{}` is exist.", path.display());
+        }
+        Overwrite::Never
+    };
+
+    let hook: String = hook(&args.trigger, &args.operation, args.timeout, mirrorup_args)?;
+    output::save(&path, hook.as_bytes(), overwrite)
+        .with_context(|| format!("Could not write hook `{}`", path.display()))?;
+
+    if !output::is_dry_run() {
+        info!(
+            "Mirror list is refreshed after next {} of {}",
+            args.operation
+                .iter()
+                .map(Operation::as_str)
+                .collect::<Vec<&str>>()
+                .join("/")
+                .to_lowercase(),
+            args.trigger.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Hook running pacman-mirrorup with `mirrorup_args` after transaction touching `targets`,
+/// killed after `timeout` so that it never blocks pacman
+fn hook(
+    targets: &[String],
+    operations: &[Operation],
+    timeout: Duration,
+    mirrorup_args: &[String],
+) -> Result<String> {
+    if timeout.is_zero() {
+        bail!("Timeout of hook must be greater than zero");
+    }
+    for target in targets {
+        if target.is_empty() || target.contains(char::is_whitespace) {
+            bail!("Invalid trigger target `{target}`");
+        }
+    }
+
+    let exec_args: String = if mirrorup_args.is_empty() {
+        DEFAULT_ARGS.to_string()
+    } else {
+        // pacman splits `Exec` on whitespace
+        cmdline::join(mirrorup_args, "pacman hook")?
+    };
+
+    let mut hook = String::from("[Trigger]\n");
+    for operation in operations {
+        let _ = writeln!(hook, "Operation = {}", operation.as_str());
+    }
+    hook.push_str("Type = Package\n");
+    for target in targets {
+        let _ = writeln!(hook, "Target = {target}");
+    }
+    let _ = write!(
+        hook,
+        "\n\
+        [Action]\n\
+        Description = Ranking pacman mirrors...\n\
+        When = PostTransaction\n\
+        Depends = pacman-mirrorup\n\
+        Exec = /usr/bin/timeout {}s /usr/bin/pacman-mirrorup {exec_args}\n",
+        timeout.as_secs().max(1)
+    );
+    Ok(hook)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook() {
+        let hook: String = hook(
+            &["pacman-mirrorlist".to_string()],
+            &[Operation::Upgrade],
+            Duration::from_secs(300),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            hook,
+            "\
+            [Trigger]\n\
+            Operation = Upgrade\n\
+            Type = Package\n\
+            Target = pacman-mirrorlist\n\
+            \n\
+            [Action]\n\
+            Description = Ranking pacman mirrors...\n\
+            When = PostTransaction\n\
+            Depends = pacman-mirrorup\n\
+            Exec = /usr/bin/timeout 300s /usr/bin/pacman-mirrorup --output-file /etc/pacman.d/mirrorlist --in-place\n\
+            "
+        );
+    }
+
+    #[test]
+    fn test_hook_options() {
+        let args: Vec<String> = ["--output-file", "/etc/pacman.d/mirrorlist", "--backup"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let hook: String = hook(
+            &["pacman-mirrorlist".to_string(), "pacman".to_string()],
+            &[Operation::Install, Operation::Upgrade],
+            Duration::from_secs(60),
+            &args,
+        )
+        .unwrap();
+        assert!(hook.contains("Operation = Install\nOperation = Upgrade\n"));
+        assert!(hook.contains("Target = pacman-mirrorlist\nTarget = pacman\n"));
+        assert!(hook.ends_with(
+            "Exec = /usr/bin/timeout 60s /usr/bin/pacman-mirrorup --output-file /etc/pacman.d/mirrorlist --backup\n"
+        ));
+
+        assert!(super::hook(&[], &[], Duration::ZERO, &[]).is_err());
+        assert!(super::hook(&[], &[], Duration::from_secs(1), &["a b".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,640 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    io::{self, Read},
+    net::{SocketAddr, ToSocketAddrs},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, WebPkiSupportedAlgorithms},
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use tracing::debug;
+use ureq::{ReadWrite, TlsConnector};
+
+pub static APP_USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("CARGO_PKG_HOMEPAGE"),
+    ")"
+);
+
+/// How long resolved addresses are reused
+const DNS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// How long resolved addresses are reused by an agent living across cycles of `interval`.
+///
+/// Addresses expire before the next cycle, so a long-running daemon does not keep
+/// connecting to addresses mirrors have moved away from.
+pub fn dns_cache_ttl(interval: Duration) -> Duration {
+    DNS_CACHE_TTL.min(interval)
+}
+
+/// Maximum number of idle keep-alive connections per host
+const MAX_IDLE_CONNECTIONS_PER_HOST: usize = 2;
+
+/// Create HTTP agent shared by all requests.
+///
+/// The agent should be created once and reused, so its keep-alive
+/// connections and DNS cache survive across fetches and benchmarks.
+pub fn agent(max_redirects: u32) -> ureq::Agent {
+    agent_with_family(max_redirects, None)
+}
+
+/// Create HTTP agent connecting over `family` only, or over any family if None
+pub fn agent_with_family(max_redirects: u32, family: Option<IpFamily>) -> ureq::Agent {
+    agent_with_dns_ttl(max_redirects, family, DNS_CACHE_TTL)
+}
+
+/// Create HTTP agent reusing resolved addresses for `dns_ttl`, see [`dns_cache_ttl`]
+pub fn agent_with_dns_ttl(
+    max_redirects: u32,
+    family: Option<IpFamily>,
+    dns_ttl: Duration,
+) -> ureq::Agent {
+    let builder = ureq::AgentBuilder::new()
+        .redirects(max_redirects)
+        .max_idle_connections_per_host(MAX_IDLE_CONNECTIONS_PER_HOST)
+        .resolver(CachingResolver::with_ttl(family, dns_ttl))
+        .tls_connector(Arc::new(TimedTlsConnector::new(tls_config())));
+    with_headers(
+        builder,
+        USER_AGENT.get().map_or(APP_USER_AGENT, String::as_str),
+        EXTRA_HEADERS.get().map_or(&[], Vec::as_slice),
+    )
+    .build()
+}
+
+/// Send `user_agent` and `headers` in requests of agent built by `builder`
+fn with_headers(
+    builder: ureq::AgentBuilder,
+    user_agent: &str,
+    headers: &'static [(String, String)],
+) -> ureq::AgentBuilder {
+    let builder = builder.user_agent(user_agent);
+    if headers.is_empty() {
+        return builder;
+    }
+    builder.middleware(ExtraHeaders(headers))
+}
+
+/// User-Agent replacing `APP_USER_AGENT`
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+/// Headers added to every request
+static EXTRA_HEADERS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Middleware adding headers to each request
+struct ExtraHeaders(&'static [(String, String)]);
+
+impl ureq::Middleware for ExtraHeaders {
+    fn handle(
+        &self,
+        request: ureq::Request,
+        next: ureq::MiddlewareNext,
+    ) -> Result<ureq::Response, ureq::Error> {
+        next.handle(
+            self.0
+                .iter()
+                .fold(request, |request, (name, value)| request.set(name, value)),
+        )
+    }
+}
+
+/// Send `user_agent` and `headers` in requests of agents created afterwards
+pub fn set_headers(user_agent: Option<String>, headers: Vec<(String, String)>) -> Result<()> {
+    if let Some(user_agent) = user_agent {
+        if USER_AGENT.set(user_agent).is_err() {
+            bail!("User-Agent is already set");
+        }
+    }
+    if EXTRA_HEADERS.set(headers).is_err() {
+        bail!("Request headers are already set");
+    }
+    Ok(())
+}
+
+/// CA certificates trusted in addition to bundled roots
+static EXTRA_ROOTS: OnceLock<Vec<CertificateDer<'static>>> = OnceLock::new();
+
+/// Trust CA certificates of PEM file at `path` in TLS configurations created afterwards,
+/// returns number of certificates
+pub fn add_ca_certs(path: &Path) -> Result<usize> {
+    let certs: Vec<CertificateDer<'static>> = read_ca_certs(path)?;
+    let count: usize = certs.len();
+    if EXTRA_ROOTS.set(certs).is_err() {
+        bail!("CA certificates are already added");
+    }
+    Ok(count)
+}
+
+/// Read and validate CA certificates of PEM file at `path`
+fn read_ca_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect())
+        .with_context(|| format!("Failed to read CA certificates `{}`", path.display()))?;
+    if certs.is_empty() {
+        bail!("No CA certificate in `{}`", path.display());
+    }
+
+    let mut root_store = rustls::RootCertStore::empty();
+    for cert in &certs {
+        root_store
+            .add(cert.clone())
+            .with_context(|| format!("Invalid CA certificate in `{}`", path.display()))?;
+    }
+    Ok(certs)
+}
+
+/// Whether certificates are accepted without verification
+static INSECURE: AtomicBool = AtomicBool::new(false);
+
+/// Accept any certificate in TLS configurations created afterwards.
+///
+/// Only for networks whose middleboxes break certificate chains;
+/// mirrors status and benchmark can then be spoofed by anyone on the path.
+pub fn set_insecure(insecure: bool) {
+    INSECURE.store(insecure, Ordering::Relaxed);
+}
+
+pub fn is_insecure() -> bool {
+    INSECURE.load(Ordering::Relaxed)
+}
+
+/// Verifier accepting any certificate, only handshake signatures are still checked
+#[derive(Debug)]
+struct NoCertificateVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// TLS configuration equivalent to ureq's default, trusting CA certificates of `add_ca_certs`,
+/// or any certificate after `set_insecure`
+pub fn tls_config() -> Arc<rustls::ClientConfig> {
+    let root_store = root_store(EXTRA_ROOTS.get().map_or(&[], Vec::as_slice));
+    let provider: CryptoProvider = rustls::crypto::ring::default_provider();
+    let algorithms: WebPkiSupportedAlgorithms = provider.signature_verification_algorithms;
+    let mut config = rustls::ClientConfig::builder_with_provider(provider.into())
+        .with_protocol_versions(&[&rustls::version::TLS12, &rustls::version::TLS13])
+        .expect("ring provider supports TLS 1.2 and 1.3")
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    if is_insecure() {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoCertificateVerification(algorithms)));
+    }
+    Arc::new(config)
+}
+
+/// Bundled roots and `extra_roots`
+fn root_store(extra_roots: &[CertificateDer<'static>]) -> rustls::RootCertStore {
+    let mut root_store = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    for cert in extra_roots {
+        // Validated by `read_ca_certs`
+        let _ = root_store.add(cert.clone());
+    }
+    root_store
+}
+
+/// Duration of each phase of a new connection.
+///
+/// Phases are recorded per thread, since each request runs entirely on the
+/// calling thread. A phase is None when it did not happen, e.g. when a pooled
+/// keep-alive connection is reused or plain HTTP is used (no TLS handshake).
+/// With redirects, the phases of the last new connection are kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ConnectionTimings {
+    pub dns: Option<Duration>,
+
+    /// Addresses were taken from DNS cache, `dns` is not the time of a lookup
+    pub dns_cached: bool,
+
+    /// TCP connect of the connection carrying the request.
+    ///
+    /// ureq has no hook around its TCP connect, so it is timed from the end of
+    /// DNS resolution until the TLS handshake starts, and is None for plain HTTP.
+    pub connect: Option<Duration>,
+
+    pub tls: Option<Duration>,
+
+    /// When DNS resolution ended, i.e. ureq started to connect
+    resolved_at: Option<Instant>,
+
+    /// When TLS handshake ended
+    handshake_done_at: Option<Instant>,
+}
+
+impl ConnectionTimings {
+    /// When the request could be written on the new connection, after TLS handshake,
+    /// or after DNS resolution for plain HTTP. None if no new connection was made.
+    pub fn request_start(&self) -> Option<Instant> {
+        self.handshake_done_at.or(self.resolved_at)
+    }
+}
+
+thread_local! {
+    static TIMINGS: RefCell<Option<ConnectionTimings>> = const { RefCell::new(None) };
+}
+
+/// Start recording connection timings of current thread before a request
+pub fn start_timings() {
+    TIMINGS.with(|t| *t.borrow_mut() = Some(ConnectionTimings::default()));
+}
+
+/// Connection timings recorded on current thread since [`start_timings`], and stop recording
+pub fn take_timings() -> ConnectionTimings {
+    TIMINGS.with(|t| t.borrow_mut().take().unwrap_or_default())
+}
+
+/// Update connection timings of current thread, if they are being recorded
+fn record_timings(f: impl FnOnce(&mut ConnectionTimings)) {
+    TIMINGS.with(|t| {
+        if let Some(timings) = t.borrow_mut().as_mut() {
+            f(timings);
+        }
+    });
+}
+
+thread_local! {
+    static LAST_REQUEST: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Wait until `delay` has passed since the previous request of current thread.
+///
+/// First requests of rayon threads are spread evenly over `delay`,
+/// so that all threads do not start at once.
+pub fn pace(delay: Duration) {
+    if delay.is_zero() {
+        return;
+    }
+
+    LAST_REQUEST.with(|last| {
+        let wait: Duration = match last.get() {
+            Some(last) => delay.saturating_sub(last.elapsed()),
+            None => {
+                let index: usize = rayon::current_thread_index().unwrap_or(0);
+                let threads: usize = rayon::current_num_threads().max(1);
+                delay.mul_f64(index as f64 / threads as f64)
+            }
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+        last.set(Some(Instant::now()));
+    });
+}
+
+/// Bytes of response bodies read since [`take_downloaded`]
+static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes of response bodies read since the previous call
+pub fn take_downloaded() -> u64 {
+    DOWNLOADED.swap(0, Ordering::Relaxed)
+}
+
+/// Response body reader counting downloaded bytes
+pub struct Counted<R>(pub R);
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n: usize = self.0.read(buf)?;
+        DOWNLOADED.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// TLS connector recording TLS handshake time
+#[derive(Debug)]
+pub struct TimedTlsConnector {
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl TimedTlsConnector {
+    pub fn new(config: Arc<rustls::ClientConfig>) -> Self {
+        Self { config }
+    }
+}
+
+impl TlsConnector for TimedTlsConnector {
+    fn connect(
+        &self,
+        dns_name: &str,
+        io: Box<dyn ReadWrite>,
+    ) -> Result<Box<dyn ReadWrite>, ureq::Error> {
+        let start = Instant::now();
+        record_timings(|t| t.connect = t.resolved_at.map(|resolved_at| start - resolved_at));
+        let stream = self.config.connect(dns_name, io)?;
+
+        let done = Instant::now();
+        record_timings(|t| {
+            t.tls = Some(done - start);
+            t.handshake_done_at = Some(done);
+        });
+        Ok(stream)
+    }
+}
+
+/// IP address family
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum IpFamily {
+    #[value(name = "4")]
+    V4,
+
+    #[value(name = "6")]
+    V6,
+}
+
+impl IpFamily {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// DNS resolver that caches resolved addresses for `ttl` (`DNS_CACHE_TTL` by default),
+/// returning only addresses of `family` if any
+#[derive(Debug)]
+pub struct CachingResolver {
+    cache: Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>,
+    family: Option<IpFamily>,
+    ttl: Duration,
+}
+
+impl Default for CachingResolver {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl CachingResolver {
+    pub fn new(family: Option<IpFamily>) -> Self {
+        Self::with_ttl(family, DNS_CACHE_TTL)
+    }
+
+    pub fn with_ttl(family: Option<IpFamily>, ttl: Duration) -> Self {
+        Self {
+            cache: Mutex::default(),
+            family,
+            ttl,
+        }
+    }
+
+    fn lookup(&self, netloc: &str) -> Option<Vec<SocketAddr>> {
+        let cache = self.cache.lock().ok()?;
+        let (resolved_at, addrs) = cache.get(netloc)?;
+        if resolved_at.elapsed() > self.ttl {
+            return None;
+        }
+        Some(addrs.clone())
+    }
+}
+
+impl CachingResolver {
+    /// Resolved addresses, and whether they are taken from cache
+    fn resolve_cached(&self, netloc: &str) -> io::Result<(Vec<SocketAddr>, bool)> {
+        if let Some(addrs) = self.lookup(netloc) {
+            debug!("DNS cache hit: {netloc}");
+            return Ok((addrs, true));
+        }
+
+        let addrs: Vec<SocketAddr> = netloc.to_socket_addrs()?.collect();
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(netloc.to_string(), (Instant::now(), addrs.clone()));
+        }
+        Ok((addrs, false))
+    }
+}
+
+impl ureq::Resolver for CachingResolver {
+    fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
+        let start = Instant::now();
+        let (mut addrs, cached) = self.resolve_cached(netloc)?;
+        if let Some(family) = self.family {
+            addrs.retain(|addr| family.matches(addr));
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No {} address of `{netloc}`", family.name()),
+                ));
+            }
+        }
+        record_timings(|t| {
+            *t = ConnectionTimings {
+                dns: Some(start.elapsed()),
+                dns_cached: cached,
+                resolved_at: Some(Instant::now()),
+                ..ConnectionTimings::default()
+            }
+        });
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ureq::Resolver;
+
+    use super::*;
+
+    #[test]
+    fn test_pace() {
+        let delay = Duration::from_millis(50);
+        let start = Instant::now();
+        pace(delay);
+        pace(delay);
+        pace(delay);
+        assert!(start.elapsed() >= delay * 2);
+
+        // No delay
+        let start = Instant::now();
+        pace(Duration::ZERO);
+        assert!(start.elapsed() < delay);
+    }
+
+    #[test]
+    fn test_read_ca_certs() {
+        assert!(read_ca_certs(Path::new("/nonexistent/ca.pem")).is_err());
+
+        let path: &Path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/ca_cert.pem"));
+        let certs: Vec<CertificateDer<'static>> = read_ca_certs(path).unwrap();
+        assert_eq!(certs.len(), 1);
+        assert_eq!(
+            root_store(&certs).len(),
+            webpki_roots::TLS_SERVER_ROOTS.len() + 1
+        );
+    }
+
+    #[test]
+    fn test_with_headers() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request: Vec<String> = BufReader::new(&stream)
+                .lines()
+                .map_while(Result::ok)
+                .take_while(|line| !line.is_empty())
+                .collect();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request
+        });
+
+        let headers = vec![("X-Requested-By".to_string(), "campus".to_string())].leak();
+        with_headers(ureq::AgentBuilder::new(), "curl/8.0.0", headers)
+            .build()
+            .get(&format!("http://{addr}/"))
+            .call()
+            .unwrap();
+
+        let request: Vec<String> = server.join().unwrap();
+        assert!(request.contains(&"User-Agent: curl/8.0.0".to_string()));
+        assert!(request.contains(&"X-Requested-By: campus".to_string()));
+    }
+
+    #[test]
+    fn test_no_certificate_verification() {
+        let provider: CryptoProvider = rustls::crypto::ring::default_provider();
+        let verifier = NoCertificateVerification(provider.signature_verification_algorithms);
+        let cert = CertificateDer::from(b"not a certificate".to_vec());
+        let name = ServerName::try_from("mirror.example.org").unwrap();
+        assert!(verifier
+            .verify_server_cert(&cert, &[], &name, &[], UnixTime::now())
+            .is_ok());
+        assert!(!verifier.supported_verify_schemes().is_empty());
+    }
+
+    #[test]
+    fn test_caching_resolver() {
+        let resolver = CachingResolver::default();
+        assert_eq!(resolver.lookup("127.0.0.1:80"), None);
+
+        let addrs = resolver.resolve("127.0.0.1:80").unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+        assert_eq!(resolver.lookup("127.0.0.1:80"), Some(addrs));
+    }
+
+    #[test]
+    fn test_caching_resolver_family() {
+        let v4 = CachingResolver::new(Some(IpFamily::V4));
+        assert_eq!(
+            v4.resolve("127.0.0.1:80").unwrap(),
+            vec!["127.0.0.1:80".parse().unwrap()]
+        );
+        assert!(v4.resolve("[::1]:80").is_err());
+
+        let v6 = CachingResolver::new(Some(IpFamily::V6));
+        assert!(v6.resolve("127.0.0.1:80").is_err());
+        assert_eq!(
+            v6.resolve("[::1]:80").unwrap(),
+            vec!["[::1]:80".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_resolver_records_timings() {
+        let resolver = CachingResolver::default();
+
+        start_timings();
+        resolver.resolve("127.0.0.1:80").unwrap();
+        let timings = take_timings();
+        assert!(timings.dns.is_some());
+        assert!(!timings.dns_cached);
+        assert!(timings.resolved_at.is_some());
+        assert_eq!(timings.connect, None);
+        assert_eq!(timings.tls, None);
+        assert_eq!(timings.request_start(), timings.resolved_at);
+
+        // Addresses of cache
+        start_timings();
+        resolver.resolve("127.0.0.1:80").unwrap();
+        assert!(take_timings().dns_cached);
+
+        // Timings are not recorded once taken
+        resolver.resolve("127.0.0.1:80").unwrap();
+        assert_eq!(take_timings(), ConnectionTimings::default());
+    }
+
+    #[test]
+    fn test_expired_cache_entry() {
+        let Some(resolved_at) = Instant::now().checked_sub(DNS_CACHE_TTL + Duration::from_secs(1))
+        else {
+            return;
+        };
+        let resolver = CachingResolver::default();
+        resolver.cache.lock().unwrap().insert(
+            "127.0.0.1:80".to_string(),
+            (resolved_at, vec!["127.0.0.1:80".parse().unwrap()]),
+        );
+        assert_eq!(resolver.lookup("127.0.0.1:80"), None);
+
+        // Shorter TTL of daemon agent
+        let resolver = CachingResolver::with_ttl(None, dns_cache_ttl(Duration::ZERO));
+        resolver.resolve("127.0.0.1:80").unwrap();
+        thread::sleep(Duration::from_millis(1));
+        assert_eq!(resolver.lookup("127.0.0.1:80"), None);
+        assert_eq!(dns_cache_ttl(Duration::from_secs(12 * 3600)), DNS_CACHE_TTL);
+    }
+}
@@ -0,0 +1,59 @@
+use std::io;
+
+use anyhow::{anyhow, Context, Result};
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
+
+/// Format of log messages
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+
+    /// One JSON object per line, with timestamp, fields, and phase (`fetch`, `benchmark`, `write`).
+    /// Durations of phases are logged when they close.
+    Json,
+}
+
+/// Destination of log messages
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+
+    /// systemd journal, with priority of log level and fields as journal fields
+    Journald,
+}
+
+/// Initialize logging, `RUST_LOG` environment variable overrides `default_filter`
+pub fn init(default_filter: &str, format: LogFormat, target: LogTarget) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or(EnvFilter::try_new(default_filter)?);
+
+    if target == LogTarget::Journald {
+        let journald = tracing_journald::layer().context("Could not connect to systemd journal")?;
+        return tracing_subscriber::registry()
+            .with(filter)
+            .with(journald)
+            .try_init()
+            .map_err(|err| anyhow!("{err:#}"))
+            .context("Failed to initialize tracing subscriber");
+    }
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+
+    match format {
+        LogFormat::Text => builder.without_time().try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_span_events(FmtSpan::CLOSE)
+            .try_init(),
+    }
+    .map_err(|err| anyhow!("{err:#}"))
+    .context("Failed to initialize tracing subscriber")
+}
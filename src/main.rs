@@ -1,78 +1,266 @@
 mod args;
+mod audit;
+mod cache;
+mod clock;
+mod cmdline;
+mod color;
+mod concurrency;
+mod daemon;
+mod dns;
+mod doctor;
 mod exclude;
+mod exit;
+mod geo;
+mod history;
+mod hook;
+mod http;
+mod logging;
+mod manjaro;
 mod mirror;
+mod mirrorlist;
+mod monitor;
+mod netinfo;
+mod notify;
+mod output;
+mod package;
+mod paths;
+#[cfg(feature = "ping")]
+mod ping;
+mod pipeline;
+mod probe;
+mod report;
+mod samples;
+mod schedule;
+mod show;
+mod source;
+mod summary;
+mod template;
+mod tls;
+mod trends;
+mod tui;
+mod units;
 
 use std::{
-    io::{self, Write},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
     process::ExitCode,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
+use chrono::{TimeDelta, Utc};
 use clap::Parser;
 use mimalloc::MiMalloc;
-use tracing::{debug, error};
-use tracing_subscriber::EnvFilter;
+use tracing::{debug, error, info, info_span, warn};
+use url::Url;
 
 use crate::{
-    args::Arguments,
-    exclude::{ExcludeKind, ExcludedMirrors},
-    mirror::{Evaluation, Filter, Mirrors, MirrorsStatus, Statistics, ToPacmanMirrorList},
+    args::{Arguments, Command},
+    cache::BenchmarkCache,
+    concurrency::Threads,
+    exclude::{ExcludeKind, ExcludedMirrors, IncludedMirrors},
+    exit::Failure,
+    history::History,
+    http::IpFamily,
+    mirror::{
+        Benchmark, BenchmarkOptions, Evaluation, Filter, FilterOptions, FollowRedirects, Layout,
+        Mirror, MirrorListOptions, Mirrors, MirrorsStatus, OutputFormat, Sampling, ScoreWeights,
+        SelectOptions, Statistics, StatsFormat, StatusFormat, ToJson, ToPacmanMirrorList,
+        DEFAULT_MAX_REDIRECTS,
+    },
+    output::Overwrite,
+    package::BenchmarkPackage,
+    probe::Probe,
+    report::{ReportFormat, ToReport},
+    summary::Summary,
+    template::Template,
 };
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
 fn run() -> Result<()> {
-    let filter =
-        EnvFilter::try_from_default_env().unwrap_or(EnvFilter::try_new("pacman_mirrorup=info")?);
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .without_time()
-        .with_writer(io::stderr)
-        .try_init()
-        .map_err(|err| anyhow!("{err:#}"))
-        .context("Failed to initialize tracing subscriber")?;
-
     let arguments = Arguments::parse();
+
+    logging::init(
+        arguments.log_filter(),
+        arguments.log_format,
+        arguments.log_target,
+    )?;
+
     debug!("Run with {:?}", arguments);
+    output::set_dry_run(arguments.dry_run);
+    if let Some(ca_cert) = &arguments.ca_cert {
+        let count: usize = http::add_ca_certs(ca_cert)?;
+        info!("Trust {count} CA certificates of `{}`", ca_cert.display());
+    }
+    http::set_headers(arguments.user_agent.clone(), arguments.headers.clone())?;
+    if let Some(asn_db) = &arguments.asn_db {
+        let count: usize = netinfo::load_asn_db(asn_db)?;
+        info!("Load {count} address ranges of `{}`", asn_db.display());
+    }
+    if arguments.insecure {
+        http::set_insecure(true);
+        warn!("TLS certificate verification is DISABLED by `--insecure`, mirrors status and mirrors can be impersonated");
+    }
+
+    match &arguments.command {
+        Some(Command::Trends(trends_args)) => return trends::run(trends_args),
+        Some(Command::CheckAll(check_args)) => return audit::run(&arguments, check_args),
+        Some(Command::Monitor(monitor_args)) => return monitor::run(&arguments, monitor_args),
+        Some(Command::Doctor) => return doctor::run(&arguments),
+        Some(Command::Show(show_args)) => return show::run(&arguments, show_args),
+        Some(Command::InstallUnits(units_args)) => {
+            let mirrorup_args: Vec<String> = command_args("install-units");
+            return units::run(units_args, &mirrorup_args, arguments.force);
+        }
+        Some(Command::InstallHook(hook_args)) => {
+            let mirrorup_args: Vec<String> = command_args("install-hook");
+            return hook::run(hook_args, &mirrorup_args, arguments.force);
+        }
+        None => {}
+    }
+
+    // Agents outlive update cycles of `--daemon`, keeping connections and DNS cache alive
+    let agents = Agents::new(&arguments);
+
+    notify::ready();
+
+    if arguments.daemon {
+        if arguments.output_file.is_some() && !arguments.in_place && !arguments.force {
+            bail!("`--daemon` requires `--in-place` or `--force` to rewrite `--output-file`");
+        }
+        daemon::run(arguments.interval, || update(&arguments, &agents));
+    }
+
+    update(&arguments, &agents)
+}
+
+/// HTTP agents shared by all requests of every update
+struct Agents {
+    /// Agent of mirrors
+    mirror: ureq::Agent,
+
+    /// Agent of mirrors status
+    status: ureq::Agent,
+
+    /// Agents of mirrors over each address family, for `--dual-stack`
+    families: Vec<(IpFamily, ureq::Agent)>,
+}
+
+impl Agents {
+    fn new(arguments: &Arguments) -> Self {
+        let dns_ttl: Duration = http::dns_cache_ttl(arguments.interval);
+        let mirror: ureq::Agent =
+            http::agent_with_dns_ttl(arguments.max_redirects, arguments.resolve, dns_ttl);
+
+        // Mirrors status is fetched following redirects over any address family,
+        // even if mirrors may not redirect
+        let status: ureq::Agent =
+            if arguments.max_redirects < DEFAULT_MAX_REDIRECTS || arguments.resolve.is_some() {
+                http::agent_with_dns_ttl(DEFAULT_MAX_REDIRECTS, None, dns_ttl)
+            } else {
+                mirror.clone()
+            };
+
+        let families: Vec<(IpFamily, ureq::Agent)> = if arguments.dual_stack {
+            [IpFamily::V4, IpFamily::V6]
+                .into_iter()
+                .map(|family| {
+                    let agent =
+                        http::agent_with_dns_ttl(arguments.max_redirects, Some(family), dns_ttl);
+                    (family, agent)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            mirror,
+            status,
+            families,
+        }
+    }
+}
+
+/// Fetch, benchmark, and rank mirrors, then write mirror list
+fn update(arguments: &Arguments, agents: &Agents) -> Result<()> {
+    let start = Instant::now();
+    http::take_downloaded();
+    netinfo::clear_caches();
+    let mut summary = Summary::default();
 
     if let Some(output_file) = &arguments.output_file {
-        if output_file.exists() {
+        if output_file.exists() && !arguments.in_place && !arguments.force {
             bail!("`{}` is exist.", output_file.display());
         }
     }
 
+    if arguments.backup.is_some() && !arguments.in_place && !arguments.force {
+        bail!("`--backup` requires `--in-place` or `--force`");
+    }
+
+    if arguments.min_improvement.is_some()
+        && arguments.output_file.is_none()
+        && arguments.merge_into.is_none()
+    {
+        bail!("`--min-improvement` requires `--output-file` or `--merge-into`");
+    }
+
+    if arguments.status_format() == StatusFormat::Manjaro
+        && (arguments.pipeline || arguments.merge_sources)
+    {
+        bail!("Manjaro mirrors status cannot be used with `--pipeline` or `--merge-sources`");
+    }
+
+    if arguments.benchmark_package.is_some() && arguments.target_dbs.len() > 1 {
+        bail!("`--benchmark-package` cannot be used with several target databases");
+    }
+
+    if arguments.merge_into.is_some() && arguments.format != OutputFormat::Pacman {
+        bail!("`--merge-into` only supports pacman mirror list format");
+    }
+
     if let Some(stats_file) = &arguments.stats_file {
-        if stats_file.exists() {
+        if is_stdout(stats_file) {
+            if arguments.output_file.is_none() && arguments.merge_into.is_none() {
+                bail!("`--stats-file -` requires `--output-file` or `--merge-into`, mirror list is written to STDOUT otherwise");
+            }
+        } else if stats_file.exists() && !arguments.force {
             bail!("`{}` is exist.", stats_file.display());
         }
     }
 
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(arguments.threads)
-        .build_global()
-        .with_context(|| {
-            format!(
-                "Failed to set number of rayon threads to {}",
-                arguments.threads
-            )
-        })?;
+    if let Some(report_file) = &arguments.report_file {
+        if is_stdout(report_file) {
+            if arguments.output_file.is_none() && arguments.merge_into.is_none() {
+                bail!("`--report-file -` requires `--output-file` or `--merge-into`, mirror list is written to STDOUT otherwise");
+            }
+            if arguments.stats_file.as_deref().is_some_and(is_stdout) {
+                bail!("`--report-file -` and `--stats-file -` cannot both write to STDOUT");
+            }
+        } else if report_file.exists() && !arguments.force {
+            bail!("`{}` is exist.", report_file.display());
+        }
+    }
 
     // Merge all excluded mirrors from --exclude and --exclude-from option
-    let excluded_mirrors: Option<ExcludedMirrors> = 'excluded_mirrors: {
+    let mut excluded_mirrors: Option<ExcludedMirrors> = 'excluded_mirrors: {
         if arguments.exclude.is_none() && arguments.exclude_from.is_none() {
             break 'excluded_mirrors None;
         }
 
         let mut exclude = ExcludedMirrors::new();
 
-        if let Some(f) = arguments.exclude_from {
-            exclude.add_from(&f)?;
+        if let Some(f) = &arguments.exclude_from {
+            exclude.add_from(f)?;
         }
 
         // Add excluded list from --exclude option after --exclude-from option,
         // since the last occurrence of excluded pattern will overrride previous one.
-        if let Some(list) = arguments.exclude {
+        if let Some(list) = &arguments.exclude {
             for m in list {
                 exclude.add(ExcludeKind::try_from(m.as_str())?);
             }
@@ -80,70 +268,623 @@ fn run() -> Result<()> {
 
         Some(exclude)
     };
+
+    let mut included_mirrors: Option<IncludedMirrors> = match &arguments.include_from {
+        Some(f) => {
+            let mut include = IncludedMirrors::new();
+            include.add_from(f)?;
+            Some(include)
+        }
+        None => None,
+    };
+
+    // `asn` and `cidr` patterns resolve mirrors and may look up their AS on RIPEstat
+    if arguments.offline {
+        for (list, name) in [
+            (&mut excluded_mirrors, "excluded"),
+            (&mut included_mirrors, "included"),
+        ] {
+            let removed: usize = list
+                .as_mut()
+                .map_or(0, ExcludedMirrors::remove_address_patterns);
+            if removed > 0 {
+                warn!("Ignore {removed} `asn` and `cidr` pattern(s) of {name} mirrors, matching them needs network access");
+            }
+        }
+    }
     debug!("Excluded mirrors: {excluded_mirrors:?}");
+    debug!("Included mirrors: {included_mirrors:?}");
+
+    let layout: Layout = arguments.layout();
+
+    let agent: &ureq::Agent = &agents.mirror;
+    let status_agent: &ureq::Agent = &agents.status;
+
+    let filter_options = FilterOptions {
+        max_check: Some(arguments.max_check),
+        excluded_mirrors,
+        included_mirrors,
+        clock_skew_tolerance: Duration::from_secs(arguments.clock_skew_tolerance),
+        max_age: arguments.age,
+        ipv4: arguments.ipv4,
+        ipv6: arguments.ipv6,
+        isos: arguments.isos,
+        dedupe_by_ip: arguments.dedupe_by_ip,
+        countries: arguments.country.clone(),
+        country_codes: arguments.country_code.clone(),
+        sample: arguments.sample.map(|n| Sampling {
+            n,
+            by_country: arguments.sample_by_country,
+            seed: arguments.seed.unwrap_or_else(schedule::random_seed),
+        }),
+    };
+    let benchmark_options = BenchmarkOptions {
+        transfer_limit: arguments.transfer_limit,
+        samples: arguments.samples,
+        aggregate: arguments.aggregate,
+        reject_outliers: arguments.reject_outliers,
+        skip: arguments.no_benchmark,
+        request_delay: Duration::from_millis(arguments.request_delay),
+        adaptive: arguments.adaptive_threads,
+        layout: layout.clone(),
+        other_dbs: arguments.target_dbs[1..].to_vec(),
+        db_aggregate: arguments.target_db_aggregate,
+        package: arguments.benchmark_package.map(BenchmarkPackage::new),
+        require_sig: arguments.require_sig,
+        require_files_db: arguments.require_files_db,
+        probe_range: arguments.probe_range,
+        require_range: arguments.require_range,
+        probe_tls: arguments.probe_tls || arguments.weight_http2 != 0.0,
+        min_tls: arguments.min_tls,
+        min_cert_days: arguments.min_cert_days,
+        family: arguments.resolve,
+        family_agents: agents.families.clone(),
+        ..BenchmarkOptions::new(arguments.target_db(), agent.clone())
+    };
+
+    let score_weights = ScoreWeights {
+        rate: arguments.weight_rate,
+        score: arguments.weight_score,
+        delay: arguments.weight_delay,
+        age: arguments.weight_age,
+        ttfb: arguments.weight_ttfb,
+        tls: arguments.weight_tls,
+        http2: arguments.weight_http2,
+        ping: arguments.weight_ping(),
+        stability: arguments.weight_stability,
+        distance: arguments.weight_distance,
+    };
+
+    let selection = SelectOptions {
+        spread_asn: arguments.spread_asn,
+        max_per_cdn: arguments.max_per_cdn.map(|n| n as usize),
+        seed: arguments.seed,
+        ..SelectOptions::new(arguments.mirrors + arguments.commented_extra)
+    };
+
+    // Mirrors given by user instead of mirrors status
+    let input: Option<(&Path, Vec<String>)> =
+        match (&arguments.input_mirrorlist, &arguments.urls_from) {
+            (Some(path), _) => Some((path, mirrorlist::read_servers(path)?)),
+            (None, Some(path)) => Some((path, mirror::read_urls(path)?)),
+            (None, None) => None,
+        };
+
+    let cache_file: PathBuf = arguments
+        .cache_file
+        .clone()
+        .unwrap_or_else(cache::default_path);
+    let status_file: PathBuf = cache::status_path(&cache_file);
+    let status_source: String = status_file.display().to_string();
+
+    // Offline mode uses cached measurements regardless of their age, unless TTL is given
+    let mut cache: Option<BenchmarkCache> = match arguments.cache_ttl {
+        Some(ttl) => Some(BenchmarkCache::open(&cache_file, ttl)?),
+        None if arguments.offline && !arguments.no_benchmark => {
+            Some(BenchmarkCache::open(&cache_file, Duration::MAX)?)
+        }
+        None => None,
+    };
+    let benchmark = |cache: &mut Option<BenchmarkCache>, mirrors: &Mirrors| -> Result<Mirrors> {
+        match cache {
+            Some(cache) if arguments.offline => cache.cached(mirrors, &benchmark_options.target()),
+            Some(cache) => Ok(cache.benchmark(mirrors, &benchmark_options)),
+            None => Ok(mirrors.benchmark(&benchmark_options)),
+        }
+    };
+
+    // Source of mirrors status which is actually used
+    let mut source_url: &str = &arguments.source_url[0];
+
+    // Mirrors picked in interactive mode, in place of ranking
+    let mut picked_mirrors: Option<Mirrors> = None;
+
+    // Probe all candidates cheaply, only the fastest ones are benchmarked
+    let pre_rank = |mirrors: &mut Mirrors| {
+        if arguments.offline {
+            return;
+        }
+        match arguments.probe {
+            Some(Probe::Tcp) => {
+                notify::status(&format!("Probing {} mirrors", mirrors.len()));
+                let dropped: usize =
+                    mirrors.pre_rank(arguments.probe_keep as usize, |mirror: &Mirror| {
+                        probe::connect_time(&mirror.url, arguments.resolve)
+                            .inspect_err(|err| debug!("{err:#}"))
+                            .ok()
+                            .map(|time| time.as_secs_f64())
+                    });
+                info!(
+                    "Benchmark {} mirrors of fastest TCP connect, skip {dropped}",
+                    mirrors.len()
+                );
+            }
+            None => {}
+        }
+    };
+
+    notify::status("Fetching mirrors status");
+    let mut measured_mirrors: Mirrors = if let Some((input, urls)) = &input {
+        let mut servers: Mirrors = urls.iter().map(|url| Mirror::from_url(url)).collect();
+        servers.collapse_duplicates();
+        if arguments.dedupe_by_ip && !arguments.offline {
+            servers.collapse_same_addresses(netinfo::resolve);
+        }
+        if servers.is_empty() {
+            return Err(anyhow!("No servers in `{}`", input.display()).context(Failure::NoMirrors));
+        }
+        info!("Rank {} servers of `{}`", servers.len(), input.display());
+        pre_rank(&mut servers);
+
+        if !arguments.offline {
+            let threads: usize = match arguments.threads {
+                Threads::Fixed(n) => n,
+                Threads::Auto => concurrency::auto_threads(&servers, &benchmark_options),
+            };
+            build_thread_pool(threads)?;
+        }
+
+        benchmark(&mut cache, &servers)?
+    } else if arguments.pipeline {
+        let Threads::Fixed(threads) = arguments.threads else {
+            bail!("`--threads auto` cannot be used with `--pipeline`");
+        };
+        build_thread_pool(threads)?;
+
+        let (measured, source) = pipeline::fetch_and_benchmark(
+            agent,
+            &arguments.source_url,
+            &filter_options,
+            &benchmark_options,
+        )
+        .context(Failure::StatusFetch)?;
+        source_url = source;
+        measured
+    } else {
+        let mirrors_status: MirrorsStatus = if arguments.offline {
+            source_url = &status_source;
+            cache::load_status(&status_file).context(Failure::StatusFetch)?
+        } else if arguments.status_format() == StatusFormat::Manjaro {
+            let (mirrors_status, source) =
+                manjaro::from_sources(status_agent, &arguments.source_url, arguments.branch)
+                    .context(Failure::StatusFetch)?;
+            source_url = source;
+            mirrors_status
+        } else if arguments.merge_sources {
+            MirrorsStatus::from_all_sources(status_agent, &arguments.source_url)
+                .context(Failure::StatusFetch)?
+        } else {
+            let (mirrors_status, source) =
+                MirrorsStatus::from_sources(status_agent, &arguments.source_url)
+                    .context(Failure::StatusFetch)?;
+            source_url = source;
+            mirrors_status
+        };
+
+        summary = mirrors_status.summary(&filter_options);
+
+        // Keep mirrors status for `--offline`
+        if arguments.cache_ttl.is_some() && !arguments.offline && !arguments.dry_run {
+            cache::save_status(&status_file, &mirrors_status)?;
+        }
+
+        let mut best_synced_mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&filter_options)
+            .context(Failure::NoMirrors)?;
+        pre_rank(&mut best_synced_mirrors);
 
-    let mirrors_status: MirrorsStatus = MirrorsStatus::from_online_json(&arguments.source_url)
-        .with_context(|| {
-            format!(
-                "Failed to fetch mirrors status from `{}`",
-                arguments.source_url
-            )
-        })?;
+        if !arguments.no_benchmark && !arguments.offline {
+            let threads: usize = match arguments.threads {
+                Threads::Fixed(n) => n,
+                Threads::Auto => {
+                    concurrency::auto_threads(&best_synced_mirrors, &benchmark_options)
+                }
+            };
+            build_thread_pool(threads)?;
+        }
+
+        if arguments.interactive {
+            let (measured, picked) = tui::run(&best_synced_mirrors, &benchmark_options)?;
+            picked_mirrors = Some(picked);
+            measured
+        } else {
+            benchmark(&mut cache, &best_synced_mirrors)?
+        }
+    };
+
+    if !arguments.no_benchmark {
+        if measured_mirrors.iter().all(|m| m.transfer_rate.is_none()) {
+            bail!(Failure::NoBenchmarkResults);
+        }
+        summary.benchmarked = Some(measured_mirrors.len());
+        summary.failed = Some(
+            measured_mirrors
+                .iter()
+                .filter(|m| m.transfer_rate.is_none())
+                .count(),
+        );
+    }
+
+    if let Some(max_lag) = arguments.max_content_lag {
+        let reference = match &arguments.content_reference {
+            Some(url) => {
+                let url: Url = Url::parse(url)
+                    .and_then(|url| url.join(&arguments.target_db().path(&layout)))
+                    .with_context(|| format!("Invalid content reference `{url}`"))?;
+                Some(mirror::fetch_last_modified(agent, &url)?)
+            }
+            None => measured_mirrors.fastest_last_modified(),
+        };
+        match reference {
+            Some(reference) => {
+                let dropped: usize =
+                    measured_mirrors.drop_stale_content(reference, TimeDelta::from_std(max_lag)?);
+                info!("Dropped {dropped} mirrors serving stale content");
+            }
+            None => warn!("No `Last-Modified` to compare mirrors against"),
+        }
+    }
+
+    if let Some(cache) = cache
+        .as_mut()
+        .filter(|_| !arguments.dry_run && !arguments.offline)
+    {
+        cache.save()?;
+    }
+
+    if let Some(location) = &arguments.location {
+        measured_mirrors.update_distance(location);
+    }
+
+    if !arguments.offline {
+        measured_mirrors.detect_cdns(netinfo::cdn);
+        if arguments.stats_network {
+            measured_mirrors.resolve_network(netinfo::resolve, netinfo::asn);
+        }
+        #[cfg(feature = "ping")]
+        if arguments.ping || arguments.weight_ping != 0.0 {
+            measured_mirrors
+                .measure_ping(|mirror| ping::mirror_round_trip(&mirror.url, arguments.resolve));
+        }
+    }
+
+    // Save all measurements to history database
+    if let Some(history_db) = arguments.history_db.as_ref().filter(|_| !arguments.dry_run) {
+        let history_db: PathBuf = history_db.clone().unwrap_or_else(paths::history_db);
+        History::open(&history_db)?
+            .record(Utc::now(), &benchmark_options.target(), &measured_mirrors)
+            .with_context(|| {
+                format!("Failed to save history database `{}`", history_db.display())
+            })?;
+    }
+
+    let score_weights = if arguments.no_benchmark {
+        score_weights.without_measurements()
+    } else if input.is_some() {
+        score_weights.without_status()
+    } else {
+        score_weights
+    };
+    let mut best_mirrors: Mirrors = match picked_mirrors {
+        // Picked mirrors are subject to the same filters after benchmark
+        Some(picked) => picked
+            .iter()
+            .filter_map(|picked| {
+                measured_mirrors
+                    .iter()
+                    .find(|m| m.url == picked.url && m.transfer_rate.is_some())
+                    .cloned()
+            })
+            .collect(),
+        None => measured_mirrors
+            .rank(&selection, &score_weights)
+            .context("Failed to evaluate mirror")?,
+    };
+    let n: usize = arguments.mirrors.try_into()?;
+    let mut extra_mirrors: Mirrors = if best_mirrors.len() > n {
+        best_mirrors.split_off(n).into_iter().collect()
+    } else {
+        Mirrors::default()
+    };
+
+    // Keep current mirror list unless the new one is faster enough
+    let current_list: Option<&Path> = arguments
+        .merge_into
+        .as_deref()
+        .or(arguments.output_file.as_deref())
+        .filter(|path| path.exists());
+    let keep_current: bool = match (arguments.min_improvement, current_list) {
+        (Some(min_improvement), Some(path)) => {
+            let current: Mirrors = measure_current(path, &measured_mirrors, &benchmark_options, n)?;
+            let current_rate: f64 = current.mean_transfer_rate();
+            let new_rate: f64 = best_mirrors.mean_transfer_rate();
+            let improvement: f64 = if current_rate > 0.0 {
+                (new_rate - current_rate) / current_rate * 100.0
+            } else {
+                f64::INFINITY
+            };
+            info!(
+                "Mean transfer rate of current mirror list: {current_rate:.0} B/s, new: {new_rate:.0} B/s ({improvement:+.1}%)"
+            );
+            improvement < min_improvement
+        }
+        _ => false,
+    };
 
-    let best_synced_mirrors: Mirrors = mirrors_status
-        .best_synced_mirrors(Some(arguments.max_check), excluded_mirrors)
-        .context("Could not filter best synced mirrors")?;
+    let overwrite: Overwrite = if arguments.force {
+        Overwrite::Always
+    } else {
+        Overwrite::Never
+    };
+    let output_overwrite: Overwrite = match arguments.backup {
+        Some(n) => Overwrite::Rotate(n),
+        None if arguments.in_place => Overwrite::Backup,
+        None => overwrite,
+    };
 
-    let best_mirrors: Mirrors = best_synced_mirrors
-        .evaluate(arguments.mirrors, arguments.target_db)
-        .context("Failed to evaluate mirror")?;
+    notify::status("Writing mirror list");
+    let _phase = info_span!("write").entered();
 
     // Save stats file
     if let Some(stats_file) = &arguments.stats_file {
+        let format: StatsFormat = arguments
+            .stats_format
+            .unwrap_or_else(|| StatsFormat::from_path(stats_file));
+        if is_stdout(stats_file) {
+            let stdout = io::BufWriter::new(io::stdout().lock());
+            if let Err(err) = best_mirrors
+                .write_stats(stdout, format)
+                .context("Could not write stats to STDOUT")
+            {
+                if !is_broken_pipe(&err) {
+                    return Err(err.context(Failure::OutputWrite));
+                }
+            }
+        } else {
+            best_mirrors
+                .to_stats_file(stats_file, format, overwrite)
+                .with_context(|| format!("Failed to save stats file `{}`", stats_file.display()))
+                .context(Failure::OutputWrite)?;
+        }
+    }
+
+    // Save report in rank order
+    if let Some(report_file) = &arguments.report_file {
+        let format: ReportFormat = arguments
+            .report_format
+            .unwrap_or_else(|| ReportFormat::from_path(report_file));
+        if is_stdout(report_file) {
+            let mut stdout = io::stdout().lock();
+            if let Err(err) = stdout
+                .write_all(
+                    best_mirrors
+                        .to_colored_report(format, arguments.color.enabled(stdout.is_terminal()))
+                        .as_bytes(),
+                )
+                .and_then(|_| stdout.flush())
+            {
+                if err.kind() != io::ErrorKind::BrokenPipe {
+                    return Err(anyhow::Error::new(err)
+                        .context("Could not write report to STDOUT")
+                        .context(Failure::OutputWrite));
+                }
+            }
+        } else {
+            best_mirrors
+                .to_report_file(report_file, format, overwrite)
+                .with_context(|| format!("Failed to save report `{}`", report_file.display()))
+                .context(Failure::OutputWrite)?;
+        }
+    }
+
+    if arguments.follow_redirects == FollowRedirects::Rewrite {
+        best_mirrors.rewrite_redirected(arguments.target_db(), &layout);
+        extra_mirrors.rewrite_redirected(arguments.target_db(), &layout);
+    }
+
+    if let Some(sort) = arguments.sort {
+        best_mirrors.sort_output(sort);
+        extra_mirrors.sort_output(sort);
+    }
+
+    if let Some(mode) = arguments.verify {
         best_mirrors
-            .to_csv(stats_file)
-            .with_context(|| format!("Failed to save stats file `{}`", stats_file.display()))?;
+            .verify(agent, arguments.target_db(), &layout, mode)
+            .context("Selected mirrors failed verification")?;
+    }
+
+    let mut pinned: Vec<String> = arguments.pin.clone();
+    if let Some(pin_from) = &arguments.pin_from {
+        pinned.extend(mirror::read_urls(pin_from).context("Could not read pinned mirrors")?);
+    }
+    best_mirrors.pin(&pinned)?;
+    extra_mirrors.retain(|mirror| !best_mirrors.iter().any(|m| m.url_key() == mirror.url_key()));
+
+    if !arguments.quiet {
+        summary.selected = best_mirrors.len();
+        summary.elapsed = start.elapsed();
+        summary.downloaded = http::take_downloaded();
+        eprint!(
+            "{}",
+            summary.to_table(arguments.color.enabled(io::stderr().is_terminal()))
+        );
+    }
+
+    // Data source written in mirrorlist header
+    let source: String = match &input {
+        Some((path, _)) => path.display().to_string(),
+        None if arguments.merge_sources => arguments.source_url.join(", "),
+        None => source_url.to_string(),
+    };
+
+    let list_options = MirrorListOptions {
+        verbose: arguments.verbose_output,
+        group_by_country: arguments.group_by_country,
+        commented_extra: extra_mirrors,
+        template: arguments
+            .template
+            .as_deref()
+            .map(Template::from_file)
+            .transpose()?,
+        flavor: arguments.flavor,
+        repo_path: arguments.repo_path_template.clone(),
+        server_line: arguments.mirrorlist_line_template.clone(),
+    };
+
+    if keep_current {
+        info!("New mirror list is not faster than `--min-improvement`, keep current one");
+        return Ok(());
+    }
+
+    // Replace generated block of existing mirrorlist
+    if let Some(merge_into) = &arguments.merge_into {
+        let generated: String = best_mirrors
+            .to_mirrorlist(&source, &list_options)
+            .context("Could not create pacman mirror list format")?;
+        mirrorlist::merge_into_file(merge_into, &generated).context(Failure::OutputWrite)?;
+        return Ok(());
     }
 
     // Save mirrors to file
     if let Some(output_file) = &arguments.output_file {
+        // Built once, so that the confirmed preview is exactly what is written
+        let contents: String = match arguments.format {
+            OutputFormat::Pacman => best_mirrors
+                .to_mirrorlist(&source, &list_options)
+                .context("Could not create pacman mirror list format")?,
+            OutputFormat::Json => format!("{}\n", best_mirrors.to_json()?),
+        };
+
+        if arguments.confirm && !output::is_dry_run() {
+            eprint!("{contents}");
+            let question: String = format!(
+                "Write {} mirrors to `{}`?",
+                best_mirrors.len(),
+                output_file.display()
+            );
+            if !output::confirm(&question, io::stdin().lock(), io::stderr())? {
+                info!("Mirror list is not written");
+                return Ok(());
+            }
+        }
+
         // Write to file
-        best_mirrors
-            .to_mirrorlist_file(output_file, &arguments.source_url)
+        output::save(output_file, contents.as_bytes(), output_overwrite)
             .with_context(|| {
                 format!(
                     "Could not write to mirrorlist file `{}`",
                     output_file.display()
                 )
-            })?;
+            })
+            .context(Failure::OutputWrite)?;
         return Ok(());
     }
 
     // Write to stdout
-    let mirror_list: String = best_mirrors
-        .to_pacman_mirror_list()
-        .context("Could not create pacman mirror list format")?;
+    let mirror_list: String = match arguments.format {
+        OutputFormat::Pacman => best_mirrors
+            .to_pacman_mirror_list(&list_options)
+            .context("Could not create pacman mirror list format")?,
+        OutputFormat::Json => best_mirrors.to_json()?,
+    };
     let mut stdout = io::BufWriter::new(io::stdout().lock());
     if let Err(err) =
         writeln!(stdout, "{mirror_list}").context("Could not write mirror list to STDOUT")
     {
-        if let Some(io_err) = err.downcast_ref::<io::Error>() {
-            match io_err.kind() {
-                io::ErrorKind::BrokenPipe => return Ok(()),
-                _ => bail!("{err:#}"),
-            }
-        } else {
-            bail!("{err:#}");
+        if !is_broken_pipe(&err) {
+            return Err(err.context(Failure::OutputWrite));
         }
     }
 
     Ok(())
 }
 
+/// Top `n` servers of current mirror list with their transfer rates,
+/// measurements of this run are reused and the other servers are benchmarked
+fn measure_current(
+    path: &Path,
+    measured: &Mirrors,
+    options: &BenchmarkOptions,
+    n: usize,
+) -> Result<Mirrors> {
+    let mut current = Mirrors::default();
+    let mut unmeasured = Mirrors::default();
+    for url in mirrorlist::read_servers(path)?.into_iter().take(n) {
+        let mirror = Mirror::from_url(&url);
+        match measured.iter().find(|m| m.url_key() == mirror.url_key()) {
+            Some(m) => current.push(m.clone()),
+            None => unmeasured.push(mirror),
+        }
+    }
+
+    unmeasured.measure_duration(options)?;
+    current.extend(unmeasured.drain(..));
+    Ok(current)
+}
+
+/// `-` as file name means STDOUT
+fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Reader of STDOUT went away, e.g. `pacman-mirrorup | head`
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|io_err| io_err.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Options given before `command` are options of pacman-mirrorup run by the installed files,
+/// except those about writing the files
+fn command_args(command: &str) -> Vec<String> {
+    std::env::args()
+        .skip(1)
+        .take_while(|arg| arg != command)
+        .filter(|arg| !["-f", "--force", "--overwrite", "--dry-run"].contains(&arg.as_str()))
+        .collect()
+}
+
+fn build_thread_pool(threads: usize) -> Result<()> {
+    // Global thread pool can only be built once, later update cycles of `--daemon` keep it
+    static BUILT: AtomicBool = AtomicBool::new(false);
+    if BUILT.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .with_context(|| format!("Failed to set number of rayon threads to {threads}"))
+}
+
 fn main() -> ExitCode {
-    if let Err(err) = run() {
+    let result: Result<()> = run();
+    notify::stopping();
+    if let Err(err) = result {
         error!("{err:#}");
-        return ExitCode::FAILURE;
+        return exit::code(&err);
     }
     ExitCode::SUCCESS
 }
@@ -0,0 +1,174 @@
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tracing::info_span;
+use url::Url;
+
+use crate::{
+    mirror::{Mirror, Mirrors, MirrorsStatus},
+    source::{self, Source},
+};
+
+/// Mirrors status of Manjaro, used by pacman-mirrors
+pub const MANJARO_SOURCE_URL: &str = "https://repo.manjaro.org/status.json";
+
+/// Manjaro branch, in order of `branches` of mirrors status
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Branch {
+    #[default]
+    Stable,
+    Testing,
+    Unstable,
+}
+
+impl Branch {
+    fn name(&self) -> &'static str {
+        match self {
+            Branch::Stable => "stable",
+            Branch::Testing => "testing",
+            Branch::Unstable => "unstable",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Mirror of Manjaro mirrors status
+#[derive(Deserialize, Debug)]
+struct ManjaroMirror {
+    url: String,
+    country: String,
+
+    /// Time since last sync as `HH:MM`
+    #[serde(default)]
+    last_sync: String,
+
+    /// 1 if branch is in sync, 0 if not, -1 if unknown
+    #[serde(default)]
+    branches: Vec<i64>,
+
+    #[serde(default)]
+    protocols: Vec<String>,
+}
+
+impl ManjaroMirror {
+    /// Mirror of `branch` for each HTTP(S) protocol it supports
+    fn into_mirrors(self, branch: Branch, now: DateTime<Utc>) -> Vec<Mirror> {
+        let in_sync: bool = self.branches.get(branch.index()) == Some(&1);
+        let last_sync: Option<DateTime<Utc>> = parse_sync_age(&self.last_sync).map(|age| now - age);
+        let country: String = self.country.replace('_', " ");
+
+        let Ok(base) = Url::parse(&self.url) else {
+            return Vec::new();
+        };
+        self.protocols
+            .iter()
+            .filter(|protocol| *protocol == "https" || *protocol == "http")
+            .filter_map(|protocol| {
+                let mut url: Url = base.clone();
+                url.set_scheme(protocol).ok()?;
+                let url: Url = url.join(&format!("{}/", branch.name())).ok()?;
+                Some(Mirror::from_derivative(
+                    url.as_str(),
+                    &country,
+                    last_sync,
+                    in_sync,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Time since last sync from `HH:MM`, None if unknown
+fn parse_sync_age(last_sync: &str) -> Option<TimeDelta> {
+    let (hours, minutes) = last_sync.split_once(':')?;
+    let hours: i64 = hours.parse().ok().filter(|h| *h >= 0)?;
+    let minutes: i64 = minutes.parse().ok().filter(|m| (0..60).contains(m))?;
+    Some(TimeDelta::hours(hours) + TimeDelta::minutes(minutes))
+}
+
+/// Mirrors of `branch` from Manjaro mirrors status
+fn from_reader<R: Read>(reader: R, branch: Branch, now: DateTime<Utc>) -> Result<MirrorsStatus> {
+    let mirrors: Vec<ManjaroMirror> = serde_json::from_reader(reader)
+        .context("Failed to deserialize the response body as Manjaro mirrors status")?;
+    let mirrors: Mirrors = mirrors
+        .into_iter()
+        .flat_map(|mirror| mirror.into_mirrors(branch, now))
+        .collect();
+    Ok(MirrorsStatus::from_mirrors(mirrors, now))
+}
+
+/// Fetch Manjaro mirrors status from the first source that works, returns it with the source used
+pub fn from_sources<'a>(
+    agent: &ureq::Agent,
+    urls: &'a [String],
+    branch: Branch,
+) -> Result<(MirrorsStatus, &'a str)> {
+    source::first_ok(urls, |url| {
+        let _phase = info_span!("fetch", source = url).entered();
+        let reader = Source::parse(url)?.open(agent)?;
+        from_reader(reader, branch, Utc::now())
+            .with_context(|| format!("Failed to fetch mirrors status from `{url}`"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sync_age() {
+        assert_eq!(
+            parse_sync_age("01:30"),
+            Some(TimeDelta::hours(1) + TimeDelta::minutes(30))
+        );
+        assert_eq!(
+            parse_sync_age("26:05"),
+            Some(TimeDelta::minutes(26 * 60 + 5))
+        );
+        assert_eq!(parse_sync_age("-1"), None);
+        assert_eq!(parse_sync_age("-01:00"), None);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let raw: &str = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/manjaro_status.json"
+        ));
+        let now: DateTime<Utc> = "2024-01-01T12:00:00Z".parse().unwrap();
+
+        let status: MirrorsStatus = from_reader(raw.as_bytes(), Branch::Stable, now).unwrap();
+        let mirrors: &Mirrors = status.mirrors();
+        assert_eq!(
+            mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "https://mirror.example.de/manjaro/stable/",
+                "http://mirror.example.de/manjaro/stable/",
+                "http://mirror.example.com/manjaro/stable/",
+            ]
+        );
+        assert_eq!(mirrors[2].country, "United States");
+
+        let clock = status.reference_clock(std::time::Duration::from_secs(300));
+        assert!(mirrors[0].is_synced(&clock));
+        assert!(!mirrors[2].is_synced(&clock));
+
+        let status: MirrorsStatus = from_reader(raw.as_bytes(), Branch::Unstable, now).unwrap();
+        let clock = status.reference_clock(std::time::Duration::from_secs(300));
+        let synced: Vec<&str> = status
+            .mirrors()
+            .iter()
+            .filter(|m| m.is_synced(&clock))
+            .map(|m| m.url.as_str())
+            .collect();
+        assert_eq!(synced, vec!["http://mirror.example.com/manjaro/unstable/"]);
+    }
+}
@@ -1,40 +1,234 @@
 use std::{
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fmt::Write,
-    fs::OpenOptions,
-    io::BufWriter,
+    io::{self, Read},
+    net::IpAddr,
     ops::{Deref, DerefMut},
     path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info};
+use tracing::{debug, info, info_span, warn};
 use url::Url;
 
-use crate::exclude::ExcludedMirrors;
-
-static APP_USER_AGENT: &str = concat!(
-    env!("CARGO_PKG_NAME"),
-    "/",
-    env!("CARGO_PKG_VERSION"),
-    " (",
-    env!("CARGO_PKG_HOMEPAGE"),
-    ")"
-);
+use crate::{
+    clock::{parse_timestamp, ReferenceClock, DEFAULT_CLOCK_SKEW_TOLERANCE},
+    concurrency::AdaptiveLimit,
+    exclude::{ExcludedMirrors, IncludedMirrors},
+    geo::Coordinates,
+    http::{self, ConnectionTimings, IpFamily},
+    mirrorlist::{BEGIN_MARKER, END_MARKER},
+    netinfo, notify,
+    output::{self, Overwrite},
+    package::BenchmarkPackage,
+    report,
+    samples::{self, Aggregate},
+    schedule,
+    source::{self, Source},
+    summary::Summary,
+    template::{self, Template},
+    tls::{self, TlsVersion},
+};
 
 pub const DEFAULT_SOURCE_URL: &str = "https://archlinux.org/mirrors/status/json/";
 
+pub const DEFAULT_MAX_REDIRECTS: u32 = 5;
+
+/// Architecture of packages benchmarked by default
+pub const DEFAULT_ARCH: &str = "x86_64";
+
+/// Repository path of Arch Linux mirrors
+pub const ARCH_REPO_PATH: &str = "$repo/os/$arch";
+
+/// Distribution whose mirrors are ranked
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Flavor {
+    #[default]
+    Arch,
+
+    /// Manjaro, mirrors of a branch from Manjaro mirrors status
+    Manjaro,
+}
+
+impl Flavor {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flavor::Arch => "Arch Linux",
+            Flavor::Manjaro => "Manjaro Linux",
+        }
+    }
+
+    /// Path of repository relative to mirror's URL, as in pacman mirror list
+    pub fn repo_path(&self) -> &'static str {
+        match self {
+            Flavor::Arch => ARCH_REPO_PATH,
+            Flavor::Manjaro => "$repo/$arch",
+        }
+    }
+
+    pub fn status_format(&self) -> StatusFormat {
+        match self {
+            Flavor::Arch => StatusFormat::Arch,
+            Flavor::Manjaro => StatusFormat::Manjaro,
+        }
+    }
+}
+
+/// Format of mirrors status
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum StatusFormat {
+    /// Mirrors status JSON of archlinux.org
+    #[default]
+    Arch,
+
+    /// Mirrors status JSON of Manjaro
+    Manjaro,
+}
+
+/// Where repositories of an architecture are under mirror's URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Path of repository, `$repo` and `$arch` are replaced as pacman does
+    pub repo_path: String,
+
+    pub arch: String,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            repo_path: ARCH_REPO_PATH.to_string(),
+            arch: DEFAULT_ARCH.to_string(),
+        }
+    }
+}
+
 #[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
 pub enum TargetDb {
     Core,
     Extra,
+    Multilib,
+    CoreTesting,
+    ExtraTesting,
+    MultilibTesting,
+}
+
+impl TargetDb {
+    pub fn repo(&self) -> &'static str {
+        match self {
+            TargetDb::Core => "core",
+            TargetDb::Extra => "extra",
+            TargetDb::Multilib => "multilib",
+            TargetDb::CoreTesting => "core-testing",
+            TargetDb::ExtraTesting => "extra-testing",
+            TargetDb::MultilibTesting => "multilib-testing",
+        }
+    }
+
+    /// Path of repository directory relative to mirror's URL, without trailing slash
+    pub fn dir(&self, layout: &Layout) -> String {
+        layout
+            .repo_path
+            .replace("$repo", self.repo())
+            .replace("$arch", &layout.arch)
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// Path of database file relative to mirror's URL
+    pub fn path(&self, layout: &Layout) -> String {
+        format!("{}/{}.db", self.dir(layout), self.repo())
+    }
+
+    /// Path of files database, used by `pacman -F`, relative to mirror's URL
+    pub fn files_path(&self, layout: &Layout) -> String {
+        format!("{}/{}.files", self.dir(layout), self.repo())
+    }
+}
+
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum FollowRedirects {
+    /// Follow redirects, keep mirror's URL in mirror list
+    #[default]
+    Follow,
+
+    /// Follow redirects, write redirect target to mirror list
+    Rewrite,
+}
+
+/// Format of mirror list output
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    /// pacman mirrorlist
+    #[default]
+    Pacman,
+
+    /// JSON array of selected mirrors
+    Json,
+}
+
+/// Format of statistics file
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum StatsFormat {
+    #[default]
+    Csv,
+    Json,
+    Tsv,
+}
+
+impl StatsFormat {
+    /// Infer format from file extension, CSV if unknown
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("json") => StatsFormat::Json,
+            Some("tsv") | Some("tab") => StatsFormat::Tsv,
+            _ => StatsFormat::Csv,
+        }
+    }
+}
+
+/// What to do with a selected mirror not serving database file
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Verify {
+    /// Drop the mirror from mirror list
+    Drop,
+
+    /// Fail without writing mirror list
+    Fail,
+}
+
+/// Order of servers in mirror list, independent of how they are selected
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SortBy {
+    /// Highest transfer rate first
+    Rate,
+
+    /// Lowest mirror score first
+    Score,
+
+    /// Lowest delay first
+    Delay,
+
+    /// Most recently synced first
+    Age,
+
+    /// Alphabetically by country, weighted score order within each country
+    Country,
 }
 
 #[allow(dead_code)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct MirrorsStatus {
     cutoff: u64,
     last_check: String,
@@ -42,9 +236,13 @@ pub struct MirrorsStatus {
     check_frequency: u64,
     urls: Mirrors,
     version: u64,
+
+    /// Just downloaded rather than read from a local file or cache
+    #[serde(skip)]
+    fresh: bool,
 }
 
-#[derive(Default, Deserialize, Clone, Debug)]
+#[derive(Default, Deserialize, Serialize, Clone, Debug)]
 pub struct Mirrors(Vec<Mirror>);
 
 #[derive(Default, Deserialize, Serialize, Clone, Debug)]
@@ -53,10 +251,10 @@ pub struct Mirror {
     protocol: String,
     last_sync: Option<String>,
     completion_pct: f64,
-    delay: Option<i64>,
+    pub delay: Option<i64>,
     duration_avg: Option<f64>,
     duration_stddev: Option<f64>,
-    score: Option<f64>,
+    pub score: Option<f64>,
     active: bool,
     pub country: String,
     pub country_code: String,
@@ -66,8 +264,70 @@ pub struct Mirror {
     details: String,
 
     // pacman-mirrorup data
-    transfer_rate: Option<f64>,
+    pub age: Option<i64>,
+    distance: Option<f64>,
+    pub transfer_rate: Option<f64>,
+    pub transfer_rate_stddev: Option<f64>,
+    pub dns_time: Option<f64>,
+    pub connect_time: Option<f64>,
+    pub tls_time: Option<f64>,
+    pub ttfb: Option<f64>,
+    pub transfer_time: Option<f64>,
     weighted_score: Option<f64>,
+    final_url: Option<String>,
+
+    /// `Last-Modified` of target database file served by mirror
+    last_modified: Option<String>,
+
+    /// Whether mirror answers range request of target database with partial content
+    range_support: Option<bool>,
+
+    /// Application protocol negotiated by TLS probe, `h2` if mirror supports HTTP/2
+    alpn: Option<String>,
+
+    /// TLS version negotiated by TLS probe
+    tls_version: Option<String>,
+
+    /// Expiry of mirror's certificate found by TLS probe
+    cert_expiry: Option<String>,
+
+    /// Why mirror's certificate is rejected, e.g. expired or issued for another name
+    cert_error: Option<String>,
+
+    /// Transfer rate over IPv4 only, measured with dual-stack benchmark
+    ipv4_transfer_rate: Option<f64>,
+
+    /// Transfer rate over IPv6 only, measured with dual-stack benchmark
+    ipv6_transfer_rate: Option<f64>,
+
+    /// Address family with higher transfer rate, `IPv4` or `IPv6`
+    faster_family: Option<String>,
+
+    /// Whether mirror has an IPv6 address but IPv6 fails or is far slower than IPv4
+    broken_ipv6: Option<bool>,
+
+    /// CDN serving mirror, e.g. `Fastly`
+    cdn: Option<String>,
+
+    /// Addresses mirror's host resolves to, separated by space
+    addresses: Option<String>,
+
+    /// Origin AS of mirror's first address
+    asn: Option<u32>,
+
+    /// Time to establish TCP connection, measured by `--probe tcp`
+    tcp_connect_time: Option<f64>,
+
+    /// Mean round-trip time of ICMP echo, measured with `ping` feature
+    ping_time: Option<f64>,
+
+    /// Whether addresses of every new benchmark connection were taken from DNS cache,
+    /// so `dns_time` is unknown rather than near zero
+    dns_cached: Option<bool>,
+
+    /// Given by user, always placed first in mirror list
+    #[serde(skip)]
+    pinned: bool,
 }
 
 impl Deref for Mirrors {
@@ -94,417 +354,3060 @@ impl FromIterator<Mirror> for Mirrors {
     }
 }
 
-impl MirrorsStatus {
-    /// Fetch mirrors status from server
-    pub fn from_online_json(url: &str) -> Result<Self> {
-        let response = ureq::get(url)
-            .set("User-Agent", APP_USER_AGENT)
-            .call()
-            .with_context(|| format!("Failed to fetch `{url}`"))?;
+impl Mirrors {
+    /// Collapse mirrors having the same URL, keeping the one with better (lower) score.
+    /// Returns number of removed duplicates.
+    pub fn collapse_duplicates(&mut self) -> usize {
+        let total: usize = self.len();
+        let mut kept: Vec<Mirror> = Vec::with_capacity(total);
+        let mut index: HashMap<String, usize> = HashMap::new();
 
-        let mirrors_status: MirrorsStatus = response
-            .into_json()
-            .context("Failed to deserialize the response body as MirrorsStatus")?;
+        for mirror in self.drain(..) {
+            let key: String = mirror.url_key();
+            match index.get(&key) {
+                Some(&i) => {
+                    warn!("Duplicate mirror `{}` in mirrors status", mirror.url);
+                    let better = match (mirror.score, kept[i].score) {
+                        (Some(new), Some(old)) => new < old,
+                        (Some(_), None) => true,
+                        _ => false,
+                    };
+                    if better {
+                        kept[i] = mirror;
+                    }
+                }
+                None => {
+                    index.insert(key, kept.len());
+                    kept.push(mirror);
+                }
+            }
+        }
 
-        Ok(mirrors_status)
+        self.0 = kept;
+        total - self.len()
     }
-}
 
-pub trait Filter {
-    /// Filter mirror by
-    ///     ==> active
-    ///     ==> protocol(http/https)
-    ///     ==> completion_pct(==1.0)
-    ///     ==> delay(< 3600)
-    fn best_synced_mirrors(
+    /// Apply `f` to each mirror on a few threads, for network lookups
+    fn map_concurrently<T: Default + Clone + Send>(
         &self,
-        max_check: Option<u32>,
-        excluded_mirrors: Option<ExcludedMirrors>,
-    ) -> Result<Mirrors>;
-}
+        f: impl Fn(&Mirror) -> T + Sync,
+    ) -> Vec<T> {
+        const THREADS: usize = 16;
 
-impl Filter for MirrorsStatus {
-    fn best_synced_mirrors(
-        &self,
-        max_check: Option<u32>,
-        excluded_mirrors: Option<ExcludedMirrors>,
-    ) -> Result<Mirrors> {
-        // Primary filter
-        let mut mirrors: Mirrors = self
-            .urls
-            .iter()
-            .filter(|m| m.active)
-            .filter(|m| m.protocol == "http" || m.protocol == "https")
-            .filter(|m| (m.completion_pct - 1.0_f64).abs() < f64::EPSILON)
-            .filter(|m| match m.delay {
-                Some(d) => d < 3600,
-                None => false,
-            })
-            .cloned()
-            .collect();
+        let mut results: Vec<T> = vec![T::default(); self.len()];
+        let chunk: usize = self.len().div_ceil(THREADS).max(1);
+        std::thread::scope(|scope| {
+            for (mirrors, results) in self.chunks(chunk).zip(results.chunks_mut(chunk)) {
+                let f = &f;
+                scope.spawn(move || {
+                    for (mirror, result) in mirrors.iter().zip(results) {
+                        *result = f(mirror);
+                    }
+                });
+            }
+        });
+        results
+    }
 
-        // Secondary filter: excluded mirrors
-        if let Some(exclude) = excluded_mirrors {
-            mirrors.retain(|m| !exclude.is_exclude(m));
+    /// Annotate mirrors with CDN serving them
+    pub fn detect_cdns(&mut self, detect: impl Fn(&str) -> Option<&'static str> + Sync) {
+        let cdns: Vec<Option<&'static str>> = self.map_concurrently(|mirror| detect(&mirror.url));
+        for (mirror, cdn) in self.iter_mut().zip(cdns) {
+            mirror.cdn = cdn.map(str::to_string);
         }
+    }
 
-        // Sort by delay value ascending
-        mirrors.sort_by(|a, b| a.delay.cmp(&b.delay));
-
-        if let Some(max_check) = max_check {
-            // Take only N synced mirrors
-            mirrors.truncate(max_check.try_into()?);
+    /// Record TCP connect time of each mirror measured by `connect`, and keep `keep` mirrors
+    /// connecting the fastest, in that order. Returns number of dropped mirrors.
+    pub fn pre_rank(
+        &mut self,
+        keep: usize,
+        connect: impl Fn(&Mirror) -> Option<f64> + Sync,
+    ) -> usize {
+        let times: Vec<Option<f64>> = self.map_concurrently(connect);
+        for (mirror, time) in self.iter_mut().zip(times) {
+            mirror.tcp_connect_time = time;
         }
+        // Unreachable mirrors last
+        self.sort_by(|a, b| {
+            let time = |m: &Mirror| m.tcp_connect_time.unwrap_or(f64::INFINITY);
+            time(a).total_cmp(&time(b))
+        });
+        let total: usize = self.len();
+        self.truncate(keep);
+        total - self.len()
+    }
 
-        if mirrors.is_empty() {
-            bail!("No best synced mirrors");
+    /// Record ICMP round-trip time of each mirror measured by `ping`
+    #[cfg(feature = "ping")]
+    pub fn measure_ping(&mut self, ping: impl Fn(&Mirror) -> Option<f64> + Sync) {
+        let times: Vec<Option<f64>> = self.map_concurrently(ping);
+        for (mirror, time) in self.iter_mut().zip(times) {
+            mirror.ping_time = time;
         }
-
-        Ok(mirrors)
     }
-}
-
-trait Benchmark {
-    /// Measure time (in seconds) it took to connect (from user's geography)
-    /// and retrive the '[core,extra]/os/x86_64/[core,extra].db' file from the given URL.
-    fn measure_duration(&mut self, target_db: TargetDb) -> Result<()>;
-}
-
-impl Benchmark for Mirror {
-    fn measure_duration(&mut self, target_db: TargetDb) -> Result<()> {
-        let url: Url = Url::parse(&self.url)?;
-        let url: Url = match target_db {
-            TargetDb::Core => url.join("core/os/x86_64/core.db")?,
-            TargetDb::Extra => url.join("extra/os/x86_64/extra.db")?,
-        };
-
-        self.transfer_rate = None;
 
-        let start = Instant::now();
-        let response = ureq::get(url.as_str())
-            .set("User-Agent", APP_USER_AGENT)
-            .timeout(Duration::from_secs(10))
-            .call()
-            .with_context(|| format!("Failed to fetch `{url}`"))?;
+    /// Record addresses of mirrors and AS number of their first address
+    pub fn resolve_network(
+        &mut self,
+        resolve: impl Fn(&str) -> Vec<IpAddr> + Sync,
+        asn: impl Fn(&IpAddr) -> Option<u32> + Sync,
+    ) {
+        let networks: Vec<(Vec<IpAddr>, Option<u32>)> = self.map_concurrently(|mirror| {
+            let addrs: Vec<IpAddr> = resolve(&mirror.url);
+            let asn: Option<u32> = addrs.first().and_then(&asn);
+            (addrs, asn)
+        });
+        for (mirror, (addrs, asn)) in self.iter_mut().zip(networks) {
+            mirror.addresses = (!addrs.is_empty()).then(|| {
+                addrs
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            });
+            mirror.asn = asn;
+        }
+    }
 
-        // If success
-        if 300 > response.status() && response.status() >= 200 {
-            let transfer_time: f64 = start.elapsed().as_secs_f64();
+    /// Collapse mirrors whose hosts resolve to the same set of addresses, keeping HTTPS
+    /// over HTTP, then the earlier mirror. Mirrors not resolving to any address are kept.
+    /// Returns number of removed mirrors.
+    pub fn collapse_same_addresses(
+        &mut self,
+        resolve: impl Fn(&str) -> Vec<IpAddr> + Sync,
+    ) -> usize {
+        let addrs: Vec<Vec<IpAddr>> = self.map_concurrently(|mirror| {
+            let mut addrs: Vec<IpAddr> = resolve(&mirror.url);
+            addrs.sort();
+            addrs.dedup();
+            addrs
+        });
 
-            let file_size: f64 = match response.header("Content-Length") {
-                Some(cl) => cl.parse()?,
+        let total: usize = self.len();
+        let is_https = |mirror: &Mirror| mirror.url.starts_with("https://");
+        let mut kept: Vec<Mirror> = Vec::with_capacity(total);
+        let mut index: HashMap<Vec<IpAddr>, usize> = HashMap::new();
+        for (mirror, addrs) in self.drain(..).zip(addrs) {
+            if addrs.is_empty() {
+                kept.push(mirror);
+                continue;
+            }
+            match index.get(&addrs) {
+                Some(&i) => {
+                    info!(
+                        "`{}` and `{}` resolve to the same addresses",
+                        kept[i].url, mirror.url
+                    );
+                    if is_https(&mirror) && !is_https(&kept[i]) {
+                        kept[i] = mirror;
+                    }
+                }
                 None => {
-                    debug!("Transfer Rate: {url} => None");
-                    return Ok(());
+                    index.insert(addrs, kept.len());
+                    kept.push(mirror);
                 }
-            };
+            }
+        }
 
-            let transfer_rate = file_size / transfer_time;
-            self.transfer_rate = Some(transfer_rate);
-            debug!("Transfer Rate: {url} => {transfer_rate}");
-        } else {
-            debug!("Transfer Rate: {url} => None");
+        self.0 = kept;
+        total - self.len()
+    }
+
+    /// Reorder selected mirrors for output.
+    /// Mirrors missing the sort key are placed last, ties keep their current order.
+    pub fn sort_output(&mut self, by: SortBy) {
+        match by {
+            SortBy::Rate => self.sort_by(|a, b| {
+                let aa: f64 = a.transfer_rate.unwrap_or(f64::NEG_INFINITY);
+                let bb: f64 = b.transfer_rate.unwrap_or(f64::NEG_INFINITY);
+                bb.total_cmp(&aa)
+            }),
+            SortBy::Score => self.sort_by(|a, b| {
+                let aa: f64 = a.score.unwrap_or(f64::INFINITY);
+                let bb: f64 = b.score.unwrap_or(f64::INFINITY);
+                aa.total_cmp(&bb)
+            }),
+            SortBy::Delay => self.sort_by_key(|m| m.delay.unwrap_or(i64::MAX)),
+            SortBy::Age => self.sort_by_key(|m| m.age.unwrap_or(i64::MAX)),
+            SortBy::Country => self.sort_by_key(|m| m.country.to_lowercase()),
         }
+    }
 
-        Ok(())
+    /// Set distance (in kilometers) of each mirror's country from origin
+    pub fn update_distance(&mut self, origin: &Coordinates) {
+        for mirror in self.iter_mut() {
+            mirror.distance =
+                Coordinates::of_country(&mirror.country_code).map(|c| origin.distance(&c));
+        }
     }
-}
 
-impl Benchmark for Mirrors {
-    fn measure_duration(&mut self, target_db: TargetDb) -> Result<()> {
-        self.par_iter_mut().for_each(|mirror| {
-            if let Err(err) = mirror
-                .measure_duration(target_db)
-                .context("Failed to measure transfer rate")
-            {
-                info!("{err:#}");
+    /// Keep n mirrors, taking at most one per network key in ranked order first,
+    /// then filling up with the next best mirrors. Ranked order is preserved.
+    fn spread_select_by<K, F>(&mut self, n: usize, mut key: F)
+    where
+        K: Eq + std::hash::Hash,
+        F: FnMut(&Mirror) -> K,
+    {
+        let mut seen: HashSet<K> = HashSet::new();
+        let mut picked: Vec<bool> = vec![false; self.len()];
+        let mut count: usize = 0;
+        for (i, mirror) in self.iter().enumerate() {
+            if count >= n {
+                break;
             }
-        });
+            if seen.insert(key(mirror)) {
+                picked[i] = true;
+                count += 1;
+            }
+        }
+        for p in picked.iter_mut() {
+            if count >= n {
+                break;
+            }
+            if !*p {
+                *p = true;
+                count += 1;
+            }
+        }
 
-        Ok(())
+        let mut picked = picked.into_iter();
+        self.retain(|_| picked.next().unwrap_or(false));
     }
-}
-
-pub trait Statistics {
-    /// Calculate weighted score
-    fn score(&mut self);
 
-    /// Sort descending order by weighted score
-    fn sort_by_weighted_score(&mut self);
+    /// Drop mirrors serving target database older than `reference` by more than `max_lag`,
+    /// returns number of dropped mirrors. Mirrors of unknown `Last-Modified` are kept.
+    pub fn drop_stale_content(&mut self, reference: DateTime<Utc>, max_lag: TimeDelta) -> usize {
+        let before: usize = self.len();
+        self.retain(|mirror| match mirror.last_modified() {
+            Some(modified) if reference - modified > max_lag => {
+                info!(
+                    "Drop `{}` serving database modified at {modified}, older than reference {reference}",
+                    mirror.url
+                );
+                false
+            }
+            _ => true,
+        });
+        before - self.len()
+    }
 
-    /// Select n mirrors
-    fn select(&mut self, n: u32);
+    /// `Last-Modified` of target database of the fastest mirror that reports it
+    pub fn fastest_last_modified(&self) -> Option<DateTime<Utc>> {
+        self.iter()
+            .filter(|mirror| mirror.last_modified.is_some())
+            .filter_map(|mirror| Some((mirror.transfer_rate?, mirror)))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .and_then(|(_, mirror)| mirror.last_modified())
+    }
 
-    /// Save evaluated mirrors to CSV file
-    fn to_csv(&self, path: &Path) -> Result<()>;
-}
+    /// Replace mirror's URL with its redirect target found during benchmark
+    pub fn rewrite_redirected(&mut self, target_db: TargetDb, layout: &Layout) {
+        for mirror in self.iter_mut() {
+            if let Some(base) = mirror.redirected_base(target_db, layout) {
+                info!("Rewrite `{}` to `{base}`", mirror.url);
+                mirror.url = base;
+            }
+        }
+    }
 
-impl Statistics for Mirrors {
-    fn score(&mut self) {
-        // According to [Mirror Status](https://archlinux.org/mirrors/status/) for Mirror Score: lower is better,
-        // while transfer_score: higher is better. The weighting cannot apply directly. The mirror scores are needed
-        // to reverse using max score as based first.
-        let max_score: f64 = self
-            .iter()
-            .map(|mirror| mirror.score.unwrap_or(f64::NAN))
-            .reduce(f64::max)
-            .unwrap_or(0.0_f64);
+    /// Check that every mirror serves database file, dropping or failing on those that do not
+    pub fn verify(
+        &mut self,
+        agent: &ureq::Agent,
+        target_db: TargetDb,
+        layout: &Layout,
+        mode: Verify,
+    ) -> Result<()> {
+        let failed: Vec<String> = self
+            .par_iter()
+            .filter_map(|mirror| match mirror.verify(agent, target_db, layout) {
+                Ok(()) => None,
+                Err(err) => {
+                    warn!("{err:#}");
+                    Some(mirror.url.clone())
+                }
+            })
+            .collect();
+        if failed.is_empty() {
+            return Ok(());
+        }
 
-        self.iter_mut().for_each(|mirror| {
-            let transfer_rate: f64 = mirror.transfer_rate.unwrap_or(0.0_f64);
-            let score: f64 = mirror.score.unwrap_or(f64::NAN);
-            mirror.weighted_score = Some(transfer_rate * (max_score - score));
-        });
+        if mode == Verify::Fail {
+            bail!("Failed to verify {}", failed.join(", "));
+        }
+        self.retain(|mirror| !failed.contains(&mirror.url));
+        if self.is_empty() {
+            bail!("No mirrors passed verification");
+        }
+        Ok(())
     }
 
-    fn sort_by_weighted_score(&mut self) {
-        self.sort_by(|a, b| {
-            let aa: f64 = a.weighted_score.unwrap_or(0.0_f64);
-            let bb: f64 = b.weighted_score.unwrap_or(0.0_f64);
-            aa.partial_cmp(&bb).unwrap().reverse()
-        });
+    /// Mean transfer rate of mirrors, a failed measurement counts as zero
+    pub fn mean_transfer_rate(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.iter()
+            .map(|mirror| mirror.transfer_rate.unwrap_or(0.0))
+            .sum::<f64>()
+            / self.len() as f64
     }
 
-    fn select(&mut self, n: u32) {
-        self.truncate(n.try_into().expect("u32 to usize"));
+    /// Place pinned mirrors first, in given order, followed by the rest of mirrors.
+    /// Pinned mirrors replace the same mirrors already in the list.
+    pub fn pin(&mut self, urls: &[String]) -> Result<()> {
+        let mut pinned: Vec<Mirror> = Vec::with_capacity(urls.len());
+        for url in urls {
+            let mut url: Url =
+                Url::parse(url).with_context(|| format!("Invalid pinned mirror `{url}`"))?;
+            if !url.path().ends_with('/') {
+                url.set_path(&format!("{}/", url.path()));
+            }
+            let mirror = Mirror {
+                url: url.to_string(),
+                pinned: true,
+                ..Default::default()
+            };
+            if !pinned.iter().any(|m| m.url_key() == mirror.url_key()) {
+                pinned.push(mirror);
+            }
+        }
+
+        self.retain(|mirror| !pinned.iter().any(|m| m.url_key() == mirror.url_key()));
+        self.splice(0..0, pinned);
+        Ok(())
     }
 
-    fn to_csv(&self, path: &Path) -> Result<()> {
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(path)
-            .with_context(|| format!("Could not create file `{}`", path.display()))?;
+    /// Active servers of mirror list, grouped by country if asked
+    fn servers(&self, options: &MirrorListOptions) -> Result<String> {
+        let mut list = String::new();
+        if options.flavor == Flavor::Manjaro
+            && options.template.is_none()
+            && options.server_line.is_none()
+        {
+            // Same as mirror list of pacman-mirrors
+            for (i, mirror) in self.iter().enumerate() {
+                if i > 0 {
+                    writeln!(&mut list)?;
+                }
+                let country: &str = match mirror.country.as_str() {
+                    "" => "Worldwide",
+                    country => country,
+                };
+                writeln!(&mut list, "## Country : {country}")?;
+                writeln!(&mut list, "{}", mirror.to_pacman_mirror_list(options)?)?;
+            }
+            return Ok(list);
+        }
+
+        if !options.group_by_country {
+            for mirror in self.iter() {
+                writeln!(&mut list, "{}", mirror.to_pacman_mirror_list(options)?)?;
+            }
+            return Ok(list);
+        }
 
-        let mut wtr = csv::Writer::from_writer(file);
+        // Countries are ordered by their best mirror, mirrors keep their order within a country
+        let mut groups: Vec<(&str, Vec<&Mirror>)> = Vec::new();
         for mirror in self.iter() {
-            wtr.serialize(mirror)?;
+            let group: &str = match (mirror.pinned, mirror.country.as_str()) {
+                (true, _) => "Pinned",
+                (false, "") => "Worldwide",
+                (false, country) => country,
+            };
+            match groups.iter_mut().find(|(g, _)| *g == group) {
+                Some((_, mirrors)) => mirrors.push(mirror),
+                None => groups.push((group, vec![mirror])),
+            }
         }
-        wtr.flush()?;
 
-        Ok(())
+        for (i, (country, mirrors)) in groups.iter().enumerate() {
+            if i > 0 {
+                writeln!(&mut list)?;
+            }
+            writeln!(&mut list, "## {country}")?;
+            for mirror in mirrors {
+                writeln!(&mut list, "{}", mirror.to_pacman_mirror_list(options)?)?;
+            }
+        }
+        Ok(list)
     }
 }
 
-pub trait Evaluation {
-    /// Returns the n best mirrors based on mirror score
-    fn evaluate(&self, n: u32, target_db: TargetDb) -> Result<Mirrors>;
+/// Read mirror URLs from file, one per line.
+/// Empty lines and lines starting with `#` or `;` are ignored.
+pub fn read_urls(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not open `{}`", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+        .map(str::to_string)
+        .collect())
 }
 
-impl Evaluation for Mirrors {
-    fn evaluate(&self, n: u32, target_db: TargetDb) -> Result<Mirrors> {
-        let mut mirrors: Mirrors = self.clone();
-        let _ = mirrors.measure_duration(target_db);
-        mirrors.score();
-        mirrors.sort_by_weighted_score();
-        mirrors.select(n);
-
-        if mirrors.is_empty() {
-            bail!("No best mirrors");
+impl Mirror {
+    /// Mirror known only by its base URL, e.g. a server of an existing mirrorlist
+    pub fn from_url(url: &str) -> Self {
+        let url: String = if url.ends_with('/') {
+            url.to_string()
+        } else {
+            format!("{url}/")
+        };
+        Self {
+            protocol: Url::parse(&url)
+                .map(|u| u.scheme().to_string())
+                .unwrap_or_default(),
+            url,
+            active: true,
+            ..Default::default()
         }
-
-        Ok(mirrors)
     }
-}
-
-pub trait ToPacmanMirrorList {
-    /// Convert to pacman mirror list format
-    fn to_pacman_mirror_list(&self) -> Result<String>;
 
-    /// Write to mirrorlist file
-    fn to_mirrorlist_file(&self, path: &Path, source_url: &str) -> Result<()>;
+    /// Mirror at `url` benchmarked at `transfer_rate`, shared by tests
+    #[cfg(test)]
+    pub fn with_transfer_rate(url: &str, transfer_rate: Option<f64>) -> Self {
+        Self {
+            transfer_rate,
+            ..Self::from_url(url)
+        }
+    }
 
-    fn header(&self, source_url: &str) -> Result<String> {
-        let now = chrono::Local::now();
-        Ok(format!(
-            "\
-            #\n\
-            # /etc/pacman.d/mirrorlist\n\
-            #\n\
-            #\n\
-            # Arch Linux mirrorlist generated by pacman-mirrorup\n\
-            #\n\
-            # pacman-mirrorup: https://github.com/bpetlert/pacman-mirrorup\n\
-            # source: {}\n\
-            # when: {}\n\
-            #\n\
-            \n\
-            ",
-            source_url,
-            now.to_rfc2822()
-        ))
+    /// Mirror of a derivative's status, which only tells whether it is in sync and when it synced
+    pub fn from_derivative(
+        url: &str,
+        country: &str,
+        last_sync: Option<DateTime<Utc>>,
+        in_sync: bool,
+    ) -> Self {
+        let mut mirror = Self::from_url(url);
+        mirror.country = country.to_string();
+        mirror.last_sync = last_sync.map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true));
+        mirror.completion_pct = if in_sync { 1.0 } else { 0.0 };
+        // Mirror in sync does not lag behind
+        mirror.delay = in_sync.then_some(0);
+        mirror
     }
-}
 
-impl ToPacmanMirrorList for Mirror {
-    fn to_pacman_mirror_list(&self) -> Result<String> {
-        Ok(format!("Server = {url}$repo/os/$arch", url = self.url))
+    /// Primary filter of a single mirror, see [`Filter::best_synced_mirrors`]
+    pub fn is_synced(&self, clock: &ReferenceClock) -> bool {
+        self.is_active() && self.stale_reason(clock).is_none()
     }
 
-    fn to_mirrorlist_file(&self, _path: &Path, _source_url: &str) -> Result<()> {
-        unreachable!()
+    /// Active and served over HTTP(S), so it can be benchmarked
+    pub fn is_active(&self) -> bool {
+        self.active && (self.protocol == "http" || self.protocol == "https")
     }
-}
 
-impl ToPacmanMirrorList for Mirrors {
-    fn to_pacman_mirror_list(&self) -> Result<String> {
-        let mut list = String::new();
-        for mirror in self.iter() {
-            writeln!(&mut list, "{}", mirror.to_pacman_mirror_list()?)?;
+    /// Why mirror fails sync conditions of primary filter, None if it is synced
+    pub fn stale_reason(&self, clock: &ReferenceClock) -> Option<String> {
+        if (self.completion_pct - 1.0_f64).abs() >= f64::EPSILON {
+            return Some(format!("completion {:.1}%", self.completion_pct * 100.0));
+        }
+        match self.delay {
+            Some(d) if d < 3600 => {}
+            Some(d) => return Some(format!("delay {d} s")),
+            None => return Some("no delay".to_string()),
+        }
+        match self.last_sync.as_deref().map(parse_timestamp) {
+            Some(Ok(t)) if clock.is_ahead(t) => Some(format!("last sync {t} is in the future")),
+            // Mirrors without (valid) last sync are judged by completion and delay only
+            _ => None,
+        }
+    }
+
+    /// Normalized URL used to detect duplicate mirrors
+    pub fn url_key(&self) -> String {
+        match Url::parse(&self.url) {
+            Ok(url) => url.as_str().trim_end_matches('/').to_string(),
+            Err(_) => self.url.trim_end_matches('/').to_lowercase(),
+        }
+    }
+
+    /// Set age (in seconds) of last sync relative to reference clock
+    pub fn update_age(&mut self, clock: &ReferenceClock) {
+        self.age = self.sync_age(clock).map(|age| age.num_seconds());
+    }
+
+    /// Time since last sync relative to reference clock
+    fn sync_age(&self, clock: &ReferenceClock) -> Option<TimeDelta> {
+        match self.last_sync.as_deref().map(parse_timestamp) {
+            Some(Ok(t)) => Some(clock.age(t)),
+            _ => None,
+        }
+    }
+
+    pub fn transfer_rate(&self) -> Option<f64> {
+        self.transfer_rate
+    }
+
+    pub fn weighted_score(&self) -> Option<f64> {
+        self.weighted_score
+    }
+
+    /// Request headers of database file, succeeds if mirror responds with 200 OK
+    pub fn verify(&self, agent: &ureq::Agent, target_db: TargetDb, layout: &Layout) -> Result<()> {
+        self.verify_file(agent, &target_db.path(layout))
+    }
+
+    /// Request headers of file at `path` relative to mirror's URL,
+    /// succeeds if mirror responds with 200 OK
+    fn verify_file(&self, agent: &ureq::Agent, path: &str) -> Result<()> {
+        let url: Url = Url::parse(&self.url)?.join(path)?;
+        let response = agent
+            .head(url.as_str())
+            .timeout(Duration::from_secs(10))
+            .call()
+            .with_context(|| format!("Failed to verify `{url}`"))?;
+        if response.status() != 200 {
+            bail!("Failed to verify `{url}`: status {}", response.status());
+        }
+        Ok(())
+    }
+
+    /// Everything known about mirror as label and value, values not known are skipped
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let secs = |value: Option<f64>| value.map(|v| format!("{v:.3} s"));
+        let yes_no = |value: bool| Some(if value { "yes" } else { "no" }.to_string());
+        let rate: Option<String> = self
+            .transfer_rate
+            .map(|rate| match self.transfer_rate_stddev {
+                Some(stddev) => format!(
+                    "{} ± {}",
+                    report::human_rate(rate),
+                    report::human_rate(stddev)
+                ),
+                None => report::human_rate(rate),
+            });
+
+        [
+            ("URL", Some(self.url.clone())),
+            ("Protocol", Some(self.protocol.clone())),
+            (
+                "Country",
+                Some(format!("{} ({})", self.country, self.country_code)),
+            ),
+            ("Active", yes_no(self.active)),
+            ("Last sync", self.last_sync.clone()),
+            ("Age", self.age.map(|age| format!("{age} s"))),
+            ("Delay", self.delay.map(|delay| format!("{delay} s"))),
+            (
+                "Completion",
+                Some(format!("{:.1}%", self.completion_pct * 100.0)),
+            ),
+            ("Score", self.score.map(|score| format!("{score:.2}"))),
+            ("Duration avg", secs(self.duration_avg)),
+            ("Duration stddev", secs(self.duration_stddev)),
+            ("ISOs", yes_no(self.isos)),
+            ("IPv4", yes_no(self.ipv4)),
+            ("IPv6", yes_no(self.ipv6)),
+            (
+                "Details",
+                Some(self.details.clone()).filter(|d| !d.is_empty()),
+            ),
+            ("Transfer rate", rate),
+            (
+                "DNS",
+                match self.dns_cached {
+                    Some(true) => Some("cached".to_string()),
+                    _ => secs(self.dns_time),
+                },
+            ),
+            ("Connect", secs(self.connect_time)),
+            ("TLS", secs(self.tls_time)),
+            ("TTFB", secs(self.ttfb)),
+            ("Final URL", self.final_url.clone()),
+            ("Range requests", self.range_support.and_then(yes_no)),
+            ("ALPN", self.alpn.clone()),
+            ("TLS version", self.tls_version.clone()),
+            ("Certificate expiry", self.cert_expiry.clone()),
+            ("Certificate error", self.cert_error.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(label, value)| Some((label, value?)))
+        .collect()
+    }
+
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.last_modified
+            .as_deref()
+            .and_then(|t| parse_timestamp(t).ok())
+    }
+
+    /// Mirror's base URL derived from final URL of benchmark,
+    /// returns None if there is no redirect.
+    pub fn redirected_base(&self, target_db: TargetDb, layout: &Layout) -> Option<String> {
+        let final_url: &str = self.final_url.as_deref()?;
+        // Final URL is of database file or of benchmark package in the same directory
+        let (dir_url, _) = final_url.rsplit_once('/')?;
+        let base: &str = dir_url.strip_suffix(&target_db.dir(layout))?;
+        let requested: Url = Url::parse(&self.url).ok()?;
+        if base == requested.as_str() {
+            return None;
+        }
+        Some(base.to_string())
+    }
+}
+
+impl MirrorsStatus {
+    /// Mirrors status of a derivative, generated at `last_check`
+    pub fn from_mirrors(urls: Mirrors, last_check: DateTime<Utc>) -> Self {
+        Self {
+            cutoff: 3600,
+            last_check: last_check.to_rfc3339_opts(SecondsFormat::Secs, true),
+            num_checks: 0,
+            check_frequency: 0,
+            urls,
+            version: 0,
+            fresh: false,
+        }
+    }
+
+    /// Fetch mirrors status from server, or read it from local file
+    pub fn from_online_json(agent: &ureq::Agent, url: &str) -> Result<Self> {
+        let _phase = info_span!("fetch", source = url).entered();
+        let source: Source = Source::parse(url)?;
+        let reader = source.open(agent)?;
+
+        let mut mirrors_status: MirrorsStatus = serde_json::from_reader(reader)
+            .context("Failed to deserialize the response body as MirrorsStatus")?;
+        mirrors_status.fresh = source.is_remote();
+
+        Ok(mirrors_status)
+    }
+
+    /// Fetch mirrors status from the first source that works, returns it with the source used
+    pub fn from_sources<'a>(agent: &ureq::Agent, urls: &'a [String]) -> Result<(Self, &'a str)> {
+        source::first_ok(urls, |url| {
+            Self::from_online_json(agent, url)
+                .with_context(|| format!("Failed to fetch mirrors status from `{url}`"))
+        })
+    }
+
+    /// Fetch mirrors status from every source and merge them in order
+    pub fn from_all_sources(agent: &ureq::Agent, urls: &[String]) -> Result<Self> {
+        let mut merged: Option<Self> = None;
+        for url in urls {
+            let status: Self = Self::from_online_json(agent, url)
+                .with_context(|| format!("Failed to fetch mirrors status from `{url}`"))?;
+            match merged.as_mut() {
+                Some(merged) => merged.merge(status),
+                None => merged = Some(status),
+            }
+        }
+        merged.context("No mirrors status source")
+    }
+
+    /// Add mirrors of another mirrors status, overriding mirrors with the same URL.
+    /// Check time and cutoff of this mirrors status are kept.
+    pub fn merge(&mut self, other: MirrorsStatus) {
+        let mut index: HashMap<String, usize> = self
+            .urls
+            .iter()
+            .enumerate()
+            .map(|(i, mirror)| (mirror.url_key(), i))
+            .collect();
+        for mirror in other.urls.0 {
+            match index.get(&mirror.url_key()) {
+                Some(&i) => self.urls[i] = mirror,
+                None => {
+                    index.insert(mirror.url_key(), self.urls.len());
+                    self.urls.push(mirror);
+                }
+            }
+        }
+    }
+
+    /// Run summary of mirrors status: fetched mirrors, synced ones (passing primary filter),
+    /// and synced ones excluded
+    pub fn summary(&self, options: &FilterOptions) -> Summary {
+        let clock: ReferenceClock = self.reference_clock(options.clock_skew_tolerance);
+        let synced: Mirrors = self
+            .urls
+            .iter()
+            .filter(|m| m.is_synced(&clock))
+            .cloned()
+            .collect();
+        options.prefetch_addresses(&synced);
+        let excluded: usize = match &options.excluded_mirrors {
+            Some(exclude) => synced.iter().filter(|m| exclude.is_exclude(m)).count(),
+            None => 0,
+        };
+        Summary {
+            fetched: Some(self.urls.len()),
+            synced: Some(synced.len()),
+            excluded: Some(excluded),
+            ..Default::default()
+        }
+    }
+
+    pub fn mirrors(&self) -> &Mirrors {
+        &self.urls
+    }
+
+    /// Time mirrors status was generated
+    pub fn generated_at(&self) -> Result<DateTime<Utc>> {
+        parse_timestamp(&self.last_check)
+    }
+
+    /// Seconds since last sync after which a mirror is out of sync
+    pub fn cutoff(&self) -> u64 {
+        self.cutoff
+    }
+
+    /// Create reference clock for freshness comparisons
+    pub fn reference_clock(&self, tolerance: Duration) -> ReferenceClock {
+        ReferenceClock::from_status(Some(&self.last_check), self.cutoff, tolerance, self.fresh)
+    }
+}
+
+#[derive(Debug)]
+pub struct FilterOptions {
+    /// Maximum number of synced mirrors to check
+    pub max_check: Option<u32>,
+
+    pub excluded_mirrors: Option<ExcludedMirrors>,
+
+    /// Only mirrors matching this allowlist, None for all
+    pub included_mirrors: Option<IncludedMirrors>,
+
+    /// Tolerated difference between local clock and mirrors status's clock
+    pub clock_skew_tolerance: Duration,
+
+    /// Maximum time since last sync
+    pub max_age: Option<Duration>,
+
+    /// Only mirrors reachable over IPv4
+    pub ipv4: bool,
+
+    /// Only mirrors reachable over IPv6
+    pub ipv6: bool,
+
+    /// Only mirrors hosting ISO images
+    pub isos: bool,
+
+    /// Only mirrors in these countries or country codes (case-insensitive), empty for all
+    pub countries: Vec<String>,
+    pub country_codes: Vec<String>,
+
+    /// Check randomly sampled mirrors instead of `max_check` mirrors with the lowest delay
+    pub sample: Option<Sampling>,
+
+    /// Keep only one of mirrors whose hosts resolve to the same addresses
+    pub dedupe_by_ip: bool,
+}
+
+/// Random sampling of synced mirrors
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    /// Number of mirrors to sample
+    pub n: u32,
+
+    /// Take mirrors from each country in turn
+    pub by_country: bool,
+
+    /// Seed of random order, the same seed samples the same mirrors
+    pub seed: u64,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        Self {
+            max_check: None,
+            excluded_mirrors: None,
+            included_mirrors: None,
+            clock_skew_tolerance: Duration::from_secs(DEFAULT_CLOCK_SKEW_TOLERANCE),
+            max_age: None,
+            ipv4: false,
+            ipv6: false,
+            isos: false,
+            countries: Vec::new(),
+            country_codes: Vec::new(),
+            sample: None,
+            dedupe_by_ip: false,
+        }
+    }
+}
+
+impl FilterOptions {
+    /// Resolve addresses of mirrors, and their origin AS if needed, on a few threads ahead
+    /// of `accepts`, when excluded or included mirrors have ASN or CIDR patterns.
+    /// Results are cached, so matching patterns afterwards does not wait on each mirror in turn.
+    pub fn prefetch_addresses(&self, mirrors: &Mirrors) {
+        let lists = || {
+            self.excluded_mirrors
+                .iter()
+                .chain(self.included_mirrors.iter())
+        };
+        if !lists().any(ExcludedMirrors::has_address_patterns) {
+            return;
+        }
+        let asn: bool = lists().any(ExcludedMirrors::has_asn_patterns);
+        mirrors.map_concurrently(|mirror| {
+            for ip in netinfo::resolve(&mirror.url) {
+                if asn {
+                    netinfo::asn(&ip);
+                }
+            }
+        });
+    }
+
+    /// Returns true if mirror passes all filters
+    pub fn accepts(&self, mirror: &Mirror, clock: &ReferenceClock) -> bool {
+        // Primary filter
+        if !mirror.is_synced(clock) {
+            return false;
+        }
+
+        // Secondary filter: IP capabilities
+        if (self.ipv4 && !mirror.ipv4) || (self.ipv6 && !mirror.ipv6) {
+            return false;
+        }
+
+        // Secondary filter: included countries
+        if (!self.countries.is_empty() || !self.country_codes.is_empty())
+            && !self
+                .countries
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&mirror.country))
+            && !self
+                .country_codes
+                .iter()
+                .any(|cc| cc.eq_ignore_ascii_case(&mirror.country_code))
+        {
+            return false;
+        }
+
+        // Secondary filter: included mirrors
+        if let Some(include) = &self.included_mirrors {
+            if !include.is_include(mirror) {
+                return false;
+            }
+        }
+
+        // Secondary filter: ISO images
+        if self.isos && !mirror.isos {
+            return false;
+        }
+
+        // Secondary filter: sync age
+        if let Some(max_age) = self.max_age {
+            match mirror.sync_age(clock).map(|age| age.to_std()) {
+                Some(Ok(age)) if age <= max_age => {}
+                _ => return false,
+            }
+        }
+
+        // Secondary filter: excluded mirrors
+        if let Some(exclude) = &self.excluded_mirrors {
+            if exclude.is_exclude(mirror) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+pub trait Filter {
+    /// Filter mirror by
+    ///     ==> active
+    ///     ==> protocol(http/https)
+    ///     ==> completion_pct(==1.0)
+    ///     ==> delay(< 3600)
+    ///     ==> last_sync(not ahead of reference clock)
+    ///     ==> last_sync(within max_age, if given)
+    ///     ==> ipv4/ipv6(if required)
+    ///     ==> isos(if required)
+    ///     ==> country/country_code(if given)
+    ///     ==> included mirrors(if given)
+    fn best_synced_mirrors(&self, options: &FilterOptions) -> Result<Mirrors>;
+}
+
+impl Filter for MirrorsStatus {
+    fn best_synced_mirrors(&self, options: &FilterOptions) -> Result<Mirrors> {
+        let clock: ReferenceClock = self.reference_clock(options.clock_skew_tolerance);
+        debug!("Reference clock: {}", clock.now());
+
+        let mut candidates: Mirrors = self.urls.clone();
+        let duplicates: usize = candidates.collapse_duplicates();
+        if duplicates > 0 {
+            debug!("Removed {duplicates} duplicate mirror(s) from mirrors status");
+        }
+        candidates.retain(|m| m.is_synced(&clock));
+        options.prefetch_addresses(&candidates);
+
+        let mut mirrors: Mirrors = candidates
+            .iter()
+            .filter(|m| options.accepts(m, &clock))
+            .cloned()
+            .map(|mut m| {
+                m.update_age(&clock);
+                m
+            })
+            .collect();
+
+        // Sort by delay value ascending
+        mirrors.sort_by_key(|m| m.delay);
+
+        if options.dedupe_by_ip {
+            let collapsed: usize = mirrors.collapse_same_addresses(netinfo::resolve);
+            debug!("Removed {collapsed} mirror(s) sharing addresses with another mirror");
+        }
+
+        if let Some(sampling) = &options.sample {
+            let picked: Vec<usize> =
+                schedule::sample(&mirrors, sampling.n.try_into()?, sampling.by_country, |m| {
+                    tie_breaker(sampling.seed, &m.url)
+                });
+            mirrors = picked.into_iter().map(|i| mirrors[i].clone()).collect();
+        } else if let Some(max_check) = options.max_check {
+            // Take only N synced mirrors
+            mirrors.truncate(max_check.try_into()?);
+        }
+
+        if mirrors.is_empty() {
+            bail!("No best synced mirrors");
+        }
+
+        Ok(mirrors)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkOptions {
+    pub target_db: TargetDb,
+
+    /// Databases measured after `target_db`, whose transfer rates are combined with it
+    pub other_dbs: Vec<TargetDb>,
+
+    /// How transfer rates of databases are combined, `min` for the worst case
+    pub db_aggregate: Aggregate,
+
+    /// Package downloaded in place of `target_db` file
+    pub package: Option<BenchmarkPackage>,
+
+    /// Mirrors not serving signatures of databases are not measured
+    pub require_sig: bool,
+
+    /// Mirrors not serving files databases are not measured
+    pub require_files_db: bool,
+
+    /// Record whether mirror honors range request
+    pub probe_range: bool,
+
+    /// Mirrors not honoring range request are not measured, implies `probe_range`
+    pub require_range: bool,
+
+    /// Handshake with HTTPS mirrors to find what they negotiate, e.g. HTTP/2
+    pub probe_tls: bool,
+
+    /// Mirrors not negotiating at least this TLS version, including plain HTTP mirrors,
+    /// are not measured
+    pub min_tls: Option<TlsVersion>,
+
+    /// Mirrors whose certificate is invalid or expires within this number of days are not measured
+    pub min_cert_days: Option<u32>,
+
+    /// Address family `agent` connects over, None for any
+    pub family: Option<IpFamily>,
+
+    /// Agents connecting over a single address family, each measuring target database
+    /// once more, empty unless dual-stack benchmark
+    pub family_agents: Vec<(IpFamily, ureq::Agent)>,
+
+    /// Where database file is under mirror's URL
+    pub layout: Layout,
+
+    /// Maximum number of bytes of database file to download, None for whole file
+    pub transfer_limit: Option<u64>,
+
+    /// Number of times database file is downloaded from each mirror
+    pub samples: u32,
+
+    /// How transfer rate samples are combined
+    pub aggregate: Aggregate,
+
+    /// Transfer rate samples more than this number of median absolute deviations
+    /// from the median are discarded before combining
+    pub reject_outliers: Option<f64>,
+
+    /// Rank mirrors from mirrors status only, without downloading from any mirror
+    pub skip: bool,
+
+    /// Minimum time between requests of each thread
+    pub request_delay: Duration,
+
+    /// Reduce concurrent transfers when they contend for local link
+    pub adaptive: bool,
+
+    pub agent: ureq::Agent,
+}
+
+impl BenchmarkOptions {
+    pub fn new(target_db: TargetDb, agent: ureq::Agent) -> Self {
+        Self {
+            target_db,
+            other_dbs: Vec::new(),
+            db_aggregate: Aggregate::default(),
+            package: None,
+            require_sig: false,
+            require_files_db: false,
+            probe_range: false,
+            require_range: false,
+            probe_tls: false,
+            min_tls: None,
+            min_cert_days: None,
+            family: None,
+            family_agents: Vec::new(),
+            layout: Layout::default(),
+            transfer_limit: None,
+            samples: 1,
+            aggregate: Aggregate::default(),
+            reject_outliers: None,
+            skip: false,
+            request_delay: Duration::ZERO,
+            adaptive: false,
+            agent,
+        }
+    }
+
+    /// Files every mirror must serve to be measured
+    fn required_files(&self) -> Vec<String> {
+        let dbs = || std::iter::once(&self.target_db).chain(&self.other_dbs);
+        let sigs = dbs()
+            .filter(|_| self.require_sig)
+            .map(|db| format!("{}.sig", db.path(&self.layout)));
+        let files_dbs = dbs()
+            .filter(|_| self.require_files_db)
+            .map(|db| db.files_path(&self.layout));
+        sigs.chain(files_dbs).collect()
+    }
+
+    /// Database files downloaded, as recorded in cache and history
+    pub fn target(&self) -> String {
+        let target: String = match &self.package {
+            Some(package) => format!(
+                "{}/package of {} bytes",
+                self.target_db.dir(&self.layout),
+                package.size()
+            ),
+            None => std::iter::once(&self.target_db)
+                .chain(&self.other_dbs)
+                .map(|db| db.path(&self.layout))
+                .collect::<Vec<String>>()
+                .join(","),
+        };
+        match self.family {
+            Some(family) => format!("{target} over {}", family.name()),
+            None => target,
+        }
+    }
+}
+
+/// Read response body up to `limit` bytes, returns number of bytes read.
+///
+/// When the read times out after some data has arrived, the partial
+/// transfer is still a valid measurement.
+fn read_body(reader: &mut impl Read, limit: Option<u64>) -> io::Result<u64> {
+    let mut buf = [0_u8; 64 * 1024];
+    let mut total: u64 = 0;
+    loop {
+        let want: usize = match limit {
+            Some(limit) if total >= limit => break,
+            Some(limit) => buf
+                .len()
+                .min((limit - total).try_into().unwrap_or(usize::MAX)),
+            None => buf.len(),
+        };
+
+        match reader.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => total += n as u64,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err)
+                if total > 0
+                    && matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+            {
+                debug!("Partial transfer of {total} bytes: {err}");
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Parse `Last-Modified` header value
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// `Last-Modified` of file at `url`, to compare mirrors against
+pub fn fetch_last_modified(agent: &ureq::Agent, url: &Url) -> Result<DateTime<Utc>> {
+    let response = agent
+        .head(url.as_str())
+        .timeout(Duration::from_secs(10))
+        .call()
+        .with_context(|| format!("Failed to fetch `{url}`"))?;
+    response
+        .header("last-modified")
+        .and_then(parse_http_date)
+        .with_context(|| format!("No valid `Last-Modified` of `{url}`"))
+}
+
+/// Length of the longest magic bytes of [`is_archive`]
+const MAGIC_LEN: usize = 6;
+
+/// Whether downloaded file starts like a compressed tar, as databases and packages are
+fn is_archive(head: &[u8]) -> bool {
+    const MAGICS: [&[u8]; 4] = [
+        &[0x1f, 0x8b],                         // gzip
+        &[0x28, 0xb5, 0x2f, 0xfd],             // zstd
+        &[0xfd, b'7', b'z', b'X', b'Z', 0x00], // xz
+        b"BZh",                                // bzip2
+    ];
+    MAGICS.iter().any(|magic| head.starts_with(magic))
+}
+
+fn is_html(head: &[u8]) -> bool {
+    let text: String = String::from_utf8_lossy(head).trim_start().to_lowercase();
+    text.starts_with("<!doctype html") || text.starts_with("<html")
+}
+
+/// Reader keeping the first bytes read, to check what is downloaded
+struct Head<R> {
+    inner: R,
+    head: Vec<u8>,
+}
+
+impl<R> Head<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            head: Vec::with_capacity(MAGIC_LEN),
+        }
+    }
+}
+
+impl<R: Read> Read for Head<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n: usize = self.inner.read(buf)?;
+        let keep: usize = MAGIC_LEN.saturating_sub(self.head.len()).min(n);
+        self.head.extend_from_slice(&buf[..keep]);
+        Ok(n)
+    }
+}
+
+/// IPv6 transfer rate below this fraction of IPv4 one is considered broken routing
+const BROKEN_IPV6_RATIO: f64 = 0.1;
+
+/// Whether IPv6 of mirror is badly broken compared to IPv4, None without IPv4 rate to compare with
+fn is_ipv6_broken(ipv4_rate: Option<f64>, ipv6_rate: Option<f64>, has_ipv6: bool) -> Option<bool> {
+    let ipv4_rate: f64 = ipv4_rate?;
+    Some(has_ipv6 && ipv6_rate.is_none_or(|rate| rate < ipv4_rate * BROKEN_IPV6_RATIO))
+}
+
+pub trait Benchmark {
+    /// Measure transfer rate (in bytes per second) of the body of
+    /// '[core,extra]/os/x86_64/[core,extra].db' file from the given URL (from user's geography).
+    /// Redirects are followed.
+    fn measure_duration(&mut self, options: &BenchmarkOptions) -> Result<()>;
+}
+
+/// Result of downloading database file once, all times are in seconds
+#[derive(Debug, Default, Clone, Copy)]
+struct Sample {
+    /// DNS resolution, TCP connect and TLS handshake of a new connection
+    timings: ConnectionTimings,
+
+    /// Time to first byte, from sending request until response headers are received
+    ttfb: Option<f64>,
+
+    /// Time to download response body
+    transfer_time: Option<f64>,
+
+    /// Transfer rate of response body (in bytes per second)
+    transfer_rate: Option<f64>,
+}
+
+impl Mirror {
+    /// Download database file once
+    fn sample(&mut self, options: &BenchmarkOptions, target_db: TargetDb) -> Result<Sample> {
+        let db_url: Url = Url::parse(&self.url)?.join(&target_db.path(&options.layout))?;
+        let url: Url = match &options.package {
+            Some(package) if target_db == options.target_db => {
+                // `./` keeps epoch of file name from being parsed as URL scheme
+                let file: String = package.file(&options.agent, &db_url)?;
+                db_url.join(&format!("./{file}"))?
+            }
+            _ => db_url,
+        };
+
+        http::pace(options.request_delay);
+        http::start_timings();
+        let request_start = Instant::now();
+        let response = options
+            .agent
+            .get(url.as_str())
+            .timeout(Duration::from_secs(10))
+            .call()
+            .with_context(|| format!("Failed to fetch `{url}`"))?;
+        let timings: ConnectionTimings = http::take_timings();
+        // Connection setup is timed by its own phases
+        let ttfb: f64 = timings
+            .request_start()
+            .unwrap_or(request_start)
+            .elapsed()
+            .as_secs_f64();
+        debug!(%url, ttfb, ?timings, "TTFB");
+
+        let mut sample = Sample {
+            timings,
+            ttfb: Some(ttfb),
+            ..Default::default()
+        };
+
+        if response.get_url() != url.as_str() {
+            debug!("Redirect: {url} => {}", response.get_url());
+        }
+        self.final_url = Some(response.get_url().to_string());
+        if target_db == options.target_db && options.package.is_none() {
+            if let Some(modified) = response.header("last-modified").and_then(parse_http_date) {
+                self.last_modified = Some(modified.to_rfc3339_opts(SecondsFormat::Secs, true));
+            }
+        }
+
+        // Redirect not followed because of `max_redirects`, 0 disallows any redirect
+        if (300..400).contains(&response.status()) {
+            bail!(
+                "`{url}` redirects to `{}`, more than maximum number of redirects",
+                response.header("location").unwrap_or_default()
+            );
+        }
+
+        // If success
+        if !(200..300).contains(&response.status()) {
+            debug!(%url, "No transfer rate");
+            return Ok(sample);
+        }
+
+        // Time body transfer only, latency until response headers is excluded
+        let start = Instant::now();
+        let mut reader = Head::new(http::Counted(response.into_reader()));
+        let file_size: u64 = read_body(&mut reader, options.transfer_limit)
+            .with_context(|| format!("Failed to download `{url}`"))?;
+        let transfer_time: f64 = start.elapsed().as_secs_f64();
+        sample.transfer_time = Some(transfer_time);
+
+        // Error page served with success status is not a valid measurement
+        if reader.head.len() >= MAGIC_LEN && !is_archive(&reader.head) {
+            if is_html(&reader.head) {
+                bail!("`{url}` is an HTML page, not a database");
+            }
+            bail!("`{url}` is not a compressed archive");
+        }
+
+        if file_size == 0 || transfer_time <= 0.0 {
+            debug!(%url, "No transfer rate");
+            return Ok(sample);
+        }
+
+        let transfer_rate = file_size as f64 / transfer_time;
+        debug!(%url, transfer_rate, file_size, transfer_time, "Transfer rate");
+        sample.transfer_rate = Some(transfer_rate);
+        Ok(sample)
+    }
+
+    /// Request the first byte of target database, true if mirror answers with partial content
+    fn probe_range(&self, options: &BenchmarkOptions) -> Result<bool> {
+        let url: Url = Url::parse(&self.url)?.join(&options.target_db.path(&options.layout))?;
+        let response = options
+            .agent
+            .get(url.as_str())
+            .set("Range", "bytes=0-0")
+            .timeout(Duration::from_secs(10))
+            .call()
+            .with_context(|| format!("Failed to probe range request of `{url}`"))?;
+        Ok(response.status() == 206 && response.header("content-range").is_some())
+    }
+
+    /// Measure transfer rate of target database over each address family of `options.family_agents`
+    fn measure_families(&mut self, options: &BenchmarkOptions) {
+        for (family, agent) in &options.family_agents {
+            let family_options = BenchmarkOptions {
+                other_dbs: Vec::new(),
+                require_sig: false,
+                require_files_db: false,
+                probe_range: false,
+                require_range: false,
+                probe_tls: false,
+                min_tls: None,
+                min_cert_days: None,
+                family: Some(*family),
+                family_agents: Vec::new(),
+                agent: agent.clone(),
+                ..options.clone()
+            };
+            let mut mirror: Mirror = self.clone();
+            let rate: Option<f64> = match mirror.measure_duration(&family_options) {
+                Ok(()) => mirror.transfer_rate,
+                Err(err) => {
+                    debug!("{} benchmark failed: {err:#}", family.name());
+                    None
+                }
+            };
+            match family {
+                IpFamily::V4 => self.ipv4_transfer_rate = rate,
+                IpFamily::V6 => self.ipv6_transfer_rate = rate,
+            }
+        }
+
+        if let (Some(ipv4), Some(ipv6)) = (self.ipv4_transfer_rate, self.ipv6_transfer_rate) {
+            let faster: IpFamily = if ipv6 > ipv4 {
+                IpFamily::V6
+            } else {
+                IpFamily::V4
+            };
+            self.faster_family = Some(faster.name().to_string());
+        }
+        let has_ipv6: bool = self.ipv6 || netinfo::resolve(&self.url).iter().any(IpAddr::is_ipv6);
+        self.broken_ipv6 =
+            is_ipv6_broken(self.ipv4_transfer_rate, self.ipv6_transfer_rate, has_ipv6);
+        if self.broken_ipv6 == Some(true) {
+            info!("IPv6 of `{}` is broken or far slower than IPv4", self.url);
+        }
+    }
+
+    /// Transfer rates of `sampled`, without outliers if `options.reject_outliers` is given
+    fn rates(&self, options: &BenchmarkOptions, sampled: &[Sample]) -> Vec<f64> {
+        let rates: Vec<f64> = sampled.iter().filter_map(|s| s.transfer_rate).collect();
+        let Some(max_mads) = options.reject_outliers else {
+            return rates;
+        };
+        let kept: Vec<f64> = samples::reject_outliers(&rates, max_mads);
+        if kept.len() < rates.len() {
+            debug!(
+                url = %self.url,
+                "Discard {} outlier samples of transfer rate",
+                rates.len() - kept.len()
+            );
+        }
+        kept
+    }
+
+    /// Download `target_db` as many times as samples, returns successful samples with last error
+    fn samples(
+        &mut self,
+        options: &BenchmarkOptions,
+        target_db: TargetDb,
+    ) -> (Vec<Sample>, Option<anyhow::Error>) {
+        let mut sampled: Vec<Sample> = Vec::new();
+        let mut last_err: Option<anyhow::Error> = None;
+        for _ in 0..options.samples.max(1) {
+            match self.sample(options, target_db) {
+                Ok(sample) => sampled.push(sample),
+                Err(err) => {
+                    debug!("{err:#}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        (sampled, last_err)
+    }
+}
+
+impl Benchmark for Mirror {
+    fn measure_duration(&mut self, options: &BenchmarkOptions) -> Result<()> {
+        let _phase = info_span!("benchmark", url = %self.url).entered();
+        self.transfer_rate = None;
+        self.transfer_rate_stddev = None;
+        self.dns_time = None;
+        self.dns_cached = None;
+        self.connect_time = None;
+        self.tls_time = None;
+        self.ttfb = None;
+        self.transfer_time = None;
+        self.final_url = None;
+        self.last_modified = None;
+        self.range_support = None;
+        self.alpn = None;
+        self.tls_version = None;
+        self.cert_expiry = None;
+        self.cert_error = None;
+        self.ipv4_transfer_rate = None;
+        self.ipv6_transfer_rate = None;
+        self.faster_family = None;
+        self.broken_ipv6 = None;
+
+        for path in options.required_files() {
+            self.verify_file(&options.agent, &path)
+                .context("Mirror lacks required file")?;
+        }
+
+        if options.probe_range || options.require_range {
+            let supported: bool = self.probe_range(options)?;
+            self.range_support = Some(supported);
+            if options.require_range && !supported {
+                bail!("`{}` does not honor range request", self.url);
+            }
+        }
+
+        let mut probed_tls: Option<f64> = None;
+        let strict_tls: bool = options.min_tls.is_some() || options.min_cert_days.is_some();
+        if options.probe_tls || strict_tls {
+            let (version, expiry) = match tls::probe(&Url::parse(&self.url)?, options.family) {
+                Ok(Some(info)) => {
+                    self.alpn = info.alpn;
+                    self.tls_version = Some(info.version.name().to_string());
+                    self.cert_expiry = info
+                        .cert_expiry
+                        .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true));
+                    probed_tls = Some(info.handshake.as_secs_f64());
+                    (Some(info.version), info.cert_expiry)
+                }
+                Ok(None) => (None, None),
+                Err(err) => {
+                    self.cert_error = tls::certificate_error(&err);
+                    if strict_tls {
+                        return Err(err);
+                    }
+                    debug!("{err:#}");
+                    (None, None)
+                }
+            };
+            if let Some(min_tls) = options.min_tls.filter(|min| version < Some(*min)) {
+                bail!("`{}` does not negotiate {}", self.url, min_tls.name());
+            }
+            if let (Some(days), Some(expiry)) = (options.min_cert_days, expiry) {
+                if expiry - Utc::now() < TimeDelta::days(days.into()) {
+                    bail!("Certificate of `{}` expires at {expiry}", self.url);
+                }
+            }
+        }
+
+        let (sampled, last_err) = self.samples(options, options.target_db);
+
+        let combine = |metric: fn(&Sample) -> Option<f64>| -> Option<f64> {
+            let values: Vec<f64> = sampled.iter().filter_map(metric).collect();
+            samples::aggregate(&values, options.aggregate)
+        };
+        // Cached addresses take no lookup, their DNS time would hide the real cost
+        self.dns_time = combine(|s| {
+            s.timings
+                .dns
+                .filter(|_| !s.timings.dns_cached)
+                .map(|d| d.as_secs_f64())
+        });
+        let resolved: Vec<&Sample> = sampled.iter().filter(|s| s.timings.dns.is_some()).collect();
+        self.dns_cached =
+            (!resolved.is_empty()).then(|| resolved.iter().all(|s| s.timings.dns_cached));
+        self.connect_time = combine(|s| s.timings.connect.map(|d| d.as_secs_f64()));
+        // Benchmark reusing a keep-alive connection has no handshake of its own
+        self.tls_time = combine(|s| s.timings.tls.map(|d| d.as_secs_f64())).or(probed_tls);
+        self.ttfb = combine(|s| s.ttfb);
+        self.transfer_time = combine(|s| s.transfer_time);
+
+        let rates: Vec<f64> = self.rates(options, &sampled);
+        if rates.is_empty() {
+            if let Some(err) = last_err {
+                self.cert_error = self
+                    .cert_error
+                    .take()
+                    .or_else(|| tls::certificate_error(&err));
+                return Err(err);
+            }
+            return Ok(());
+        }
+
+        self.transfer_rate = samples::aggregate(&rates, options.aggregate);
+        self.transfer_rate_stddev = samples::stddev(&rates);
+        if !options.family_agents.is_empty() {
+            self.measure_families(options);
+        }
+        if options.other_dbs.is_empty() {
+            return Ok(());
+        }
+
+        // Redirect of `target_db` is kept for rewriting mirror's URL
+        let final_url: Option<String> = self.final_url.clone();
+        let mut db_rates: Vec<f64> = self.transfer_rate.into_iter().collect();
+        for &target_db in &options.other_dbs {
+            let (sampled, last_err) = self.samples(options, target_db);
+            let rates: Vec<f64> = self.rates(options, &sampled);
+            match samples::aggregate(&rates, options.aggregate) {
+                Some(rate) => db_rates.push(rate),
+                None => {
+                    // Mirror missing a database is useless to users of that repository
+                    self.transfer_rate = None;
+                    self.transfer_rate_stddev = None;
+                    self.final_url = final_url;
+                    return Err(last_err.unwrap_or_else(|| {
+                        anyhow!("No transfer rate of `{}` database", target_db.repo())
+                    }));
+                }
+            }
+        }
+        self.final_url = final_url;
+        self.transfer_rate = samples::aggregate(&db_rates, options.db_aggregate);
+        Ok(())
+    }
+}
+
+impl Benchmark for Mirrors {
+    fn measure_duration(&mut self, options: &BenchmarkOptions) -> Result<()> {
+        // Spread concurrent measurements across countries/providers
+        let order: Vec<usize> = schedule::interleave(self);
+        let mut slots: Vec<Option<&mut Mirror>> = self.iter_mut().map(Some).collect();
+        let scheduled: Vec<&mut Mirror> = order
+            .into_iter()
+            .map(|i| slots[i].take().expect("Schedule each mirror once"))
+            .collect();
+
+        let total: usize = scheduled.len();
+        let done: AtomicUsize = AtomicUsize::new(0);
+        let limit: Option<AdaptiveLimit> = options
+            .adaptive
+            .then(|| AdaptiveLimit::new(rayon::current_num_threads()));
+        scheduled.into_iter().par_bridge().for_each(|mirror| {
+            if let Some(limit) = &limit {
+                limit.acquire();
+            }
+            if let Err(err) = mirror
+                .measure_duration(options)
+                .context("Failed to measure transfer rate")
+            {
+                info!("{err:#}");
+            }
+            if let Some(limit) = &limit {
+                limit.release(mirror.transfer_rate);
+            }
+            let done: usize = done.fetch_add(1, Ordering::Relaxed) + 1;
+            notify::status(&format!("Benchmarking {done}/{total} mirrors"));
+        });
+        if let Some(limit) = &limit {
+            debug!("Finished benchmark with {} threads", limit.limit());
+        }
+
+        Ok(())
+    }
+}
+
+/// Weights of metrics in weighted score.
+///
+/// Weighted score is a product of factors, each raised to the power of its weight.
+/// A weight of 0 ignores the metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreWeights {
+    /// Weight of measured transfer rate
+    pub rate: f64,
+
+    /// Weight of mirror score from mirrors status
+    pub score: f64,
+
+    /// Weight of mirror delay from mirrors status
+    pub delay: f64,
+
+    /// Weight of time since last sync
+    pub age: f64,
+
+    /// Weight of time to first byte
+    pub ttfb: f64,
+
+    /// Weight of TLS handshake time
+    pub tls: f64,
+
+    /// Weight of geographic distance
+    pub distance: f64,
+
+    /// Weight of HTTP/2 support, whose factor is 2 for mirrors supporting it and 1 otherwise
+    pub http2: f64,
+
+    /// Weight of ICMP round-trip time
+    pub ping: f64,
+
+    /// Weight of duration stddev from mirrors status, penalizing mirrors of inconsistent performance
+    pub stability: f64,
+}
+
+impl ScoreWeights {
+    /// Weights for ranking without benchmark, measured metrics are unavailable.
+    /// Mirror score already combines delay and duration_avg.
+    pub fn without_measurements(&self) -> Self {
+        Self {
+            rate: 0.0,
+            ttfb: 0.0,
+            tls: 0.0,
+            http2: 0.0,
+            ping: 0.0,
+            ..self.clone()
+        }
+    }
+
+    /// Weights for ranking mirrors not listed in mirrors status, e.g. servers of an existing mirrorlist.
+    /// Only measured metrics are available.
+    pub fn without_status(&self) -> Self {
+        Self {
+            score: 0.0,
+            delay: 0.0,
+            age: 0.0,
+            stability: 0.0,
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            score: 1.0,
+            delay: 0.0,
+            age: 0.0,
+            ttfb: 0.0,
+            tls: 0.0,
+            distance: 0.0,
+            http2: 0.0,
+            ping: 0.0,
+            stability: 0.0,
+        }
+    }
+}
+
+/// How the best mirrors are picked from ranked mirrors
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectOptions {
+    /// Number of mirrors to select
+    pub n: u32,
+
+    /// Prefer mirrors from distinct networks (ASN, or provider when unknown)
+    pub spread_asn: bool,
+
+    /// Maximum number of selected mirrors served by the same CDN
+    pub max_per_cdn: Option<usize>,
+
+    /// Seed of tie-breaking between mirrors with equal weighted score
+    pub seed: Option<u64>,
+}
+
+impl SelectOptions {
+    pub fn new(n: u32) -> Self {
+        Self {
+            n,
+            spread_asn: false,
+            max_per_cdn: None,
+            seed: None,
+        }
+    }
+}
+
+/// Pseudo-random key of a mirror's URL for seed, stable across runs and platforms
+fn tie_breaker(seed: u64, url: &str) -> u64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in url.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    // SplitMix64 finalizer
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Penalty factor of a cost (lower is better), higher cost gives lower factor
+fn penalty(cost: f64, weight: f64) -> f64 {
+    (1.0_f64 + cost).powf(-weight)
+}
+
+/// Cost of each mirror, the worst (highest) known one for mirrors missing it,
+/// so that a missing value never ranks above a known one
+fn costs(mirrors: &Mirrors, cost: impl Fn(&Mirror) -> Option<f64>) -> Vec<f64> {
+    let worst: f64 = mirrors.iter().filter_map(&cost).fold(0.0_f64, f64::max);
+    mirrors
+        .iter()
+        .map(|mirror| cost(mirror).unwrap_or(worst))
+        .collect()
+}
+
+pub trait Statistics {
+    /// Calculate weighted score
+    fn score(&mut self, weights: &ScoreWeights);
+
+    /// Sort descending order by weighted score.
+    ///
+    /// Ties are broken by mirror score then URL, or pseudo-randomly from `seed` if given,
+    /// so the order is reproducible either way.
+    fn sort_by_weighted_score(&mut self, seed: Option<u64>);
+
+    /// Select mirrors
+    fn select(&mut self, options: &SelectOptions);
+
+    /// Write evaluated mirrors in format
+    fn write_stats<W: io::Write>(&self, writer: W, format: StatsFormat) -> Result<()>;
+
+    /// Save evaluated mirrors to file
+    fn to_stats_file(&self, path: &Path, format: StatsFormat, overwrite: Overwrite) -> Result<()>;
+}
+
+impl Statistics for Mirrors {
+    fn score(&mut self, weights: &ScoreWeights) {
+        // According to [Mirror Status](https://archlinux.org/mirrors/status/) for Mirror Score: lower is better,
+        // while transfer_score: higher is better. The weighting cannot apply directly. The mirror scores are needed
+        // to reverse using max score as based first.
+        let max_score: f64 = self
+            .iter()
+            .map(|mirror| mirror.score.unwrap_or(f64::NAN))
+            .reduce(f64::max)
+            .unwrap_or(0.0_f64);
+
+        // Delay and age are in hours, time to first byte, TLS handshake, ping, and duration stddev
+        // are in seconds, distance is in thousands of kilometers.
+        // Never synced mirrors are as stale as the stalest one
+        let ages: Vec<f64> = costs(self, |mirror| mirror.age.map(|age| age as f64 / 3600.0));
+        // No reply, e.g. ICMP dropped or ping socket denied, is as slow as the slowest reply
+        let pings: Vec<f64> = costs(self, |mirror| mirror.ping_time);
+        // Plain HTTP does not earn a perfect TLS factor
+        let tls_times: Vec<f64> = costs(self, |mirror| mirror.tls_time);
+        let stddevs: Vec<f64> = costs(self, |mirror| mirror.duration_stddev);
+        // Mirror of unknown location is as far as the farthest one
+        let distances: Vec<f64> = costs(self, |mirror| mirror.distance.map(|d| d / 1000.0));
+
+        self.iter_mut().enumerate().for_each(|(i, mirror)| {
+            let transfer_rate: f64 = mirror.transfer_rate.unwrap_or(0.0_f64);
+            let score: f64 = mirror.score.unwrap_or(f64::NAN);
+
+            let delay: f64 = mirror.delay.unwrap_or(0) as f64 / 3600.0;
+            let ttfb: f64 = mirror.ttfb.unwrap_or(0.0_f64);
+            let http2: f64 = if mirror.alpn.as_deref() == Some("h2") {
+                2.0
+            } else {
+                1.0
+            };
+
+            mirror.weighted_score = Some(
+                transfer_rate.powf(weights.rate)
+                    * (max_score - score).powf(weights.score)
+                    * penalty(delay, weights.delay)
+                    * penalty(ages[i], weights.age)
+                    * penalty(ttfb, weights.ttfb)
+                    * penalty(tls_times[i], weights.tls)
+                    * penalty(pings[i], weights.ping)
+                    * penalty(stddevs[i], weights.stability)
+                    * penalty(distances[i], weights.distance)
+                    * http2.powf(weights.http2),
+            );
+        });
+    }
+
+    fn sort_by_weighted_score(&mut self, seed: Option<u64>) {
+        self.sort_by(|a, b| {
+            let aa: f64 = a.weighted_score.filter(|s| !s.is_nan()).unwrap_or(0.0_f64);
+            let bb: f64 = b.weighted_score.filter(|s| !s.is_nan()).unwrap_or(0.0_f64);
+            bb.total_cmp(&aa).then_with(|| match seed {
+                Some(seed) => tie_breaker(seed, &a.url).cmp(&tie_breaker(seed, &b.url)),
+                None => {
+                    let aa: f64 = a.score.unwrap_or(f64::INFINITY);
+                    let bb: f64 = b.score.unwrap_or(f64::INFINITY);
+                    aa.total_cmp(&bb).then_with(|| a.url.cmp(&b.url))
+                }
+            })
+        });
+    }
+
+    fn select(&mut self, options: &SelectOptions) {
+        let n: usize = options.n.try_into().expect("u32 to usize");
+        if let Some(max_per_cdn) = options.max_per_cdn {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            self.retain(|mirror| match &mirror.cdn {
+                Some(cdn) => {
+                    let count: &mut usize = counts.entry(cdn.clone()).or_default();
+                    *count += 1;
+                    *count <= max_per_cdn
+                }
+                None => true,
+            });
+        }
+        if options.spread_asn {
+            self.spread_select_by(n, |mirror| {
+                netinfo::resolve(&mirror.url)
+                    .first()
+                    .and_then(netinfo::asn)
+                    .map(|asn| format!("AS{asn}"))
+                    .unwrap_or_else(|| schedule::provider(mirror))
+            });
+        } else {
+            self.truncate(n);
+        }
+    }
+
+    fn write_stats<W: io::Write>(&self, writer: W, format: StatsFormat) -> Result<()> {
+        match format {
+            StatsFormat::Csv | StatsFormat::Tsv => {
+                let delimiter: u8 = if format == StatsFormat::Tsv {
+                    b'\t'
+                } else {
+                    b','
+                };
+                let mut wtr = csv::WriterBuilder::new()
+                    .delimiter(delimiter)
+                    .from_writer(writer);
+                for mirror in self.iter() {
+                    wtr.serialize(mirror)?;
+                }
+                wtr.flush()?;
+            }
+            StatsFormat::Json => {
+                let mut writer = writer;
+                serde_json::to_writer_pretty(&mut writer, &self.0)?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_stats_file(&self, path: &Path, format: StatsFormat, overwrite: Overwrite) -> Result<()> {
+        let mut stats: Vec<u8> = Vec::new();
+        self.write_stats(&mut stats, format)?;
+        output::save(path, &stats, overwrite)
+    }
+}
+
+pub trait Evaluation {
+    /// Returns all mirrors with their measurements, unchanged when benchmark is skipped
+    fn benchmark(&self, options: &BenchmarkOptions) -> Mirrors;
+
+    /// Returns the best mirrors of already benchmarked mirrors
+    fn rank(&self, selection: &SelectOptions, weights: &ScoreWeights) -> Result<Mirrors>;
+}
+
+impl Evaluation for Mirrors {
+    fn benchmark(&self, options: &BenchmarkOptions) -> Mirrors {
+        let mut mirrors: Mirrors = self.clone();
+        if !options.skip {
+            let _ = mirrors.measure_duration(options);
+        }
+        mirrors
+    }
+
+    fn rank(&self, selection: &SelectOptions, weights: &ScoreWeights) -> Result<Mirrors> {
+        let mut mirrors: Mirrors = self.clone();
+        mirrors.score(weights);
+        mirrors.sort_by_weighted_score(selection.seed);
+        mirrors.select(selection);
+
+        if mirrors.is_empty() {
+            bail!("No best mirrors");
+        }
+
+        Ok(mirrors)
+    }
+}
+
+/// How mirror list is written
+#[derive(Debug, Clone, Default)]
+pub struct MirrorListOptions {
+    /// Comment each server with its country, transfer rate, and weighted score
+    pub verbose: bool,
+
+    /// Group servers under `## Country` headers, like the official mirrorlist
+    pub group_by_country: bool,
+
+    /// Runner-up mirrors appended as commented out servers
+    pub commented_extra: Mirrors,
+
+    /// Custom server line, header, and footer
+    pub template: Option<Template>,
+
+    /// Repository path of server lines and style of mirror list
+    pub flavor: Flavor,
+
+    /// Repository path of server lines instead of the one of flavor
+    pub repo_path: Option<String>,
+
+    /// Server line with placeholders of template instead of `Server = <URL><REPO-PATH>`
+    pub server_line: Option<String>,
+}
+
+pub trait ToPacmanMirrorList {
+    /// Convert to pacman mirror list format
+    fn to_pacman_mirror_list(&self, options: &MirrorListOptions) -> Result<String>;
+
+    /// Whole mirrorlist file, generated block is delimited by markers
+    fn to_mirrorlist(&self, source_url: &str, options: &MirrorListOptions) -> Result<String> {
+        let (header, footer): (String, String) = match &options.template {
+            Some(template) => template.render_frame(source_url, &chrono::Local::now().to_rfc2822()),
+            None => (self.header(source_url, options.flavor)?, String::new()),
+        };
+        Ok(format!(
+            "{BEGIN_MARKER}\n{header}{list}{footer}{END_MARKER}\n",
+            list = self.to_pacman_mirror_list(options)?
+        ))
+    }
+
+    fn header(&self, source_url: &str, flavor: Flavor) -> Result<String> {
+        let now = chrono::Local::now();
+        Ok(format!(
+            "\
+            #\n\
+            # /etc/pacman.d/mirrorlist\n\
+            #\n\
+            #\n\
+            # {} mirrorlist generated by pacman-mirrorup\n\
+            #\n\
+            # pacman-mirrorup: https://github.com/bpetlert/pacman-mirrorup\n\
+            # source: {}\n\
+            # when: {}\n\
+            #\n\
+            \n\
+            ",
+            flavor.name(),
+            source_url,
+            now.to_rfc2822()
+        ))
+    }
+}
+
+impl ToPacmanMirrorList for Mirror {
+    fn to_pacman_mirror_list(&self, options: &MirrorListOptions) -> Result<String> {
+        let server: String = match (&options.template, &options.server_line) {
+            (Some(template), _) => template.render_server(self),
+            (None, Some(line)) => template::render_line(line, self),
+            (None, None) => format!(
+                "Server = {url}{repo_path}",
+                url = self.url,
+                repo_path = options
+                    .repo_path
+                    .as_deref()
+                    .unwrap_or(options.flavor.repo_path())
+            ),
+        };
+        if !options.verbose {
+            return Ok(server);
+        }
+
+        let country: &str = if self.country.is_empty() {
+            "Unknown country"
+        } else {
+            &self.country
+        };
+        let transfer_rate: String = self
+            .transfer_rate
+            .map(report::human_rate)
+            .unwrap_or_else(|| "-".to_string());
+        let weighted_score: String = self
+            .weighted_score
+            .map(|score| format!("{score:.2}"))
+            .unwrap_or_else(|| "-".to_string());
+        Ok(format!(
+            "# {country}, transfer rate: {transfer_rate}, weighted score: {weighted_score}\n{server}"
+        ))
+    }
+}
+
+impl ToPacmanMirrorList for Mirrors {
+    fn to_pacman_mirror_list(&self, options: &MirrorListOptions) -> Result<String> {
+        let mut list: String = self.servers(options)?;
+
+        if !options.commented_extra.is_empty() {
+            writeln!(&mut list)?;
+            writeln!(&mut list, "## Runner-up mirrors")?;
+            for mirror in options.commented_extra.iter() {
+                // Server line is always the last line of a mirror
+                let entry: String = mirror.to_pacman_mirror_list(options)?;
+                match entry.rsplit_once('\n') {
+                    Some((comment, server)) => writeln!(&mut list, "{comment}\n#{server}")?,
+                    None => writeln!(&mut list, "#{entry}")?,
+                }
+            }
         }
         Ok(list)
     }
+}
+
+/// Selected mirror in JSON output
+#[derive(Serialize, Debug)]
+struct MirrorSummary<'a> {
+    url: &'a str,
+    country: &'a str,
+    country_code: &'a str,
+    score: Option<f64>,
+    transfer_rate: Option<f64>,
+    weighted_score: Option<f64>,
+}
+
+impl<'a> From<&'a Mirror> for MirrorSummary<'a> {
+    fn from(mirror: &'a Mirror) -> Self {
+        Self {
+            url: &mirror.url,
+            country: &mirror.country,
+            country_code: &mirror.country_code,
+            score: mirror.score,
+            transfer_rate: mirror.transfer_rate,
+            weighted_score: mirror.weighted_score,
+        }
+    }
+}
+
+pub trait ToJson {
+    /// Convert to JSON array
+    fn to_json(&self) -> Result<String>;
+}
+
+impl ToJson for Mirrors {
+    fn to_json(&self) -> Result<String> {
+        let summaries: Vec<MirrorSummary> = self.iter().map(MirrorSummary::from).collect();
+        serde_json::to_string_pretty(&summaries).context("Could not serialize mirrors to JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+    use crate::exclude::ExcludeKind;
+
+    #[test]
+    fn test_online_deserialize_mirrors_status() {
+        let mirrors: MirrorsStatus = MirrorsStatus::from_online_json(
+            &http::agent(DEFAULT_MAX_REDIRECTS),
+            DEFAULT_SOURCE_URL,
+        )
+        .unwrap();
+        assert!(mirrors.urls.len() >= 100);
+    }
+
+    #[test]
+    fn test_local_mirrors_status() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mirrors_status.json");
+        let agent = http::agent(DEFAULT_MAX_REDIRECTS);
+        let from_path = MirrorsStatus::from_online_json(&agent, path).unwrap();
+        let from_url = MirrorsStatus::from_online_json(&agent, &format!("file://{path}")).unwrap();
+        assert!(!from_path.urls.is_empty());
+        assert_eq!(from_path.urls.len(), from_url.urls.len());
+    }
+
+    #[test]
+    fn test_deserialize_mirrors_status() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let _: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+    }
+
+    #[test]
+    fn test_sample_synced_mirrors() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus = serde_json::from_str(mirrors_status_raw).unwrap();
+        let sample = |seed: u64, by_country: bool| -> Vec<String> {
+            mirrors_status
+                .best_synced_mirrors(&FilterOptions {
+                    max_check: Some(5),
+                    sample: Some(Sampling {
+                        n: 10,
+                        by_country,
+                        seed,
+                    }),
+                    ..Default::default()
+                })
+                .unwrap()
+                .iter()
+                .map(|m| m.url.clone())
+                .collect()
+        };
+
+        // Reproducible by seed, regardless of `max_check`
+        assert_eq!(sample(1, false).len(), 10);
+        assert_eq!(sample(1, false), sample(1, false));
+        assert_ne!(sample(1, false), sample(2, false));
+
+        let by_country = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                sample: Some(Sampling {
+                    n: 10,
+                    by_country: true,
+                    seed: 1,
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        let countries: HashSet<&str> = by_country.iter().map(|m| m.country_code.as_str()).collect();
+        assert_eq!(countries.len(), 10);
+    }
+
+    #[test]
+    fn test_merge_mirrors_status() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mut merged: MirrorsStatus = serde_json::from_str(mirrors_status_raw).unwrap();
+        let total: usize = merged.urls.len();
+
+        let mut private: MirrorsStatus = serde_json::from_str(mirrors_status_raw).unwrap();
+        let mut overridden: Mirror = private.urls[0].clone();
+        overridden.score = Some(0.1);
+        private.urls = [
+            overridden,
+            Mirror {
+                url: "https://private.example/archlinux/".to_string(),
+                ..Default::default()
+            },
+        ]
+        .into_iter()
+        .collect();
+        merged.merge(private);
+
+        assert_eq!(merged.urls.len(), total + 1);
+        assert_eq!(merged.urls[0].score, Some(0.1));
+        assert_eq!(merged.urls[total].url, "https://private.example/archlinux/");
+    }
+
+    #[test]
+    fn test_best_synced_mirrors() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                max_check: Some(100),
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+
+        mirrors.iter().for_each(|m| {
+            // Only active mirror
+            assert!(m.active);
+
+            // Only protocol HTTP/HTTPS
+            assert!(m.protocol == "http" || m.protocol == "https");
+
+            // 100% sync
+            assert!((m.completion_pct - 1.0_f64).abs() < f64::EPSILON);
+
+            // delay < 3600
+            assert_ne!(m.delay, None);
+            assert!(m.delay.expect("delay value") < 3600);
+        });
+
+        // Sort by delay value ascending
+        for n in 1..mirrors.len() {
+            assert!(mirrors[n - 1].delay <= mirrors[n].delay);
+        }
+
+        // Ensure only 100 mirrors returned
+        assert_eq!(
+            mirrors.len(),
+            100,
+            "Number of mirrors returned = {}",
+            mirrors.len()
+        );
+    }
+
+    #[test]
+    fn test_stale_reason() {
+        let clock = ReferenceClock::from_status(None, 3600, Duration::from_secs(300), false);
+        let mirror = |last_sync: Option<DateTime<Utc>>| {
+            Mirror::from_derivative("https://mirror.example.org/", "Germany", last_sync, true)
+        };
+        assert_eq!(mirror(None).stale_reason(&clock), None);
+        assert_eq!(mirror(Some(Utc::now())).stale_reason(&clock), None);
+        assert!(mirror(Some(Utc::now() + TimeDelta::days(1)))
+            .stale_reason(&clock)
+            .is_some());
+    }
+
+    #[test]
+    fn exclude_mirrors() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+
+        let mut excluded_mirrors = ExcludedMirrors::new();
+        excluded_mirrors.add(ExcludeKind::Domain("mirrors.kernel.org".to_string()));
+        excluded_mirrors.add(ExcludeKind::Domain("mirror.xtom.com.hk".to_string()));
+
+        let mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                excluded_mirrors: Some(excluded_mirrors),
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+
+        assert_eq!(
+            mirrors.len(),
+            237,
+            "Number of mirrors returned = {}",
+            mirrors.len()
+        );
+
+        mirrors.iter().for_each(|m| {
+            assert!(!m.url.contains("mirrors.kernel.org") && !m.url.contains("mirror.xtom.com.hk"));
+        });
+    }
+
+    #[test]
+    fn test_clock_skew_tolerance() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mut mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let status_time = parse_timestamp(&mirrors_status.last_check).unwrap();
 
-    fn to_mirrorlist_file(&self, path: &Path, source_url: &str) -> Result<()> {
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(path)
-            .with_context(|| format!("Could not create file `{}`", path.display()))?;
+        let best: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        let ahead_url = best[0].url.clone();
+        let within_tolerance_url = best[1].url.clone();
 
-        let mut file = BufWriter::new(file);
-        std::io::Write::write_all(&mut file, self.header(source_url)?.as_bytes())?;
-        std::io::Write::write_all(&mut file, self.to_pacman_mirror_list()?.as_bytes())?;
-        std::io::Write::flush(&mut file)?;
-        Ok(())
+        for m in mirrors_status.urls.iter_mut() {
+            if m.url == ahead_url {
+                m.last_sync = Some((status_time + TimeDelta::hours(1)).to_rfc3339());
+            } else if m.url == within_tolerance_url {
+                m.last_sync = Some((status_time + TimeDelta::seconds(60)).to_rfc3339());
+            }
+        }
+
+        let mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        assert_eq!(mirrors.len(), best.len() - 1);
+        assert!(mirrors.iter().all(|m| m.url != ahead_url));
+        assert!(mirrors.iter().any(|m| m.url == within_tolerance_url));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use regex::Regex;
+    #[test]
+    fn test_max_age() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
 
-    use super::*;
-    use crate::exclude::ExcludeKind;
+        let all: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        let recent: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                max_age: Some(Duration::from_secs(3600)),
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+
+        assert!(recent.len() < all.len());
+        assert!(recent.iter().all(|m| m.age.unwrap() <= 3600));
+        assert_eq!(
+            recent.len(),
+            all.iter().filter(|m| m.age.unwrap() <= 3600).count()
+        );
+    }
 
     #[test]
-    fn test_online_deserialize_mirrors_status() {
-        let mirrors: MirrorsStatus = MirrorsStatus::from_online_json(DEFAULT_SOURCE_URL).unwrap();
-        assert!(mirrors.urls.len() >= 100);
+    fn test_ip_capabilities() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+
+        let all: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        let ipv6: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                ipv6: true,
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+        let dual_stack: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                ipv4: true,
+                ipv6: true,
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+
+        assert!(ipv6.len() < all.len());
+        assert!(ipv6.iter().all(|m| m.ipv6));
+        assert_eq!(ipv6.len(), all.iter().filter(|m| m.ipv6).count());
+        assert!(dual_stack.iter().all(|m| m.ipv4 && m.ipv6));
     }
 
     #[test]
-    fn test_deserialize_mirrors_status() {
+    fn test_isos() {
         let mirrors_status_raw = include_str!(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/tests/mirrors_status.json"
         ));
-        let _: MirrorsStatus =
+        let mut mirrors_status: MirrorsStatus =
             serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+
+        let best: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        let no_isos_url = best[0].url.clone();
+        for m in mirrors_status.urls.iter_mut() {
+            if m.url == no_isos_url {
+                m.isos = false;
+            }
+        }
+
+        let mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                isos: true,
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+        assert!(mirrors.iter().all(|m| m.isos));
+        assert!(mirrors.iter().all(|m| m.url != no_isos_url));
     }
 
     #[test]
-    fn test_best_synced_mirrors() {
+    fn test_include_countries() {
         let mirrors_status_raw = include_str!(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/tests/mirrors_status.json"
         ));
         let mirrors_status: MirrorsStatus =
             serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+
         let mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(Some(100), None)
+            .best_synced_mirrors(&FilterOptions {
+                countries: vec!["germany".to_string()],
+                country_codes: vec!["FR".to_string()],
+                ..Default::default()
+            })
             .expect("Get best synced mirrors");
+        assert!(mirrors.iter().any(|m| m.country_code == "DE"));
+        assert!(mirrors.iter().any(|m| m.country_code == "FR"));
+        assert!(mirrors
+            .iter()
+            .all(|m| m.country == "Germany" || m.country_code == "FR"));
+    }
 
-        mirrors.iter().for_each(|m| {
-            // Only active mirror
-            assert!(m.active);
+    #[test]
+    fn test_collapse_same_addresses() {
+        let mut mirrors: Mirrors = [
+            "http://a.example/archlinux/",
+            "https://b.example/archlinux/",
+            "https://a.example/archlinux/",
+            "https://c.example/archlinux/",
+            "https://unresolved.example/archlinux/",
+            "https://unresolved2.example/archlinux/",
+            "https://d.example/archlinux/",
+        ]
+        .into_iter()
+        .map(Mirror::from_url)
+        .collect();
 
-            // Only protocol HTTP/HTTPS
-            assert!(m.protocol == "http" || m.protocol == "https");
+        let resolve = |url: &str| -> Vec<IpAddr> {
+            let addrs: &[&str] = match Url::parse(url).unwrap().host_str().unwrap() {
+                "a.example" => &["192.0.2.1", "2001:db8::1"],
+                "b.example" => &["192.0.2.2"],
+                "c.example" => &["2001:db8::1", "192.0.2.1", "192.0.2.1"],
+                "d.example" => &["192.0.2.2"],
+                _ => &[],
+            };
+            addrs.iter().map(|ip| ip.parse().unwrap()).collect()
+        };
+        assert_eq!(mirrors.collapse_same_addresses(resolve), 3);
+        assert_eq!(
+            mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "https://a.example/archlinux/",
+                "https://b.example/archlinux/",
+                "https://unresolved.example/archlinux/",
+                "https://unresolved2.example/archlinux/",
+            ]
+        );
+    }
 
-            // 100% sync
-            assert!((m.completion_pct - 1.0_f64).abs() < f64::EPSILON);
+    #[test]
+    fn test_pre_rank() {
+        let mut mirrors: Mirrors = [
+            "https://unreachable.example/",
+            "https://slow.example/",
+            "https://fast.example/",
+            "https://medium.example/",
+        ]
+        .into_iter()
+        .map(Mirror::from_url)
+        .collect();
+        let dropped: usize = mirrors.pre_rank(3, |mirror| match mirror.url.as_str() {
+            "https://slow.example/" => Some(0.3),
+            "https://fast.example/" => Some(0.01),
+            "https://medium.example/" => Some(0.05),
+            _ => None,
+        });
+        assert_eq!(dropped, 1);
+        assert_eq!(
+            mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "https://fast.example/",
+                "https://medium.example/",
+                "https://slow.example/"
+            ]
+        );
+        assert_eq!(mirrors[0].tcp_connect_time, Some(0.01));
+    }
 
-            // delay < 3600
-            assert_ne!(m.delay, None);
-            assert!(m.delay.expect("delay value") < 3600);
+    #[test]
+    fn test_resolve_network() {
+        let mut mirrors: Mirrors = ["https://a.example/", "https://unresolved.example/"]
+            .into_iter()
+            .map(Mirror::from_url)
+            .collect();
+        mirrors.resolve_network(
+            |url| match url {
+                "https://a.example/" => vec![
+                    "203.0.113.7".parse().unwrap(),
+                    "2001:db8::7".parse().unwrap(),
+                ],
+                _ => Vec::new(),
+            },
+            |ip| ip.is_ipv4().then_some(64496),
+        );
+        assert_eq!(
+            mirrors[0].addresses.as_deref(),
+            Some("203.0.113.7 2001:db8::7")
+        );
+        assert_eq!(mirrors[0].asn, Some(64496));
+        assert_eq!(mirrors[1].addresses, None);
+        assert_eq!(mirrors[1].asn, None);
+    }
+
+    #[test]
+    fn test_select_max_per_cdn() {
+        let mut mirrors: Mirrors = [
+            "https://a.example/",
+            "https://b.example/",
+            "https://c.example/",
+            "https://d.example/",
+            "https://e.example/",
+        ]
+        .into_iter()
+        .map(Mirror::from_url)
+        .collect();
+        mirrors.detect_cdns(|url| match url {
+            "https://a.example/" | "https://b.example/" | "https://d.example/" => Some("Fastly"),
+            "https://c.example/" => Some("Cloudflare"),
+            _ => None,
         });
+        assert_eq!(mirrors[0].cdn.as_deref(), Some("Fastly"));
+        assert_eq!(mirrors[4].cdn, None);
 
-        // Sort by delay value ascending
-        for n in 1..mirrors.len() {
-            assert!(mirrors[n - 1].delay <= mirrors[n].delay);
-        }
+        mirrors.select(&SelectOptions {
+            max_per_cdn: Some(1),
+            ..SelectOptions::new(3)
+        });
+        assert_eq!(
+            mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "https://a.example/",
+                "https://c.example/",
+                "https://e.example/"
+            ]
+        );
+    }
 
-        // Ensure only 100 mirrors returned
+    #[test]
+    fn test_collapse_duplicates() {
+        let mut mirrors: Mirrors = [
+            ("https://mirror.a/archlinux/", Some(2.0)),
+            ("https://mirror.b/archlinux/", None),
+            ("https://MIRROR.A/archlinux", Some(1.0)),
+            ("https://mirror.b/archlinux/", Some(3.0)),
+            ("https://mirror.a/archlinux/", Some(5.0)),
+        ]
+        .into_iter()
+        .map(|(url, score)| Mirror {
+            url: url.to_string(),
+            score,
+            ..Default::default()
+        })
+        .collect();
+
+        assert_eq!(mirrors.collapse_duplicates(), 3);
+        assert_eq!(mirrors.len(), 2);
+        assert_eq!(mirrors[0].url, "https://MIRROR.A/archlinux");
+        assert_eq!(mirrors[0].score, Some(1.0));
+        assert_eq!(mirrors[1].url, "https://mirror.b/archlinux/");
+        assert_eq!(mirrors[1].score, Some(3.0));
+    }
+
+    #[test]
+    fn test_best_synced_mirrors_without_duplicates() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mut mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let best: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+
+        let mut duplicate: Mirror = best[0].clone();
+        duplicate.score = duplicate.score.map(|s| s + 1.0);
+        mirrors_status.urls.push(duplicate);
+
+        let mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+        assert_eq!(mirrors.len(), best.len());
+        assert_eq!(mirrors.iter().filter(|m| m.url == best[0].url).count(), 1);
+        assert_eq!(mirrors[0].score, best[0].score);
+    }
+
+    #[test]
+    fn test_messure_duration() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let mut mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                max_check: Some(100),
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+        mirrors.truncate(10);
+        let _ = mirrors.measure_duration(&BenchmarkOptions::new(
+            TargetDb::Core,
+            http::agent(DEFAULT_MAX_REDIRECTS),
+        ));
+        mirrors.iter().for_each(|m| {
+            assert_ne!(m.transfer_rate, None, "Failed host = {}", m.url);
+        });
+    }
+
+    #[test]
+    fn test_sort_output() {
+        let mut mirrors: Mirrors = [
+            ("https://a/", "Germany", Some(1.5), Some(10.0), Some(300)),
+            ("https://b/", "France", Some(0.5), None, Some(900)),
+            ("https://c/", "Germany", None, Some(30.0), None),
+            ("https://d/", "Austria", Some(1.0), Some(20.0), Some(60)),
+        ]
+        .into_iter()
+        .map(|(url, country, score, transfer_rate, delay)| Mirror {
+            url: url.to_string(),
+            country: country.to_string(),
+            score,
+            transfer_rate,
+            delay,
+            ..Default::default()
+        })
+        .collect();
+        let urls = |mirrors: &Mirrors| -> String { mirrors.iter().map(|m| &m.url[8..9]).collect() };
+
+        mirrors.sort_output(SortBy::Rate);
+        assert_eq!(urls(&mirrors), "cdab");
+
+        mirrors.sort_output(SortBy::Score);
+        assert_eq!(urls(&mirrors), "bdac");
+
+        mirrors.sort_output(SortBy::Delay);
+        assert_eq!(urls(&mirrors), "dabc");
+
+        // Stable within country
+        mirrors.sort_output(SortBy::Country);
+        assert_eq!(urls(&mirrors), "dbac");
+    }
+
+    #[test]
+    fn test_target_db_path() {
         assert_eq!(
-            mirrors.len(),
-            100,
-            "Number of mirrors returned = {}",
-            mirrors.len()
+            TargetDb::Core.path(&Layout::default()),
+            "core/os/x86_64/core.db"
+        );
+        let layout = Layout {
+            repo_path: Flavor::Manjaro.repo_path().to_string(),
+            arch: "aarch64".to_string(),
+        };
+        assert_eq!(TargetDb::Extra.path(&layout), "extra/aarch64/extra.db");
+        assert_eq!(
+            TargetDb::MultilibTesting.path(&Layout::default()),
+            "multilib-testing/os/x86_64/multilib-testing.db"
+        );
+    }
+
+    #[test]
+    fn test_drop_stale_content() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some("1994-11-06T08:49:37Z".parse().unwrap())
+        );
+        assert_eq!(parse_http_date("yesterday"), None);
+
+        let mut mirrors: Mirrors = [
+            (
+                "https://fast.example/",
+                Some(2000.0),
+                Some("2024-01-01T12:00:00Z"),
+            ),
+            (
+                "https://stale.example/",
+                Some(1000.0),
+                Some("2024-01-01T06:00:00Z"),
+            ),
+            ("https://lagging.example/", Some(3000.0), None),
+            (
+                "https://unknown.example/",
+                None,
+                Some("2023-01-01T00:00:00Z"),
+            ),
+        ]
+        .into_iter()
+        .map(|(url, transfer_rate, last_modified)| Mirror {
+            url: url.to_string(),
+            transfer_rate,
+            last_modified: last_modified.map(String::from),
+            ..Default::default()
+        })
+        .collect();
+
+        let reference: DateTime<Utc> = "2024-01-01T12:00:00Z".parse().unwrap();
+        assert_eq!(mirrors.fastest_last_modified(), Some(reference));
+        assert_eq!(
+            mirrors.drop_stale_content(reference, TimeDelta::hours(2)),
+            2
+        );
+        assert_eq!(
+            mirrors.iter().map(|m| m.url.as_str()).collect::<Vec<_>>(),
+            vec!["https://fast.example/", "https://lagging.example/"]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_redirected() {
+        let mut mirrors: Mirrors = [
+            ("https://mirror.a/archlinux/", None),
+            (
+                "https://mirror.b/archlinux/",
+                Some("https://mirror.b/archlinux/extra/os/x86_64/extra.db"),
+            ),
+            (
+                "http://mirror.c/archlinux/",
+                Some("https://cdn.mirror.c/arch/extra/os/x86_64/extra.db"),
+            ),
+            (
+                "https://mirror.d/archlinux/",
+                Some("https://mirror.d/error.html"),
+            ),
+            (
+                "https://mirror.e/archlinux/",
+                Some("https://cdn.mirror.e/extra/os/x86_64/linux-6.0-1-x86_64.pkg.tar.zst"),
+            ),
+        ]
+        .into_iter()
+        .map(|(url, final_url)| Mirror {
+            url: url.to_string(),
+            final_url: final_url.map(String::from),
+            ..Default::default()
+        })
+        .collect();
+
+        mirrors.rewrite_redirected(TargetDb::Extra, &Layout::default());
+        assert_eq!(
+            mirrors.iter().map(|m| m.url.as_str()).collect::<Vec<_>>(),
+            vec![
+                "https://mirror.a/archlinux/",
+                "https://mirror.b/archlinux/",
+                "https://cdn.mirror.c/arch/",
+                "https://mirror.d/archlinux/",
+                "https://cdn.mirror.e/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = vec![0_u8; 200 * 1024];
+        assert_eq!(
+            read_body(&mut io::Cursor::new(&body), None).unwrap(),
+            200 * 1024
+        );
+        assert_eq!(
+            read_body(&mut io::Cursor::new(&body), Some(100_000)).unwrap(),
+            100_000
+        );
+        assert_eq!(read_body(&mut io::empty(), None).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_is_archive() {
+        let mut reader = Head::new(io::Cursor::new(b"\x1f\x8b\x08\x00\x00\x00\x00\x00"));
+        assert_eq!(read_body(&mut reader, None).unwrap(), 8);
+        assert_eq!(reader.head, b"\x1f\x8b\x08\x00\x00\x00");
+        assert!(is_archive(&reader.head));
+        assert!(is_archive(b"\x28\xb5\x2f\xfd\x04\x00"));
+
+        let page: &[u8] = b"\n<!DOCTYPE html>";
+        assert!(!is_archive(page));
+        assert!(is_html(page));
+        assert!(!is_archive(b"core"));
+    }
+
+    #[test]
+    fn test_read_partial_body() {
+        struct TimeoutAfter(usize);
+        impl Read for TimeoutAfter {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(io::Error::from(io::ErrorKind::TimedOut));
+                }
+                let n = self.0.min(buf.len());
+                self.0 -= n;
+                Ok(n)
+            }
+        }
+
+        assert_eq!(read_body(&mut TimeoutAfter(1000), None).unwrap(), 1000);
+        assert!(read_body(&mut TimeoutAfter(0), None).is_err());
+    }
+
+    #[test]
+    fn test_score() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let mut mirrors: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions {
+                max_check: Some(100),
+                ..Default::default()
+            })
+            .expect("Get best synced mirrors");
+        mirrors.iter_mut().for_each(|m| {
+            m.transfer_rate = m.duration_avg;
+        });
+        mirrors.score(&ScoreWeights::default());
+        let sum: f64 = mirrors
+            .iter()
+            .map(|m| m.weighted_score.expect("Weighted score value"))
+            .sum();
+        assert!(
+            (sum - 130.20669769595168).abs() < f64::EPSILON,
+            "sum = {}",
+            sum
+        );
+    }
+
+    #[test]
+    fn test_score_with_ttfb_weight() {
+        let mut mirrors: Mirrors = [(1.0, Some(0.1)), (1.0, Some(2.0)), (3.0, None)]
+            .into_iter()
+            .map(|(score, ttfb)| Mirror {
+                score: Some(score),
+                transfer_rate: Some(1000.0),
+                ttfb,
+                ..Default::default()
+            })
+            .collect();
+
+        // Latency is ignored by default
+        mirrors.score(&ScoreWeights::default());
+        assert_eq!(mirrors[0].weighted_score, Some(2000.0));
+        assert_eq!(mirrors[1].weighted_score, Some(2000.0));
+
+        mirrors.score(&ScoreWeights {
+            ttfb: 1.0,
+            ..Default::default()
+        });
+        let fast: f64 = mirrors[0].weighted_score.unwrap();
+        let slow: f64 = mirrors[1].weighted_score.unwrap();
+        assert!((fast - 2000.0 / 1.1).abs() < 1e-9, "fast = {fast}");
+        assert!((slow - 2000.0 / 3.0).abs() < 1e-9, "slow = {slow}");
+    }
+
+    #[test]
+    fn test_score_with_tls_weight() {
+        let mut mirrors: Mirrors = [Some(0.5), None]
+            .into_iter()
+            .map(|tls_time| Mirror {
+                transfer_rate: Some(1000.0),
+                tls_time,
+                ..Default::default()
+            })
+            .collect();
+
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            tls: 1.0,
+            ..Default::default()
+        });
+        let slow: f64 = mirrors[0].weighted_score.unwrap();
+        assert!((slow - 1000.0 / 1.5).abs() < 1e-9, "slow = {slow}");
+
+        // No handshake is as slow as the slowest one
+        assert_eq!(mirrors[1].weighted_score, Some(slow));
+    }
+
+    #[test]
+    fn test_score_with_ping_weight() {
+        let mut mirrors: Mirrors = [Some(0.25), None]
+            .into_iter()
+            .map(|ping_time| Mirror {
+                transfer_rate: Some(1000.0),
+                ping_time,
+                ..Default::default()
+            })
+            .collect();
+
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            ping: 2.0,
+            ..Default::default()
+        });
+        let far: f64 = mirrors[0].weighted_score.unwrap();
+        assert!((far - 640.0).abs() < 1e-9, "far = {far}");
+
+        // No reply is as far as the farthest mirror
+        assert_eq!(mirrors[1].weighted_score, Some(far));
+
+        // Neutral when no mirror replies
+        mirrors[0].ping_time = None;
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            ping: 2.0,
+            ..Default::default()
+        });
+        assert_eq!(mirrors[0].weighted_score, Some(1000.0));
+        assert_eq!(mirrors[1].weighted_score, Some(1000.0));
+    }
+
+    #[test]
+    fn test_score_with_stability_weight() {
+        let mut mirrors: Mirrors = [Some(0.0), Some(1.0), None]
+            .into_iter()
+            .map(|duration_stddev| Mirror {
+                transfer_rate: Some(1000.0),
+                duration_stddev,
+                ..Default::default()
+            })
+            .collect();
+
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            stability: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(mirrors[0].weighted_score, Some(1000.0));
+        assert_eq!(mirrors[1].weighted_score, Some(500.0));
+
+        // Unknown stddev is as jittery as the most jittery mirror
+        assert_eq!(mirrors[2].weighted_score, Some(500.0));
+
+        mirrors.score(
+            &ScoreWeights {
+                score: 0.0,
+                stability: 1.0,
+                ..Default::default()
+            }
+            .without_status(),
         );
+        assert_eq!(mirrors[1].weighted_score, Some(1000.0));
+    }
+
+    #[cfg(feature = "ping")]
+    #[test]
+    fn test_measure_ping() {
+        let mut mirrors: Mirrors = ["https://a.example/", "https://b.example/"]
+            .into_iter()
+            .map(Mirror::from_url)
+            .collect();
+        mirrors.measure_ping(|mirror| (mirror.url == "https://a.example/").then_some(0.02));
+        assert_eq!(mirrors[0].ping_time, Some(0.02));
+        assert_eq!(mirrors[1].ping_time, None);
     }
 
     #[test]
-    fn exclude_mirrors() {
-        let mirrors_status_raw = include_str!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/tests/mirrors_status.json"
-        ));
-        let mirrors_status: MirrorsStatus =
-            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+    fn test_score_with_http2() {
+        let mut mirrors: Mirrors = [Some("h2"), Some("http/1.1"), None]
+            .into_iter()
+            .map(|alpn| Mirror {
+                transfer_rate: Some(1000.0),
+                alpn: alpn.map(String::from),
+                ..Default::default()
+            })
+            .collect();
 
-        let mut excluded_mirrors = ExcludedMirrors::new();
-        excluded_mirrors.add(ExcludeKind::Domain("mirrors.kernel.org".to_string()));
-        excluded_mirrors.add(ExcludeKind::Domain("mirror.xtom.com.hk".to_string()));
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            http2: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(mirrors[0].weighted_score, Some(2000.0));
+        assert_eq!(mirrors[1].weighted_score, Some(1000.0));
+        assert_eq!(mirrors[2].weighted_score, Some(1000.0));
+    }
 
-        let mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(None, Some(excluded_mirrors))
-            .expect("Get best synced mirrors");
+    #[test]
+    fn test_score_with_custom_weights() {
+        let mut mirrors: Mirrors = [(1.0, 100.0, 0), (2.0, 400.0, 3600), (3.0, 900.0, 7200)]
+            .into_iter()
+            .map(|(score, transfer_rate, delay)| Mirror {
+                score: Some(score),
+                transfer_rate: Some(transfer_rate),
+                delay: Some(delay),
+                age: Some(delay),
+                ..Default::default()
+            })
+            .collect();
 
-        assert_eq!(
-            mirrors.len(),
-            237,
-            "Number of mirrors returned = {}",
-            mirrors.len()
-        );
+        // Transfer rate only
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(mirrors[2].weighted_score, Some(900.0));
 
-        mirrors.iter().for_each(|m| {
-            assert!(!m.url.contains("mirrors.kernel.org") && !m.url.contains("mirror.xtom.com.hk"));
+        // Mirror score only
+        mirrors.score(&ScoreWeights {
+            rate: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(mirrors[0].weighted_score, Some(2.0));
+        assert_eq!(mirrors[2].weighted_score, Some(0.0));
+
+        // Square root of transfer rate, penalized by delay and age
+        mirrors.score(&ScoreWeights {
+            rate: 0.5,
+            score: 0.0,
+            delay: 1.0,
+            age: 1.0,
+            ..Default::default()
         });
+        assert_eq!(mirrors[0].weighted_score, Some(10.0));
+        assert_eq!(mirrors[1].weighted_score, Some(5.0));
+        let weighted_score = mirrors[2].weighted_score.unwrap();
+        assert!((weighted_score - 30.0 / 9.0).abs() < 1e-12);
     }
 
     #[test]
-    fn test_messure_duration() {
-        let mirrors_status_raw = include_str!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/tests/mirrors_status.json"
-        ));
-        let mirrors_status: MirrorsStatus =
-            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
-        let mut mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(Some(100), None)
-            .expect("Get best synced mirrors");
-        mirrors.truncate(10);
-        let _ = mirrors.measure_duration(TargetDb::Core);
-        mirrors.iter().for_each(|m| {
-            assert_ne!(m.transfer_rate, None, "Failed host = {}", m.url);
+    fn test_score_with_age_weight() {
+        let mut mirrors: Mirrors = [Some(0), Some(3600), None]
+            .into_iter()
+            .map(|age| Mirror {
+                transfer_rate: Some(1000.0),
+                age,
+                ..Default::default()
+            })
+            .collect();
+
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            age: 1.0,
+            ..Default::default()
         });
+        assert_eq!(mirrors[0].weighted_score, Some(1000.0));
+        assert_eq!(mirrors[1].weighted_score, Some(500.0));
+
+        // Unknown last sync is as stale as the stalest mirror
+        assert_eq!(mirrors[2].weighted_score, Some(500.0));
     }
 
     #[test]
-    fn test_score() {
+    fn test_rank_without_benchmark() {
         let mirrors_status_raw = include_str!(concat!(
             env!("CARGO_MANIFEST_DIR"),
             "/tests/mirrors_status.json"
         ));
         let mirrors_status: MirrorsStatus =
             serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
-        let mut mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(Some(100), None)
+        let best_synced_mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
             .expect("Get best synced mirrors");
-        mirrors.iter_mut().for_each(|m| {
-            m.transfer_rate = m.duration_avg;
+
+        // No mirror is contacted
+        let options = BenchmarkOptions {
+            skip: true,
+            ..BenchmarkOptions::new(TargetDb::Extra, ureq::agent())
+        };
+        let best_mirrors = best_synced_mirrors
+            .benchmark(&options)
+            .rank(
+                &SelectOptions::new(5),
+                &ScoreWeights::default().without_measurements(),
+            )
+            .expect("Rank without benchmark");
+        assert_eq!(best_mirrors.len(), 5);
+
+        // Ordered by mirror score, lower is better
+        assert!(best_mirrors
+            .windows(2)
+            .all(|w| w[0].score.unwrap() <= w[1].score.unwrap()));
+        assert!(best_mirrors.iter().all(|m| m.transfer_rate.is_none()));
+    }
+
+    #[test]
+    fn test_score_with_distance_weight() {
+        let mut mirrors: Mirrors = ["TH", "SG", "US", ""]
+            .into_iter()
+            .map(|country_code| Mirror {
+                country_code: country_code.to_string(),
+                score: Some(1.0),
+                transfer_rate: Some(100.0),
+                ..Default::default()
+            })
+            .collect();
+        mirrors.update_distance(&Coordinates::of_country("TH").unwrap());
+        assert_eq!(mirrors[0].distance, Some(0.0));
+        assert_eq!(mirrors[3].distance, None);
+
+        mirrors.score(&ScoreWeights {
+            score: 0.0,
+            distance: 1.0,
+            ..Default::default()
         });
-        mirrors.score();
-        let sum: f64 = mirrors
-            .iter()
-            .map(|m| m.weighted_score.expect("Weighted score value"))
-            .sum();
-        assert!(
-            (sum - 130.20669769595168).abs() < f64::EPSILON,
-            "sum = {}",
-            sum
-        );
+        let scores: Vec<f64> = mirrors.iter().map(|m| m.weighted_score.unwrap()).collect();
+        assert_eq!(scores[0], 100.0);
+        assert!(scores[0] > scores[1] && scores[1] > scores[2]);
+
+        // Unknown location is as far as the farthest mirror
+        assert_eq!(scores[3], scores[2]);
     }
 
     #[test]
@@ -516,13 +3419,16 @@ mod tests {
         let mirrors_status: MirrorsStatus =
             serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
         let mut mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(Some(100), None)
+            .best_synced_mirrors(&FilterOptions {
+                max_check: Some(100),
+                ..Default::default()
+            })
             .expect("Get best synced mirrors");
         mirrors.iter_mut().for_each(|m| {
             m.transfer_rate = m.duration_avg;
         });
-        mirrors.score();
-        mirrors.sort_by_weighted_score();
+        mirrors.score(&ScoreWeights::default());
+        mirrors.sort_by_weighted_score(None);
 
         // 1st mirror
         let first: f64 = mirrors
@@ -554,6 +3460,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sort_ties() {
+        let mirrors: Mirrors = [
+            ("https://c/", Some(2.0), Some(1.0)),
+            ("https://a/", Some(2.0), Some(1.0)),
+            ("https://b/", Some(1.0), Some(1.0)),
+            ("https://d/", Some(1.0), Some(f64::NAN)),
+            ("https://e/", None, None),
+            ("https://f/", Some(1.0), Some(3.0)),
+        ]
+        .into_iter()
+        .map(|(url, score, weighted_score)| Mirror {
+            url: url.to_string(),
+            score,
+            weighted_score,
+            ..Default::default()
+        })
+        .collect();
+        let urls = |mirrors: &Mirrors| -> String { mirrors.iter().map(|m| &m.url[8..9]).collect() };
+
+        // Mirror score, then URL
+        let mut sorted = mirrors.clone();
+        sorted.sort_by_weighted_score(None);
+        assert_eq!(urls(&sorted), "fbacde");
+
+        // Same seed gives same order regardless of input order
+        let mut sorted = mirrors.clone();
+        sorted.sort_by_weighted_score(Some(42));
+        let mut reversed = mirrors.clone();
+        reversed.reverse();
+        reversed.sort_by_weighted_score(Some(42));
+        assert_eq!(urls(&sorted), urls(&reversed));
+        assert_eq!(&urls(&sorted)[..1], "f");
+    }
+
     #[test]
     fn test_select_n_mirrors() {
         let mirrors_status_raw = include_str!(concat!(
@@ -563,12 +3504,59 @@ mod tests {
         let mirrors_status: MirrorsStatus =
             serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
         let mut mirrors: Mirrors = mirrors_status
-            .best_synced_mirrors(Some(100), None)
+            .best_synced_mirrors(&FilterOptions {
+                max_check: Some(100),
+                ..Default::default()
+            })
             .expect("Get best synced mirrors");
-        mirrors.select(20);
+        mirrors.select(&SelectOptions::new(20));
         assert_eq!(mirrors.len(), 20);
     }
 
+    #[test]
+    fn test_spread_select() {
+        let mut mirrors: Mirrors = [
+            "https://a1.provider-a.de/",
+            "https://a2.provider-a.de/",
+            "https://b1.provider-b.de/",
+            "https://a3.provider-a.de/",
+            "https://c1.provider-c.fr/",
+            "https://b2.provider-b.de/",
+        ]
+        .into_iter()
+        .map(|url| Mirror {
+            url: url.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+        let mut spread = mirrors.clone();
+        spread.spread_select_by(3, schedule::provider);
+        let urls: Vec<&str> = spread.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://a1.provider-a.de/",
+                "https://b1.provider-b.de/",
+                "https://c1.provider-c.fr/",
+            ]
+        );
+
+        // Not enough distinct providers, fill up with next best mirrors
+        mirrors.spread_select_by(5, schedule::provider);
+        let urls: Vec<&str> = mirrors.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://a1.provider-a.de/",
+                "https://a2.provider-a.de/",
+                "https://b1.provider-b.de/",
+                "https://a3.provider-a.de/",
+                "https://c1.provider-c.fr/",
+            ]
+        );
+    }
+
     #[test]
     fn test_mirrorlist_file_header() {
         let header_format = Regex::new(include_str!(concat!(
@@ -578,7 +3566,10 @@ mod tests {
         .expect("Creating regex");
         let mirror: Mirror = Default::default();
         let header = mirror
-            .header("https://www.archlinux.org/mirrors/status/json/")
+            .header(
+                "https://www.archlinux.org/mirrors/status/json/",
+                Flavor::Arch,
+            )
             .unwrap();
         assert!(
             header_format.is_match(&header),
@@ -601,18 +3592,575 @@ mod tests {
             Regex::new(r"Server\x20=\x20(http(s?)|rsync|ftp)://(\S+\.\S+/)(\$repo/os/\$arch)")
                 .expect("Creating regex");
 
+        let options = MirrorListOptions::default();
+
         // Check Mirror
         for mirror in mirrors.iter() {
             assert!(
-                mirror_format.is_match(&mirror.to_pacman_mirror_list().unwrap()),
+                mirror_format.is_match(&mirror.to_pacman_mirror_list(&options).unwrap()),
                 "Pacman mirrorlist: {:#?}",
-                mirror.to_pacman_mirror_list().unwrap()
+                mirror.to_pacman_mirror_list(&options).unwrap()
             );
         }
 
         // Check Mirrors
-        for line in mirrors.to_pacman_mirror_list().unwrap().lines() {
+        for line in mirrors.to_pacman_mirror_list(&options).unwrap().lines() {
             assert!(mirror_format.is_match(line));
         }
     }
+
+    #[test]
+    fn test_to_verbose_pacman_mirror_list() {
+        let mut mirror = Mirror {
+            url: "https://mirror.example/archlinux/".to_string(),
+            country: "Thailand".to_string(),
+            ..Default::default()
+        };
+        mirror.transfer_rate = Some(2.0 * 1024.0 * 1024.0);
+        mirror.weighted_score = Some(1234.5678);
+        let options = MirrorListOptions {
+            verbose: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mirror.to_pacman_mirror_list(&options).unwrap(),
+            "# Thailand, transfer rate: 2.0 MiB/s, weighted score: 1234.57\n\
+             Server = https://mirror.example/archlinux/$repo/os/$arch"
+        );
+
+        let mirror = Mirror {
+            url: "https://unmeasured.example/archlinux/".to_string(),
+            ..Default::default()
+        };
+        assert!(mirror
+            .to_pacman_mirror_list(&options)
+            .unwrap()
+            .starts_with("# Unknown country, transfer rate: -, weighted score: -\n"));
+    }
+
+    #[test]
+    fn test_to_manjaro_mirror_list() {
+        let mirrors: Mirrors = [
+            ("https://mirror.example.de/manjaro/stable/", "Germany"),
+            ("https://mirror.example.com/manjaro/stable/", ""),
+        ]
+        .into_iter()
+        .map(|(url, country)| Mirror {
+            url: url.to_string(),
+            country: country.to_string(),
+            ..Default::default()
+        })
+        .collect();
+        let options = MirrorListOptions {
+            flavor: Flavor::Manjaro,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mirrors.to_pacman_mirror_list(&options).unwrap(),
+            "\
+            ## Country : Germany\n\
+            Server = https://mirror.example.de/manjaro/stable/$repo/$arch\n\
+            \n\
+            ## Country : Worldwide\n\
+            Server = https://mirror.example.com/manjaro/stable/$repo/$arch\n\
+            "
+        );
+        assert!(mirrors
+            .header("https://repo.manjaro.org/status.json", Flavor::Manjaro)
+            .unwrap()
+            .contains("# Manjaro Linux mirrorlist generated by pacman-mirrorup\n"));
+    }
+
+    #[test]
+    fn test_to_derivative_mirror_list() {
+        let mirrors: Mirrors = [Mirror::from_url("https://mirror.example/artix/")]
+            .into_iter()
+            .collect();
+        let options = MirrorListOptions {
+            repo_path: Some("$repo/os/$arch".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            mirrors.to_pacman_mirror_list(&options).unwrap(),
+            "Server = https://mirror.example/artix/$repo/os/$arch\n"
+        );
+
+        let options = MirrorListOptions {
+            flavor: Flavor::Manjaro,
+            server_line: Some("Server = {url}$arch/$repo".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            mirrors.to_pacman_mirror_list(&options).unwrap(),
+            "Server = https://mirror.example/artix/$arch/$repo\n"
+        );
+    }
+
+    #[test]
+    fn test_to_pacman_mirror_list_by_country() {
+        let mirrors: Mirrors = [
+            ("https://th1.example/", "Thailand"),
+            ("https://geo.example/", ""),
+            ("https://de.example/", "Germany"),
+            ("https://th2.example/", "Thailand"),
+        ]
+        .into_iter()
+        .map(|(url, country)| Mirror {
+            url: url.to_string(),
+            country: country.to_string(),
+            ..Default::default()
+        })
+        .collect();
+        let options = MirrorListOptions {
+            group_by_country: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mirrors.to_pacman_mirror_list(&options).unwrap(),
+            "\
+            ## Thailand\n\
+            Server = https://th1.example/$repo/os/$arch\n\
+            Server = https://th2.example/$repo/os/$arch\n\
+            \n\
+            ## Worldwide\n\
+            Server = https://geo.example/$repo/os/$arch\n\
+            \n\
+            ## Germany\n\
+            Server = https://de.example/$repo/os/$arch\n\
+            "
+        );
+    }
+
+    #[test]
+    fn test_to_pacman_mirror_list_with_commented_extra() {
+        let mirror = |url: &str| Mirror {
+            url: url.to_string(),
+            country: "Thailand".to_string(),
+            ..Default::default()
+        };
+        let mirrors: Mirrors = [mirror("https://a.example/")].into_iter().collect();
+        let options = MirrorListOptions {
+            verbose: true,
+            commented_extra: [mirror("https://b.example/")].into_iter().collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            mirrors.to_pacman_mirror_list(&options).unwrap(),
+            "\
+            # Thailand, transfer rate: -, weighted score: -\n\
+            Server = https://a.example/$repo/os/$arch\n\
+            \n\
+            ## Runner-up mirrors\n\
+            # Thailand, transfer rate: -, weighted score: -\n\
+            #Server = https://b.example/$repo/os/$arch\n\
+            "
+        );
+    }
+
+    /// Serve `extra.db` under `/ok/` only, on a local port
+    fn serve_database(requests: usize) -> String {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                let status = if request_line.contains(" /ok/extra/os/x86_64/extra.db ") {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn test_verify() {
+        let base = serve_database(4);
+        let mirror = |path: &str| Mirror {
+            url: format!("{base}/{path}/"),
+            ..Default::default()
+        };
+        let agent = http::agent(DEFAULT_MAX_REDIRECTS);
+
+        let mut mirrors: Mirrors = [mirror("ok"), mirror("broken")].into_iter().collect();
+        assert!(mirrors
+            .clone()
+            .verify(&agent, TargetDb::Extra, &Layout::default(), Verify::Fail)
+            .is_err());
+
+        mirrors
+            .verify(&agent, TargetDb::Extra, &Layout::default(), Verify::Drop)
+            .unwrap();
+        assert_eq!(mirrors.len(), 1);
+        assert_eq!(mirrors[0].url, format!("{base}/ok/"));
+    }
+
+    #[test]
+    fn test_require_sig() {
+        let base = serve_database(1);
+        let mut mirror = Mirror::from_url(&format!("{base}/ok/"));
+        let options = BenchmarkOptions {
+            require_sig: true,
+            ..BenchmarkOptions::new(TargetDb::Extra, http::agent(DEFAULT_MAX_REDIRECTS))
+        };
+        assert_eq!(
+            options.required_files(),
+            vec!["extra/os/x86_64/extra.db.sig"]
+        );
+        let files_db = BenchmarkOptions {
+            require_sig: false,
+            require_files_db: true,
+            other_dbs: vec![TargetDb::Multilib],
+            ..options.clone()
+        };
+        assert_eq!(
+            files_db.required_files(),
+            vec![
+                "extra/os/x86_64/extra.files",
+                "multilib/os/x86_64/multilib.files"
+            ]
+        );
+
+        let err = mirror.measure_duration(&options).unwrap_err();
+        assert!(format!("{err:#}").contains("extra.db.sig"));
+        assert_eq!(mirror.transfer_rate, None);
+        assert_eq!(mirror.ttfb, None);
+    }
+
+    #[test]
+    fn test_probe_range() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        // Honor range request under `/range/` only
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(3) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                let response: &str = if request.starts_with("GET /range/")
+                    && request.to_lowercase().contains("range: bytes=0-0")
+                {
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-0/6\r\nContent-Length: 1\r\nConnection: close\r\n\r\nB"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nBZh9AY"
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let options = BenchmarkOptions {
+            require_range: true,
+            ..BenchmarkOptions::new(TargetDb::Extra, ureq::agent())
+        };
+        let mut mirror = Mirror::from_url(&format!("http://{addr}/range/"));
+        mirror.measure_duration(&options).unwrap();
+        assert_eq!(mirror.range_support, Some(true));
+        assert!(mirror.transfer_rate.is_some());
+
+        let mut mirror = Mirror::from_url(&format!("http://{addr}/whole/"));
+        assert!(mirror.measure_duration(&options).is_err());
+        assert_eq!(mirror.range_support, Some(false));
+        assert_eq!(mirror.transfer_rate, None);
+    }
+
+    #[test]
+    fn test_min_tls_without_https() {
+        let mut mirror = Mirror::from_url("http://127.0.0.1:1/");
+        let options = BenchmarkOptions {
+            min_tls: Some(TlsVersion::Tls12),
+            ..BenchmarkOptions::new(TargetDb::Extra, ureq::agent())
+        };
+        let err = mirror.measure_duration(&options).unwrap_err();
+        assert!(format!("{err:#}").contains("does not negotiate TLSv1.2"));
+        assert_eq!(mirror.tls_version, None);
+    }
+
+    #[test]
+    fn test_max_redirects() {
+        use std::{io::Write, net::TcpListener};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 302 Found\r\nLocation: https://elsewhere.example/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+        });
+
+        let mut mirror = Mirror::from_url(&format!("http://{addr}/"));
+        let options = BenchmarkOptions::new(TargetDb::Extra, http::agent(0));
+        let err = mirror.measure_duration(&options).unwrap_err();
+        assert!(format!("{err:#}").contains("redirects to `https://elsewhere.example/`"));
+        assert_eq!(mirror.transfer_rate, None);
+    }
+
+    #[test]
+    fn test_measure_target_dbs() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        // Serve `core.db` only
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(5) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                let response: &str = if request_line.contains("/core.db ") {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nBZh9AY"
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let mut mirror = Mirror::from_url(&format!("http://{addr}/"));
+        let mut options = BenchmarkOptions::new(TargetDb::Core, ureq::agent());
+        mirror.measure_duration(&options).unwrap();
+        assert!(mirror.transfer_rate.is_some());
+
+        options.other_dbs = vec![TargetDb::Multilib];
+        options.db_aggregate = Aggregate::Min;
+        assert_eq!(
+            options.target(),
+            "core/os/x86_64/core.db,multilib/os/x86_64/multilib.db"
+        );
+        assert!(mirror.measure_duration(&options).is_err());
+        assert_eq!(mirror.transfer_rate, None);
+        assert_eq!(
+            mirror.final_url,
+            Some(format!("http://{addr}/core/os/x86_64/core.db"))
+        );
+
+        options.other_dbs.clear();
+        options.family_agents = vec![
+            (IpFamily::V4, http::agent_with_family(0, Some(IpFamily::V4))),
+            (IpFamily::V6, http::agent_with_family(0, Some(IpFamily::V6))),
+        ];
+        mirror.measure_duration(&options).unwrap();
+        assert!(mirror.ipv4_transfer_rate.is_some());
+        assert_eq!(mirror.ipv6_transfer_rate, None);
+        assert_eq!(mirror.faster_family, None);
+        assert_eq!(mirror.broken_ipv6, Some(false));
+
+        options.family = Some(IpFamily::V6);
+        options.other_dbs = vec![TargetDb::Multilib];
+        assert_eq!(
+            options.target(),
+            "core/os/x86_64/core.db,multilib/os/x86_64/multilib.db over IPv6"
+        );
+    }
+
+    #[test]
+    fn test_is_ipv6_broken() {
+        assert_eq!(is_ipv6_broken(Some(1e6), Some(8e5), true), Some(false));
+        assert_eq!(is_ipv6_broken(Some(1e6), Some(5e4), true), Some(true));
+        assert_eq!(is_ipv6_broken(Some(1e6), None, true), Some(true));
+        assert_eq!(is_ipv6_broken(Some(1e6), None, false), Some(false));
+        assert_eq!(is_ipv6_broken(None, Some(1e6), true), None);
+    }
+
+    #[test]
+    fn test_rank_without_status() {
+        let mut mirrors: Mirrors = ["https://slow.example/", "http://fast.example/archlinux"]
+            .into_iter()
+            .map(Mirror::from_url)
+            .collect();
+        assert_eq!(mirrors[1].protocol, "http");
+        mirrors[0].transfer_rate = Some(1000.0);
+        mirrors[1].transfer_rate = Some(2000.0);
+
+        let ranked = mirrors
+            .rank(
+                &SelectOptions::new(2),
+                &ScoreWeights::default().without_status(),
+            )
+            .unwrap();
+        assert_eq!(ranked[0].url, "http://fast.example/archlinux/");
+        assert_eq!(ranked[0].weighted_score, Some(2000.0));
+    }
+
+    #[test]
+    fn test_mean_transfer_rate() {
+        assert_eq!(Mirrors::default().mean_transfer_rate(), 0.0);
+
+        let mirrors: Mirrors = [Some(300.0), None, Some(600.0)]
+            .into_iter()
+            .map(|transfer_rate| Mirror {
+                transfer_rate,
+                ..Default::default()
+            })
+            .collect();
+        assert_eq!(mirrors.mean_transfer_rate(), 300.0);
+    }
+
+    #[test]
+    fn test_pin() {
+        let mut mirrors: Mirrors = [
+            ("https://a.example/", "Thailand"),
+            ("https://internal.example/arch/", "Germany"),
+            ("https://b.example/", "Thailand"),
+        ]
+        .into_iter()
+        .map(|(url, country)| Mirror {
+            url: url.to_string(),
+            country: country.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+        mirrors
+            .pin(&[
+                "https://internal.example/arch".to_string(),
+                "http://10.0.0.1/archlinux/".to_string(),
+            ])
+            .unwrap();
+        let urls: Vec<&str> = mirrors.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://internal.example/arch/",
+                "http://10.0.0.1/archlinux/",
+                "https://a.example/",
+                "https://b.example/",
+            ]
+        );
+
+        let options = MirrorListOptions {
+            group_by_country: true,
+            ..Default::default()
+        };
+        assert!(mirrors
+            .to_pacman_mirror_list(&options)
+            .unwrap()
+            .starts_with("## Pinned\nServer = https://internal.example/arch/$repo/os/$arch\n"));
+
+        assert!(mirrors.pin(&["not a url".to_string()]).is_err());
+
+        let urls = read_urls(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/pinned_mirrors"
+        )))
+        .unwrap();
+        assert_eq!(
+            urls,
+            vec![
+                "https://internal.example/archlinux/",
+                "http://10.0.0.1/archlinux/"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mirrors: Mirrors = [Mirror {
+            url: "https://mirror.example/archlinux/".to_string(),
+            country: "Thailand".to_string(),
+            country_code: "TH".to_string(),
+            score: Some(1.5),
+            transfer_rate: Some(1000.0),
+            weighted_score: Some(2.5),
+            ..Default::default()
+        }]
+        .into_iter()
+        .collect();
+
+        let json: serde_json::Value = serde_json::from_str(&mirrors.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "url": "https://mirror.example/archlinux/",
+                "country": "Thailand",
+                "country_code": "TH",
+                "score": 1.5,
+                "transfer_rate": 1000.0,
+                "weighted_score": 2.5
+            }])
+        );
+    }
+
+    #[test]
+    fn test_stats_format_from_path() {
+        assert_eq!(
+            StatsFormat::from_path(Path::new("/tmp/stats.json")),
+            StatsFormat::Json
+        );
+        assert_eq!(
+            StatsFormat::from_path(Path::new("/tmp/stats.TSV")),
+            StatsFormat::Tsv
+        );
+        assert_eq!(
+            StatsFormat::from_path(Path::new("/tmp/stats.csv")),
+            StatsFormat::Csv
+        );
+        assert_eq!(
+            StatsFormat::from_path(Path::new("/tmp/stats")),
+            StatsFormat::Csv
+        );
+    }
+
+    #[test]
+    fn test_write_stats() {
+        let mirrors: Mirrors = ["https://a.example/", "https://b.example/"]
+            .into_iter()
+            .map(|url| Mirror {
+                url: url.to_string(),
+                country: "Thailand".to_string(),
+                transfer_rate: Some(1000.0),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut csv: Vec<u8> = Vec::new();
+        mirrors.write_stats(&mut csv, StatsFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.starts_with("url,"));
+
+        let mut tsv: Vec<u8> = Vec::new();
+        mirrors.write_stats(&mut tsv, StatsFormat::Tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert_eq!(tsv.replace('\t', ","), csv);
+
+        let mut json: Vec<u8> = Vec::new();
+        mirrors.write_stats(&mut json, StatsFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[1]["url"], "https://b.example/");
+        assert_eq!(json[1]["transfer_rate"], 1000.0);
+    }
 }
@@ -0,0 +1,145 @@
+use std::{fs, io, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+use crate::output::{self, Overwrite};
+
+/// First line of block generated by pacman-mirrorup
+pub const BEGIN_MARKER: &str = "# BEGIN pacman-mirrorup";
+
+/// Last line of block generated by pacman-mirrorup
+pub const END_MARKER: &str = "# END pacman-mirrorup";
+
+/// Base URLs of active servers of mirrorlist, in order
+pub fn servers(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            if key.trim() != "Server" {
+                return None;
+            }
+            let value: &str = value.trim();
+            let base: &str = value.find("$repo").map_or(value, |i| &value[..i]);
+            Some(base.to_string())
+        })
+        .filter(|base| !base.is_empty())
+        .collect()
+}
+
+/// Base URLs of active servers of mirrorlist file
+pub fn read_servers(path: &Path) -> Result<Vec<String>> {
+    let content: String =
+        fs::read_to_string(path).with_context(|| format!("Could not read `{}`", path.display()))?;
+    Ok(servers(&content))
+}
+
+/// Replace block generated by pacman-mirrorup in existing mirrorlist,
+/// keeping custom servers and comments outside of it.
+///
+/// Without a generated block, the new block is placed on top of existing content,
+/// so that ranked mirrors take precedence over custom servers.
+pub fn merge(existing: &str, generated: &str) -> Result<String> {
+    let lines: Vec<&str> = existing.split_inclusive('\n').collect();
+    let begin: Option<usize> = lines
+        .iter()
+        .position(|line| line.trim_end() == BEGIN_MARKER);
+
+    let Some(begin) = begin else {
+        if existing.trim().is_empty() {
+            return Ok(generated.to_string());
+        }
+        return Ok(format!("{generated}\n{existing}"));
+    };
+
+    let Some(end) = lines[begin..]
+        .iter()
+        .position(|line| line.trim_end() == END_MARKER)
+        .map(|end| begin + end)
+    else {
+        bail!("Block generated by pacman-mirrorup is not terminated by `{END_MARKER}`");
+    };
+
+    Ok(format!(
+        "{before}{generated}{after}",
+        before = lines[..begin].concat(),
+        after = lines[end + 1..].concat()
+    ))
+}
+
+/// Merge generated block into mirrorlist file, creating it if it does not exist
+pub fn merge_into_file(path: &Path, generated: &str) -> Result<()> {
+    let existing: String = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not read `{}`", path.display()))
+        }
+    };
+
+    let merged: String = merge(&existing, generated)
+        .with_context(|| format!("Could not merge into `{}`", path.display()))?;
+    output::save(path, merged.as_bytes(), Overwrite::Always)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENERATED: &str = "# BEGIN pacman-mirrorup\nServer = https://new.example/$repo/os/$arch\n# END pacman-mirrorup\n";
+
+    #[test]
+    fn test_merge() {
+        let existing = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/merged_mirrorlist"
+        ));
+        let merged = merge(existing, GENERATED).unwrap();
+        assert_eq!(
+            merged,
+            "\
+            # Internal mirror\n\
+            Server = https://internal.example/$repo/os/$arch\n\
+            \n\
+            # BEGIN pacman-mirrorup\n\
+            Server = https://new.example/$repo/os/$arch\n\
+            # END pacman-mirrorup\n\
+            \n\
+            #Server = https://disabled.example/$repo/os/$arch\n\
+            "
+        );
+
+        // Merging again only replaces generated block
+        assert_eq!(merge(&merged, GENERATED).unwrap(), merged);
+    }
+
+    #[test]
+    fn test_servers() {
+        let existing = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/merged_mirrorlist"
+        ));
+        assert_eq!(
+            servers(existing),
+            vec!["https://internal.example/", "https://old.example/"]
+        );
+        assert_eq!(
+            servers("  Server=https://a.example/archlinux/$repo/os/$arch\n#Server = x\nInclude = /etc/pacman.d/other\n"),
+            vec!["https://a.example/archlinux/"]
+        );
+    }
+
+    #[test]
+    fn test_merge_without_block() {
+        assert_eq!(merge("", GENERATED).unwrap(), GENERATED);
+        assert_eq!(
+            merge(
+                "Server = https://custom.example/$repo/os/$arch\n",
+                GENERATED
+            )
+            .unwrap(),
+            format!("{GENERATED}\nServer = https://custom.example/$repo/os/$arch\n")
+        );
+        assert!(merge("# BEGIN pacman-mirrorup\nServer = x\n", GENERATED).is_err());
+    }
+}
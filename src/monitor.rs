@@ -0,0 +1,227 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use tracing::{info, warn};
+
+use crate::{
+    args::{Arguments, MonitorArgs},
+    concurrency::{self, Threads},
+    daemon, http,
+    mirror::{self, BenchmarkOptions, Evaluation, Mirror, Mirrors},
+    mirrorlist, netinfo, output,
+};
+
+/// Latest metrics in Prometheus text format, shared with HTTP endpoint
+type Metrics = Arc<RwLock<String>>;
+
+/// Run `monitor` subcommand, benchmarking mirrors of `--input-mirrorlist` or `--urls-from`
+/// every interval
+pub fn run(arguments: &Arguments, args: &MonitorArgs) -> Result<()> {
+    let urls: Vec<String> = match (&arguments.input_mirrorlist, &arguments.urls_from) {
+        (Some(path), _) => mirrorlist::read_servers(path)?,
+        (None, Some(path)) => mirror::read_urls(path)?,
+        (None, None) => bail!("`monitor` requires `--input-mirrorlist` or `--urls-from`"),
+    };
+    let mut mirrors: Mirrors = urls.iter().map(|url| Mirror::from_url(url)).collect();
+    mirrors.collapse_duplicates();
+    if mirrors.is_empty() {
+        bail!("No mirrors to monitor");
+    }
+
+    let options = BenchmarkOptions {
+        transfer_limit: arguments.transfer_limit,
+        samples: arguments.samples,
+        aggregate: arguments.aggregate,
+        reject_outliers: arguments.reject_outliers,
+        layout: arguments.layout(),
+        other_dbs: arguments.target_dbs[1..].to_vec(),
+        db_aggregate: arguments.target_db_aggregate,
+        family: arguments.resolve,
+        ..BenchmarkOptions::new(
+            arguments.target_db(),
+            http::agent_with_dns_ttl(
+                arguments.max_redirects,
+                arguments.resolve,
+                http::dns_cache_ttl(args.interval),
+            ),
+        )
+    };
+    let threads: usize = match arguments.threads {
+        Threads::Fixed(n) => n,
+        Threads::Auto => concurrency::auto_threads(&mirrors, &options),
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context("Failed to build thread pool")?;
+
+    let metrics: Metrics = Arc::default();
+    if let Some(listen) = &args.listen {
+        let listener =
+            TcpListener::bind(listen).with_context(|| format!("Could not listen on `{listen}`"))?;
+        info!("Serve metrics on http://{listen}/metrics");
+        let metrics = Arc::clone(&metrics);
+        thread::spawn(move || serve(listener, metrics));
+    }
+
+    info!("Monitor {} mirrors", mirrors.len());
+    daemon::run(args.interval, || {
+        netinfo::clear_caches();
+        let measured: Mirrors = pool.install(|| mirrors.benchmark(&options));
+        let text: String = render(&measured, Utc::now().timestamp());
+        if let Some(textfile) = &args.textfile {
+            write_textfile(textfile, &text)?;
+        }
+        *metrics.write().unwrap_or_else(|e| e.into_inner()) = text;
+        Ok(())
+    })
+}
+
+/// Answer `GET /metrics` with latest metrics, anything else with 404
+fn serve(listener: TcpListener, metrics: Metrics) {
+    for stream in listener.incoming() {
+        let result = stream.map_err(anyhow::Error::from).and_then(|stream| {
+            let metrics: String = metrics.read().unwrap_or_else(|e| e.into_inner()).clone();
+            respond(stream, &metrics)
+        });
+        if let Err(err) = result {
+            warn!("Failed to serve metrics: {err:#}");
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &str) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let response: String = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{metrics}",
+            metrics.len()
+        ),
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Replace textfile of node exporter atomically, so it never reads a partial file
+fn write_textfile(path: &Path, text: &str) -> Result<()> {
+    output::write_file(path, text.as_bytes())
+        .with_context(|| format!("Failed to write metrics `{}`", path.display()))
+}
+
+/// Metrics of benchmarked mirrors in Prometheus text format
+fn render(mirrors: &Mirrors, timestamp: i64) -> String {
+    type Measurement = fn(&Mirror) -> Option<f64>;
+    let gauges: [(&str, &str, Measurement); 5] = [
+        (
+            "up",
+            "Whether the last benchmark of mirror succeeded",
+            |m| Some(if m.transfer_rate.is_some() { 1.0 } else { 0.0 }),
+        ),
+        (
+            "transfer_rate_bytes",
+            "Transfer rate of target database in bytes per second",
+            |m| m.transfer_rate,
+        ),
+        (
+            "transfer_rate_stddev_bytes",
+            "Standard deviation of transfer rate over samples in bytes per second",
+            |m| m.transfer_rate_stddev,
+        ),
+        (
+            "ttfb_seconds",
+            "Time to first byte of target database",
+            |m| m.ttfb,
+        ),
+        (
+            "transfer_time_seconds",
+            "Time to transfer target database",
+            |m| m.transfer_time,
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, help, measurement) in gauges {
+        let _ = writeln!(text, "# HELP pacman_mirrorup_{name} {help}");
+        let _ = writeln!(text, "# TYPE pacman_mirrorup_{name} gauge");
+        for mirror in mirrors.iter() {
+            if let Some(value) = measurement(mirror) {
+                let _ = writeln!(
+                    text,
+                    "pacman_mirrorup_{name}{{url=\"{}\"}} {value}",
+                    escape(&mirror.url)
+                );
+            }
+        }
+    }
+    let name = "pacman_mirrorup_last_run_timestamp_seconds";
+    let _ = writeln!(text, "# HELP {name} Time the last benchmark finished");
+    let _ = writeln!(text, "# TYPE {name} gauge");
+    let _ = writeln!(text, "{name} {timestamp}");
+    text
+}
+
+/// Escape label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut up = Mirror::from_url("https://a.example/");
+        up.transfer_rate = Some(1024.0);
+        up.ttfb = Some(0.25);
+        let down = Mirror::from_url("https://b.example/");
+        let mirrors: Mirrors = [up, down].into_iter().collect();
+
+        let text: String = render(&mirrors, 1700000000);
+        assert!(text.contains("# TYPE pacman_mirrorup_up gauge\n"));
+        assert!(text.contains("pacman_mirrorup_up{url=\"https://a.example/\"} 1\n"));
+        assert!(text.contains("pacman_mirrorup_up{url=\"https://b.example/\"} 0\n"));
+        assert!(
+            text.contains("pacman_mirrorup_transfer_rate_bytes{url=\"https://a.example/\"} 1024\n")
+        );
+        assert!(!text.contains("pacman_mirrorup_transfer_rate_bytes{url=\"https://b.example/\"}"));
+        assert!(text.contains("pacman_mirrorup_ttfb_seconds{url=\"https://a.example/\"} 0.25\n"));
+        assert!(text.ends_with("pacman_mirrorup_last_run_timestamp_seconds 1700000000\n"));
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics: Metrics = Arc::new(RwLock::new("pacman_mirrorup_up 1\n".to_string()));
+        thread::spawn(move || serve(listener, metrics));
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response: String = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\npacman_mirrorup_up 1\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
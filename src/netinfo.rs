@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{BufRead, BufReader, Read},
+    net::{IpAddr, ToSocketAddrs},
+    path::Path,
+    str::FromStr,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use tracing::debug;
+use url::Url;
+
+use crate::{dns, http, mirror::DEFAULT_MAX_REDIRECTS};
+
+/// Service used to find the origin AS of an address
+const ASN_LOOKUP_URL: &str = "https://stat.ripe.net/data/network-info/data.json";
+
+const ASN_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+static AGENT: LazyLock<ureq::Agent> = LazyLock::new(|| http::agent(DEFAULT_MAX_REDIRECTS));
+
+/// Resolved addresses by host name
+static ADDRS: LazyLock<Mutex<HashMap<String, Vec<IpAddr>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Address ranges and their origin AS, sorted by start of range
+static ASN_DB: OnceLock<Vec<(IpAddr, IpAddr, u32)>> = OnceLock::new();
+
+/// CDN serving host, by host name
+static CDNS: LazyLock<Mutex<HashMap<String, Option<&'static str>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Known CDNs by domain suffix of their host names
+const CDN_SUFFIXES: &[(&str, &str)] = &[
+    ("akamai.net", "Akamai"),
+    ("akamaiedge.net", "Akamai"),
+    ("akamaized.net", "Akamai"),
+    ("edgekey.net", "Akamai"),
+    ("edgesuite.net", "Akamai"),
+    ("azureedge.net", "Azure"),
+    ("azurefd.net", "Azure"),
+    ("b-cdn.net", "Bunny"),
+    ("cdn77.org", "CDN77"),
+    ("cdn.cloudflare.net", "Cloudflare"),
+    ("cloudfront.net", "CloudFront"),
+    ("edgecastcdn.net", "Edgecast"),
+    ("fastly.net", "Fastly"),
+    ("fastlylb.net", "Fastly"),
+    ("gcdn.co", "Gcore"),
+    ("kxcdn.com", "KeyCDN"),
+    ("llnwd.net", "Limelight"),
+];
+
+/// Origin AS by address, None if not routed
+static ASNS: LazyLock<Mutex<HashMap<IpAddr, Option<u32>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// IP address range in CIDR notation, e.g. `203.0.113.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask: u32 = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask: u128 = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("Invalid address in `{s}`"))?;
+        let max_prefix: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix: u8 = match prefix {
+            Some(p) => p
+                .parse()
+                .with_context(|| format!("Invalid prefix length in `{s}`"))?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            bail!("Prefix length of `{s}` is greater than {max_prefix}");
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Lowercase host of URL, without brackets of IPv6 address
+fn host(url: &str) -> Option<String> {
+    Url::parse(url).ok().and_then(|u| {
+        u.host_str()
+            .map(|h| h.trim_matches(['[', ']']).to_lowercase())
+    })
+}
+
+/// Forget resolved addresses, CDNs and AS numbers, so that each update cycle
+/// of a long-running process sees current ones
+pub fn clear_caches() {
+    if let Ok(mut cache) = ADDRS.lock() {
+        cache.clear();
+    }
+    if let Ok(mut cache) = CDNS.lock() {
+        cache.clear();
+    }
+    if let Ok(mut cache) = ASNS.lock() {
+        cache.clear();
+    }
+}
+
+/// Resolve addresses of mirror's host, cached until [`clear_caches`].
+/// Failed lookups are not cached.
+pub fn resolve(url: &str) -> Vec<IpAddr> {
+    let Some(host) = host(url) else {
+        return Vec::new();
+    };
+
+    if let Some(addrs) = ADDRS.lock().ok().and_then(|c| c.get(&host).cloned()) {
+        return addrs;
+    }
+
+    let addrs: Vec<IpAddr> = match (host.as_str(), 443).to_socket_addrs() {
+        Ok(addrs) => addrs.map(|a| a.ip()).collect(),
+        Err(err) => {
+            debug!("Could not resolve `{host}`: {err}");
+            return Vec::new();
+        }
+    };
+    if let Ok(mut cache) = ADDRS.lock() {
+        cache.insert(host, addrs.clone());
+    }
+    addrs
+}
+
+/// CDN serving mirror's host, detected from its name and CNAME chain,
+/// cached until [`clear_caches`] unless CNAME lookup failed
+pub fn cdn(url: &str) -> Option<&'static str> {
+    let host: String = host(url).filter(|host| host.parse::<IpAddr>().is_err())?;
+    if let Some(cdn) = CDNS.lock().ok().and_then(|c| c.get(&host).copied()) {
+        return cdn;
+    }
+
+    let chain: Vec<String> = match dns::cname_chain(&host) {
+        Ok(chain) => chain,
+        Err(err) => {
+            debug!("Could not look up CNAME of `{host}`: {err:#}");
+            return cdn_of(&host);
+        }
+    };
+    let cdn: Option<&'static str> = std::iter::once(&host)
+        .chain(&chain)
+        .find_map(|name| cdn_of(name));
+    if let Ok(mut cache) = CDNS.lock() {
+        cache.insert(host, cdn);
+    }
+    cdn
+}
+
+/// CDN of known domain suffix of `name`
+fn cdn_of(name: &str) -> Option<&'static str> {
+    let name: &str = name.trim_end_matches('.');
+    CDN_SUFFIXES
+        .iter()
+        .find(|(suffix, _)| {
+            name.strip_suffix(suffix)
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+        })
+        .map(|(_, cdn)| *cdn)
+}
+
+#[derive(Deserialize)]
+struct NetworkInfo {
+    data: NetworkInfoData,
+}
+
+#[derive(Deserialize)]
+struct NetworkInfoData {
+    asns: Vec<String>,
+}
+
+/// Origin AS number of address, cached until [`clear_caches`] unless lookup failed
+pub fn asn(ip: &IpAddr) -> Option<u32> {
+    if let Some(db) = ASN_DB.get() {
+        return lookup_asn_db(db, ip);
+    }
+    if let Some(asn) = ASNS.lock().ok().and_then(|c| c.get(ip).copied()) {
+        return asn;
+    }
+
+    let asn: Option<u32> = match lookup_asn(ip) {
+        Ok(asn) => asn,
+        Err(err) => {
+            debug!("{err:#}");
+            return None;
+        }
+    };
+    if let Ok(mut cache) = ASNS.lock() {
+        cache.insert(*ip, asn);
+    }
+    asn
+}
+
+/// Look up AS numbers from IP-to-ASN database at `path` instead of RIPEstat, returns number of ranges.
+///
+/// The database is a TSV file, optionally gzip-compressed, of `start end AS-number ...` lines
+/// like https://iptoasn.com/[iptoasn].
+pub fn load_asn_db(path: &Path) -> Result<usize> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open IP-to-ASN database `{}`", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let reader: Box<dyn Read> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    let db = parse_asn_db(reader)
+        .with_context(|| format!("Failed to read IP-to-ASN database `{}`", path.display()))?;
+    let count: usize = db.len();
+    if ASN_DB.set(db).is_err() {
+        bail!("IP-to-ASN database is already loaded");
+    }
+    Ok(count)
+}
+
+fn parse_asn_db<R: Read>(reader: R) -> Result<Vec<(IpAddr, IpAddr, u32)>> {
+    let mut db: Vec<(IpAddr, IpAddr, u32)> = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line: String = line?;
+        let mut fields = line.split('\t');
+        let (Some(start), Some(end), Some(asn)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let parse = || -> Option<(IpAddr, IpAddr, u32)> {
+            Some((start.parse().ok()?, end.parse().ok()?, asn.parse().ok()?))
+        };
+        db.push(parse().with_context(|| format!("Invalid line {}: `{line}`", i + 1))?);
+    }
+    db.sort_unstable();
+    Ok(db)
+}
+
+/// Origin AS of `ip` in sorted ranges, None if not routed (AS 0)
+fn lookup_asn_db(db: &[(IpAddr, IpAddr, u32)], ip: &IpAddr) -> Option<u32> {
+    let i: usize = db.partition_point(|(start, _, _)| start <= ip);
+    let (_, end, asn) = db.get(i.checked_sub(1)?)?;
+    (ip <= end && *asn != 0).then_some(*asn)
+}
+
+fn lookup_asn(ip: &IpAddr) -> Result<Option<u32>> {
+    let info: NetworkInfo = AGENT
+        .get(ASN_LOOKUP_URL)
+        .query("resource", &ip.to_string())
+        .timeout(ASN_LOOKUP_TIMEOUT)
+        .call()
+        .with_context(|| format!("Failed to look up ASN of `{ip}`"))?
+        .into_json()
+        .with_context(|| format!("Failed to deserialize ASN of `{ip}`"))?;
+    Ok(info.data.asns.first().and_then(|asn| asn.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr() {
+        let net: Cidr = "203.0.113.0/24".parse().unwrap();
+        assert!(net.contains(&"203.0.113.7".parse().unwrap()));
+        assert!(!net.contains(&"203.0.114.7".parse().unwrap()));
+        assert!(!net.contains(&"2001:db8::1".parse().unwrap()));
+
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!net.contains(&"2001:db9::1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"198.51.100.1".parse().unwrap()));
+
+        let host: Cidr = "198.51.100.1".parse().unwrap();
+        assert_eq!(host.to_string(), "198.51.100.1/32");
+        assert!(!host.contains(&"198.51.100.2".parse().unwrap()));
+
+        assert!("203.0.113.0/33".parse::<Cidr>().is_err());
+        assert!("mirror.example/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_asn_db() {
+        let db = parse_asn_db(
+            fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/ip2asn.tsv")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(db.len(), 4);
+        let asn = |ip: &str| lookup_asn_db(&db, &ip.parse().unwrap());
+        assert_eq!(asn("1.0.0.1"), Some(13335));
+        assert_eq!(asn("1.0.2.1"), None);
+        assert_eq!(asn("203.0.113.255"), Some(64496));
+        assert_eq!(asn("203.0.114.1"), None);
+        assert_eq!(asn("0.0.0.1"), None);
+        assert_eq!(asn("2001:db8::1"), Some(64497));
+
+        assert!(parse_asn_db(b"1.0.0.0\tnot an address\t1\n".as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_cdn_of() {
+        assert_eq!(cdn_of("dualstack.k.sni.global.fastly.net."), Some("Fastly"));
+        assert_eq!(cdn_of("d1234.cloudfront.net"), Some("CloudFront"));
+        assert_eq!(cdn_of("mirror.cdn.cloudflare.net"), Some("Cloudflare"));
+        assert_eq!(cdn_of("notfastly.net"), None);
+        assert_eq!(cdn_of("mirror.example.org"), None);
+        assert_eq!(cdn("https://203.0.113.7/archlinux/"), None);
+    }
+
+    #[test]
+    fn test_resolve_ip_literal() {
+        assert_eq!(
+            resolve("https://203.0.113.7/archlinux/"),
+            vec!["203.0.113.7".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            resolve("https://[2001:db8::1]/archlinux/"),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+}
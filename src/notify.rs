@@ -0,0 +1,96 @@
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    sync::OnceLock,
+};
+
+use tracing::debug;
+
+/// Socket of systemd service manager given by `NOTIFY_SOCKET`
+struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Notifier {
+    /// Path, or abstract socket name starting with `@`
+    fn new(notify_socket: &OsStr) -> io::Result<Self> {
+        let addr: SocketAddr = match notify_socket.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(notify_socket)?,
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        })
+    }
+
+    fn send(&self, state: &str) -> io::Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+}
+
+/// None if not run by systemd, or its socket cannot be used
+fn notifier() -> Option<&'static Notifier> {
+    static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+    NOTIFIER
+        .get_or_init(|| {
+            let notify_socket = env::var_os("NOTIFY_SOCKET")?;
+            Notifier::new(&notify_socket)
+                .map_err(|err| debug!("Could not use `NOTIFY_SOCKET`: {err}"))
+                .ok()
+        })
+        .as_ref()
+}
+
+/// Send state to systemd, see sd_notify(3). Does nothing if not run by systemd.
+pub fn notify(state: &str) {
+    if let Some(notifier) = notifier() {
+        if let Err(err) = notifier.send(state) {
+            debug!("Could not notify systemd of `{state}`: {err}");
+        }
+    }
+}
+
+/// Startup is finished
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Human-readable status shown by `systemctl status`
+pub fn status(status: &str) {
+    notify(&format!("STATUS={status}"));
+}
+
+/// Shutdown is started
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifier() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::new(path.as_os_str()).unwrap();
+        notifier.send("STATUS=Benchmarking 1/2 mirrors").unwrap();
+
+        let mut buf = [0_u8; 64];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STATUS=Benchmarking 1/2 mirrors");
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+
+/// How an existing file is handled when saving output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+    /// Fail if file exists
+    #[default]
+    Never,
+
+    /// Replace existing file
+    Always,
+
+    /// Replace existing file, keeping the old one as `<path>.bak`
+    Backup,
+
+    /// Replace existing file, keeping the last N old ones as `<path>.1` (newest) to `<path>.N`
+    Rotate(usize),
+}
+
+/// Print files instead of writing them
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Save `contents` to `path` atomically, or print what would be written in dry-run mode
+pub fn save(path: &Path, contents: &[u8], overwrite: Overwrite) -> Result<()> {
+    if is_dry_run() {
+        let action: &str = if path.exists() { "replace" } else { "create" };
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "==> Would {action} `{}`", path.display())?;
+        stdout.write_all(contents)?;
+        if !contents.ends_with(b"\n") {
+            writeln!(stdout)?;
+        }
+        return Ok(());
+    }
+
+    match overwrite {
+        Overwrite::Never => create_file(path, contents),
+        Overwrite::Always => write_file(path, contents),
+        Overwrite::Backup => replace_file(path, contents),
+        Overwrite::Rotate(n) => {
+            rotate_backups(path, n)?;
+            write_file(path, contents)
+        }
+    }
+}
+
+/// Ask yes/no `question` on `output`, only an answer starting with `y` or `Y` is yes
+pub fn confirm(
+    question: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<bool> {
+    write!(output, "{question} [y/N] ")?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim_start().starts_with(['y', 'Y']))
+}
+
+/// `<path>.bak`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name: OsString = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// `<path>.<n>`
+pub fn numbered_backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name: OsString = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Shift `<path>.1` .. `<path>.<n-1>` by one, dropping the oldest, then copy `path` to `<path>.1`
+fn rotate_backups(path: &Path, n: usize) -> Result<()> {
+    if n == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    for i in (1..n).rev() {
+        let from: PathBuf = numbered_backup_path(path, i);
+        if from.exists() {
+            let to: PathBuf = numbered_backup_path(path, i + 1);
+            fs::rename(&from, &to).with_context(|| {
+                format!(
+                    "Could not rename `{}` to `{}`",
+                    from.display(),
+                    to.display()
+                )
+            })?;
+        }
+    }
+
+    let backup: PathBuf = numbered_backup_path(path, 1);
+    fs::copy(path, &backup).with_context(|| {
+        format!(
+            "Could not back up `{}` to `{}`",
+            path.display(),
+            backup.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// Temporary file next to `path`, so it can be renamed over `path` atomically
+fn temp_path(path: &Path) -> PathBuf {
+    let name: OsString = path.file_name().map(OsString::from).unwrap_or_default();
+    let mut temp: OsString = OsString::from(".");
+    temp.push(name);
+    temp.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(temp)
+}
+
+/// Write `contents` to a synced temporary file next to `path`
+fn write_temp(
+    path: &Path,
+    contents: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> Result<PathBuf> {
+    let temp: PathBuf = temp_path(path);
+    let result: Result<()> = (|| {
+        let mut file = File::create(&temp)
+            .with_context(|| format!("Could not create file `{}`", temp.display()))?;
+        file.write_all(contents)?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.sync_all()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => Ok(temp),
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+/// Create new file at `path` with `contents`, fails if it already exists.
+///
+/// `contents` is written to a temporary file in the same directory which is then linked to `path`,
+/// so a crash never leaves a partially written file behind.
+pub fn create_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let temp: PathBuf = write_temp(path, contents, None)?;
+    let result: Result<()> = match fs::hard_link(&temp, path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            Err(err).with_context(|| format!("Could not create file `{}`", path.display()))
+        }
+        // File system without hard links
+        Err(_) if !path.exists() => fs::rename(&temp, path)
+            .with_context(|| format!("Could not create file `{}`", path.display())),
+        Err(err) => Err(err).with_context(|| format!("Could not create file `{}`", path.display())),
+    };
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// Write `contents` to file at `path`, replacing it atomically if it exists.
+///
+/// Readers see either the old or the new file, never a partially written one.
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let permissions: Option<fs::Permissions> = fs::metadata(path).ok().map(|m| m.permissions());
+    let temp: PathBuf = write_temp(path, contents, permissions)?;
+    fs::rename(&temp, path).map_err(|err| {
+        let _ = fs::remove_file(&temp);
+        anyhow::Error::new(err).context(format!(
+            "Could not rename `{}` to `{}`",
+            temp.display(),
+            path.display()
+        ))
+    })
+}
+
+/// Replace file at `path` with `contents` atomically, keeping a copy of the old file as `<path>.bak`
+pub fn replace_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if path.exists() {
+        let backup: PathBuf = backup_path(path);
+        fs::copy(path, &backup).with_context(|| {
+            format!(
+                "Could not back up `{}` to `{}`",
+                path.display(),
+                backup.display()
+            )
+        })?;
+    }
+    write_file(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mirrorlist");
+
+        // New file, nothing to back up
+        replace_file(&path, b"Server = https://old.example/$repo/os/$arch\n").unwrap();
+        assert!(!backup_path(&path).exists());
+
+        replace_file(&path, b"Server = https://new.example/$repo/os/$arch\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Server = https://new.example/$repo/os/$arch\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backup_path(&path)).unwrap(),
+            "Server = https://old.example/$repo/os/$arch\n"
+        );
+
+        // No temporary file left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_rotate_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mirrorlist");
+
+        for run in 1..=4 {
+            save(
+                &path,
+                format!("run {run}\n").as_bytes(),
+                Overwrite::Rotate(2),
+            )
+            .unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "run 4\n");
+        assert_eq!(
+            fs::read_to_string(numbered_backup_path(&path, 1)).unwrap(),
+            "run 3\n"
+        );
+        assert_eq!(
+            fs::read_to_string(numbered_backup_path(&path, 2)).unwrap(),
+            "run 2\n"
+        );
+        assert!(!numbered_backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_create_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+
+        create_file(&path, b"url\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "url\n");
+
+        // Existing file is kept
+        assert!(create_file(&path, b"other\n").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "url\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_confirm() {
+        let mut question: Vec<u8> = Vec::new();
+        assert!(confirm("Write?", &b"y\n"[..], &mut question).unwrap());
+        assert_eq!(question, b"Write? [y/N] ");
+        assert!(confirm("Write?", &b"Yes\n"[..], io::sink()).unwrap());
+        assert!(!confirm("Write?", &b"\n"[..], io::sink()).unwrap());
+        assert!(!confirm("Write?", &b""[..], io::sink()).unwrap());
+    }
+
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(
+            backup_path(Path::new("/etc/pacman.d/mirrorlist")),
+            PathBuf::from("/etc/pacman.d/mirrorlist.bak")
+        );
+        assert_eq!(
+            temp_path(Path::new("/etc/pacman.d/mirrorlist")),
+            PathBuf::from(format!(
+                "/etc/pacman.d/.mirrorlist.{}.tmp",
+                std::process::id()
+            ))
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    io::Read,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use tracing::info;
+use url::Url;
+
+/// Largest database file downloaded to pick a package from
+const MAX_DB_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Package of roughly `size` bytes measured in place of database file,
+/// picked once from the database of the first mirror that serves it
+#[derive(Debug, Clone)]
+pub struct BenchmarkPackage {
+    size: u64,
+    file: Arc<Mutex<Option<String>>>,
+}
+
+impl BenchmarkPackage {
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            file: Arc::default(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// File name of picked package, downloading database at `db_url` if none is picked yet
+    pub fn file(&self, agent: &ureq::Agent, db_url: &Url) -> Result<String> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(file) = file.as_ref() {
+            return Ok(file.clone());
+        }
+
+        let response = agent
+            .get(db_url.as_str())
+            .timeout(Duration::from_secs(60))
+            .call()
+            .with_context(|| format!("Failed to fetch `{db_url}`"))?;
+        let (picked, csize) = pick(response.into_reader().take(MAX_DB_SIZE), self.size)
+            .with_context(|| format!("Failed to pick package from `{db_url}`"))?;
+        info!("Benchmark with package `{picked}` of {csize} bytes");
+        *file = Some(picked.clone());
+        Ok(picked)
+    }
+}
+
+/// File name and size of package whose size is the closest to `size`,
+/// from gzip-compressed repository database
+fn pick<R: Read>(db: R, size: u64) -> Result<(String, u64)> {
+    let mut db = std::io::BufReader::new(db);
+    let magic: &[u8] = std::io::BufRead::fill_buf(&mut db)?;
+    if !magic.starts_with(&[0x1f, 0x8b]) {
+        bail!("Database is not gzip-compressed");
+    }
+
+    let mut archive = tar::Archive::new(GzDecoder::new(db));
+    let mut best: Option<(String, u64)> = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.path()?.ends_with("desc") {
+            continue;
+        }
+        let mut desc = String::new();
+        entry.read_to_string(&mut desc)?;
+        let Some((file, csize)) = parse_desc(&desc) else {
+            continue;
+        };
+        if best
+            .as_ref()
+            .is_none_or(|(_, best)| best.abs_diff(size) > csize.abs_diff(size))
+        {
+            best = Some((file, csize));
+        }
+    }
+    best.context("No package in database")
+}
+
+/// `%FILENAME%` and `%CSIZE%` of package description
+fn parse_desc(desc: &str) -> Option<(String, u64)> {
+    let value = |key: &str| -> Option<&str> {
+        let mut lines = desc.lines();
+        lines.find(|line| *line == key)?;
+        lines.next()
+    };
+    let file: &str = value("%FILENAME%").filter(|file| !file.contains('/'))?;
+    let csize: u64 = value("%CSIZE%")?.parse().ok()?;
+    Some((file.to_string(), csize))
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    fn database(packages: &[(&str, u64)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for (name, csize) in packages {
+            let desc: String = format!("%FILENAME%\n{name}\n\n%CSIZE%\n{csize}\n");
+            let mut header = tar::Header::new_gnu();
+            header.set_size(desc.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("{name}/desc"), desc.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_pick() {
+        let db: Vec<u8> = database(&[
+            ("small-1.0-1-any.pkg.tar.zst", 10_000),
+            ("medium-1:2.0-1-x86_64.pkg.tar.zst", 40_000_000),
+            ("large-3.0-1-x86_64.pkg.tar.zst", 200_000_000),
+        ]);
+        assert_eq!(
+            pick(db.as_slice(), 50_000_000).unwrap(),
+            ("medium-1:2.0-1-x86_64.pkg.tar.zst".to_string(), 40_000_000)
+        );
+        assert_eq!(pick(db.as_slice(), 0).unwrap().1, 10_000);
+
+        assert!(pick(database(&[]).as_slice(), 1).is_err());
+        assert!(pick(b"not a database".as_slice(), 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_desc() {
+        assert_eq!(
+            parse_desc("%NAME%\nfoo\n\n%FILENAME%\nfoo-1-1-any.pkg.tar.zst\n\n%CSIZE%\n42\n"),
+            Some(("foo-1-1-any.pkg.tar.zst".to_string(), 42))
+        );
+        assert_eq!(parse_desc("%FILENAME%\nfoo-1-1-any.pkg.tar.zst\n"), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{env, ffi::OsString, fs, os::unix::fs::MetadataExt, path::PathBuf};
+
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Where a kind of persistent files is kept
+struct BaseDir {
+    /// Set by systemd for `CacheDirectory=`, `StateDirectory=`, etc. of the unit
+    systemd: &'static str,
+
+    /// XDG base directory variable
+    xdg: &'static str,
+
+    /// XDG default relative to home directory
+    home: &'static str,
+
+    /// System-wide directory used by root
+    system: &'static str,
+}
+
+const CACHE: BaseDir = BaseDir {
+    systemd: "CACHE_DIRECTORY",
+    xdg: "XDG_CACHE_HOME",
+    home: ".cache",
+    system: "/var/cache",
+};
+
+const STATE: BaseDir = BaseDir {
+    systemd: "STATE_DIRECTORY",
+    xdg: "XDG_STATE_HOME",
+    home: ".local/state",
+    system: "/var/lib",
+};
+
+/// Directory of cached data, e.g. `~/.cache/pacman-mirrorup` or `/var/cache/pacman-mirrorup`
+pub fn cache_dir() -> PathBuf {
+    resolve(&CACHE, |name| env::var_os(name), is_root())
+}
+
+/// Directory of persistent state, e.g. `~/.local/state/pacman-mirrorup` or `/var/lib/pacman-mirrorup`
+pub fn state_dir() -> PathBuf {
+    resolve(&STATE, |name| env::var_os(name), is_root())
+}
+
+/// Default history database of `--history-db`
+pub fn history_db() -> PathBuf {
+    state_dir().join("history.db")
+}
+
+/// Resolve directory in order of systemd unit directory, XDG variable,
+/// then system-wide directory for root or XDG default for other users
+fn resolve<F>(base: &BaseDir, var: F, root: bool) -> PathBuf
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    // May be a colon-separated list, already specific to the unit
+    if let Some(dirs) = var(base.systemd) {
+        if let Some(dir) = env::split_paths(&dirs).find(|dir| dir.is_absolute()) {
+            return dir;
+        }
+    }
+
+    // Relative paths are invalid and must be ignored
+    let base_dir: PathBuf = match var(base.xdg).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => match var("HOME").map(PathBuf::from) {
+            Some(home) if !root && home.is_absolute() => home.join(base.home),
+            _ => PathBuf::from(base.system),
+        },
+    };
+    base_dir.join(APP_NAME)
+}
+
+/// Running as root, e.g. from system service
+fn is_root() -> bool {
+    fs::metadata("/proc/self").is_ok_and(|metadata| metadata.uid() == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| OsString::from(value))
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let home = [("HOME", "/home/user")];
+        assert_eq!(
+            resolve(&CACHE, env(&home), false),
+            PathBuf::from("/home/user/.cache/pacman-mirrorup")
+        );
+        assert_eq!(
+            resolve(&STATE, env(&home), false),
+            PathBuf::from("/home/user/.local/state/pacman-mirrorup")
+        );
+        assert_eq!(
+            resolve(&STATE, env(&home), true),
+            PathBuf::from("/var/lib/pacman-mirrorup")
+        );
+        assert_eq!(
+            resolve(&CACHE, env(&[]), false),
+            PathBuf::from("/var/cache/pacman-mirrorup")
+        );
+
+        let xdg = [("HOME", "/home/user"), ("XDG_CACHE_HOME", "/tmp/cache")];
+        assert_eq!(
+            resolve(&CACHE, env(&xdg), false),
+            PathBuf::from("/tmp/cache/pacman-mirrorup")
+        );
+        let relative = [("HOME", "/home/user"), ("XDG_CACHE_HOME", "cache")];
+        assert_eq!(
+            resolve(&CACHE, env(&relative), false),
+            PathBuf::from("/home/user/.cache/pacman-mirrorup")
+        );
+
+        let systemd = [
+            ("XDG_CACHE_HOME", "/tmp/cache"),
+            ("CACHE_DIRECTORY", "/var/cache/mirrorup:/var/cache/other"),
+        ];
+        assert_eq!(
+            resolve(&CACHE, env(&systemd), true),
+            PathBuf::from("/var/cache/mirrorup")
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    os::fd::{FromRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{http::IpFamily, netinfo};
+
+/// Number of echo requests sent to each mirror
+const PING_COUNT: u16 = 3;
+
+/// Timeout of each echo request
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Mean round-trip time (in seconds) of echo requests to the first address of mirror's host
+/// of `family`, if any, None if it cannot be pinged
+pub fn mirror_round_trip(url: &str, family: Option<IpFamily>) -> Option<f64> {
+    let ip: IpAddr = netinfo::resolve(url)
+        .into_iter()
+        .find(|ip| family.is_none_or(|family| family.matches(&SocketAddr::new(*ip, 0))))?;
+    match round_trip(ip) {
+        Ok(rtt) => rtt.map(|rtt| rtt.as_secs_f64()),
+        Err(err) => {
+            debug!("Could not ping `{ip}` of `{url}`: {err:#}");
+            None
+        }
+    }
+}
+
+/// Mean round-trip time of echo requests to `ip`, None if none is answered
+pub fn round_trip(ip: IpAddr) -> Result<Option<Duration>> {
+    let socket: UdpSocket = icmp_socket(ip).context("Failed to open ICMP socket")?;
+    let (request, reply): (u8, u8) = match ip {
+        IpAddr::V4(_) => (ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY),
+        IpAddr::V6(_) => (ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY),
+    };
+
+    let mut rtts: Vec<Duration> = Vec::new();
+    let mut buf = [0u8; 64];
+    for seq in 0..PING_COUNT {
+        let start = Instant::now();
+        socket.send(&echo_request(request, seq))?;
+        // Skip late replies of earlier requests
+        while start.elapsed() < PING_TIMEOUT {
+            match socket.recv(&mut buf) {
+                Ok(len) if is_echo_reply(&buf[..len], reply, seq) => {
+                    rtts.push(start.elapsed());
+                    break;
+                }
+                Ok(_) => {}
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    Ok((!rtts.is_empty()).then(|| rtts.iter().sum::<Duration>() / rtts.len() as u32))
+}
+
+/// Unprivileged ICMP socket connected to `ip`.
+///
+/// The kernel allows it only to groups in `net.ipv4.ping_group_range`, and fills in
+/// identifier and checksum of echo requests itself.
+fn icmp_socket(ip: IpAddr) -> io::Result<UdpSocket> {
+    let (domain, protocol) = match ip {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+    };
+    // SAFETY: socket() has no preconditions, the returned descriptor is checked below
+    let fd: libc::c_int =
+        unsafe { libc::socket(domain, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a newly opened descriptor owned by nothing else
+    let socket = UdpSocket::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    socket.connect(SocketAddr::new(ip, 0))?;
+    socket.set_read_timeout(Some(PING_TIMEOUT))?;
+    Ok(socket)
+}
+
+/// Echo request of sequence number `seq`, with identifier and checksum left to the kernel
+fn echo_request(kind: u8, seq: u16) -> [u8; 16] {
+    let mut packet = [0u8; 16];
+    packet[0] = kind;
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    packet[8..].copy_from_slice(b"mirrorup");
+    packet
+}
+
+fn is_echo_reply(packet: &[u8], kind: u8, seq: u16) -> bool {
+    packet.len() >= 8 && packet[0] == kind && packet[6..8] == seq.to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_request() {
+        let request: [u8; 16] = echo_request(ICMP_ECHO_REQUEST, 258);
+        assert_eq!(request[..8], [8, 0, 0, 0, 0, 0, 1, 2]);
+
+        let mut reply: [u8; 16] = request;
+        reply[0] = ICMP_ECHO_REPLY;
+        assert!(is_echo_reply(&reply, ICMP_ECHO_REPLY, 258));
+        assert!(!is_echo_reply(&reply, ICMP_ECHO_REPLY, 257));
+        assert!(!is_echo_reply(&request, ICMP_ECHO_REPLY, 258));
+        assert!(!is_echo_reply(&reply[..6], ICMP_ECHO_REPLY, 258));
+    }
+
+    #[test]
+    fn test_mirror_round_trip_unresolved() {
+        assert_eq!(mirror_round_trip("not a URL", None), None);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashSet, fmt, io::Read, sync::Mutex};
+
+use anyhow::{Context, Result};
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use tracing::{info, warn};
+
+use crate::{
+    clock::ReferenceClock,
+    mirror::{Benchmark, BenchmarkOptions, FilterOptions, Mirror, Mirrors},
+    source::{self, Source},
+};
+
+/// Used until `cutoff` of mirrors status is known
+const DEFAULT_CUTOFF: u64 = 86400;
+
+/// Fetch mirrors status and benchmark each synced mirror as soon as it is parsed,
+/// instead of waiting for the whole mirrors status to be downloaded.
+///
+/// Since mirrors cannot be sorted by delay before all of them are known,
+/// `max_check` limits number of mirrors in the order they appear in mirrors status.
+///
+/// Sources are tried in order until one can be opened, returns measured mirrors with the source used.
+pub fn fetch_and_benchmark<'a>(
+    agent: &ureq::Agent,
+    sources: &'a [String],
+    filter: &FilterOptions,
+    benchmark: &BenchmarkOptions,
+) -> Result<(Mirrors, &'a str)> {
+    let (reader, source) = source::open_first(agent, sources)?;
+    let fresh: bool = Source::parse(source)?.is_remote();
+
+    let measured: Mutex<Vec<Mirror>> = Mutex::new(Vec::new());
+    rayon::in_place_scope(|scope| {
+        stream_synced_mirrors(reader, fresh, filter, |mut mirror| {
+            let measured = &measured;
+            scope.spawn(move |_| {
+                if let Err(err) = mirror
+                    .measure_duration(benchmark)
+                    .context("Failed to measure transfer rate")
+                {
+                    info!("{err:#}");
+                }
+                measured.lock().expect("Lock measured mirrors").push(mirror);
+            });
+        })
+    })?;
+
+    let measured: Mirrors = measured
+        .into_inner()
+        .expect("Unlock measured mirrors")
+        .into_iter()
+        .collect();
+    Ok((measured, source))
+}
+
+/// Parse mirrors status from reader, calling `on_mirror` for each mirror passing filters.
+/// `fresh` tells whether mirrors status is just downloaded, see [`ReferenceClock::new`].
+pub fn stream_synced_mirrors<R, F>(
+    reader: R,
+    fresh: bool,
+    filter: &FilterOptions,
+    on_mirror: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(Mirror),
+{
+    let mut dispatcher = Dispatcher {
+        fresh,
+        filter,
+        on_mirror,
+        seen: HashSet::new(),
+        dispatched: 0,
+    };
+
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    StatusSeed {
+        dispatcher: &mut dispatcher,
+    }
+    .deserialize(&mut de)
+    .context("Failed to deserialize mirrors status")?;
+    de.end().context("Trailing data after mirrors status")?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct StatusHeader {
+    last_check: Option<String>,
+    cutoff: Option<u64>,
+}
+
+struct Dispatcher<'a, F> {
+    fresh: bool,
+    filter: &'a FilterOptions,
+    on_mirror: F,
+    seen: HashSet<String>,
+    dispatched: u32,
+}
+
+impl<F: FnMut(Mirror)> Dispatcher<'_, F> {
+    fn dispatch(&mut self, clock: &ReferenceClock, mut mirror: Mirror) {
+        if !self.filter.accepts(&mirror, clock) {
+            return;
+        }
+
+        if !self.seen.insert(mirror.url_key()) {
+            warn!("Duplicate mirror `{}` in mirrors status", mirror.url);
+            return;
+        }
+
+        if let Some(max_check) = self.filter.max_check {
+            if self.dispatched >= max_check {
+                return;
+            }
+        }
+
+        self.dispatched += 1;
+        mirror.update_age(clock);
+        (self.on_mirror)(mirror);
+    }
+}
+
+struct StatusSeed<'a, 'b, F> {
+    dispatcher: &'a mut Dispatcher<'b, F>,
+}
+
+impl<'de, F: FnMut(Mirror)> DeserializeSeed<'de> for StatusSeed<'_, '_, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(Mirror)> Visitor<'de> for StatusSeed<'_, '_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("mirrors status")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut header = StatusHeader::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "last_check" => header.last_check = Some(map.next_value()?),
+                "cutoff" => header.cutoff = Some(map.next_value()?),
+                "urls" => map.next_value_seed(UrlsSeed {
+                    header: &header,
+                    dispatcher: &mut *self.dispatcher,
+                })?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct UrlsSeed<'a, 'b, F> {
+    header: &'a StatusHeader,
+    dispatcher: &'a mut Dispatcher<'b, F>,
+}
+
+impl<'de, F: FnMut(Mirror)> DeserializeSeed<'de> for UrlsSeed<'_, '_, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(Mirror)> Visitor<'de> for UrlsSeed<'_, '_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("list of mirrors")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let clock = ReferenceClock::from_status(
+            self.header.last_check.as_deref(),
+            self.header.cutoff.unwrap_or(DEFAULT_CUTOFF),
+            self.dispatcher.filter.clock_skew_tolerance,
+            self.dispatcher.fresh,
+        );
+
+        while let Some(mirror) = seq.next_element::<Mirror>()? {
+            self.dispatcher.dispatch(&clock, mirror);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        exclude::{ExcludeKind, ExcludedMirrors},
+        mirror::{Filter, MirrorsStatus},
+    };
+
+    const MIRRORS_STATUS_RAW: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/mirrors_status.json"
+    ));
+
+    #[test]
+    fn test_stream_synced_mirrors() {
+        let mirrors_status: MirrorsStatus =
+            serde_json::from_str(MIRRORS_STATUS_RAW).expect("Deserialized mirror status");
+        let best: Mirrors = mirrors_status
+            .best_synced_mirrors(&FilterOptions::default())
+            .expect("Get best synced mirrors");
+
+        let mut streamed: Vec<Mirror> = Vec::new();
+        stream_synced_mirrors(
+            MIRRORS_STATUS_RAW.as_bytes(),
+            false,
+            &FilterOptions::default(),
+            |m| streamed.push(m),
+        )
+        .unwrap();
+
+        assert_eq!(streamed.len(), best.len());
+        for m in streamed.iter() {
+            assert!(best.iter().any(|b| b.url == m.url));
+        }
+    }
+
+    #[test]
+    fn test_stream_synced_mirrors_with_limit_and_exclude() {
+        let mut excluded_mirrors = ExcludedMirrors::new();
+        excluded_mirrors.add(ExcludeKind::Domain("mirror.aarnet.edu.au".to_string()));
+        let filter = FilterOptions {
+            max_check: Some(10),
+            excluded_mirrors: Some(excluded_mirrors),
+            ..Default::default()
+        };
+
+        let mut streamed: Vec<Mirror> = Vec::new();
+        stream_synced_mirrors(MIRRORS_STATUS_RAW.as_bytes(), false, &filter, |m| {
+            streamed.push(m)
+        })
+        .unwrap();
+
+        assert_eq!(streamed.len(), 10);
+        assert!(streamed
+            .iter()
+            .all(|m| !m.url.contains("mirror.aarnet.edu.au")));
+    }
+
+    #[test]
+    fn test_stream_invalid_status() {
+        assert!(stream_synced_mirrors(
+            r#"{"urls": [{"url": 1}]}"#.as_bytes(),
+            false,
+            &FilterOptions::default(),
+            |_| {}
+        )
+        .is_err());
+    }
+}
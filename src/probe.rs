@@ -0,0 +1,58 @@
+use std::{
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use url::Url;
+
+use crate::http::IpFamily;
+
+/// Timeout of connecting to a mirror
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Cheap measurement ranking mirrors before download benchmark
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Probe {
+    /// TCP connection establishment time, without any HTTP request
+    Tcp,
+}
+
+/// Time to establish TCP connection to host of `url` over `family`, if any
+pub fn connect_time(url: &str, family: Option<IpFamily>) -> Result<Duration> {
+    let url: Url = Url::parse(url)?;
+    let host: &str = url
+        .host_str()
+        .context("No host in URL")?
+        .trim_matches(['[', ']']);
+    let port: u16 = url.port_or_known_default().context("No port of URL")?;
+    let addr: SocketAddr = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve `{host}`"))?
+        .find(|addr| family.is_none_or(|family| family.matches(addr)))
+        .with_context(|| format!("No address of `{host}`"))?;
+
+    let start = Instant::now();
+    TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .with_context(|| format!("Failed to connect `{addr}`"))?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_connect_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!("http://{}/archlinux/", listener.local_addr().unwrap());
+        assert!(connect_time(&url, None).unwrap() < CONNECT_TIMEOUT);
+        assert!(connect_time(&url, Some(IpFamily::V6)).is_err());
+
+        drop(listener);
+        assert!(connect_time(&url, None).is_err());
+        assert!(connect_time("not a URL", None).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use std::{fmt::Write, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    color::{paint, Color},
+    mirror::Mirrors,
+    output::{self, Overwrite},
+    samples::{self, Aggregate},
+};
+
+/// Format of human-readable report
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    /// Infer format from file extension, Markdown if unknown
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("html") | Some("htm") => ReportFormat::Html,
+            _ => ReportFormat::Markdown,
+        }
+    }
+}
+
+const COLUMNS: [&str; 5] = ["#", "Mirror", "Country", "Transfer Rate", "Score"];
+
+/// Numeric columns are right-aligned
+const RIGHT_ALIGNED: [bool; 5] = [true, false, false, true, true];
+
+/// Cells of a ranked mirror, in order of `COLUMNS`
+fn rows(mirrors: &Mirrors) -> Vec<[String; 5]> {
+    mirrors
+        .iter()
+        .enumerate()
+        .map(|(i, mirror)| {
+            [
+                (i + 1).to_string(),
+                mirror.url.clone(),
+                mirror.country.clone(),
+                mirror
+                    .transfer_rate
+                    .map(human_rate)
+                    .unwrap_or_else(|| "-".to_string()),
+                mirror
+                    .score
+                    .map(|score| format!("{score:.2}"))
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect()
+}
+
+/// Transfer rate in bytes per second, in binary units
+pub fn human_rate(rate: f64) -> String {
+    format!("{}/s", human_bytes(rate))
+}
+
+/// Size in bytes, in binary units
+pub fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut bytes: f64 = bytes;
+    let mut unit: usize = 0;
+    while bytes >= 1024.0 && unit < UNITS.len() - 1 {
+        bytes /= 1024.0;
+        unit += 1;
+    }
+    format!("{bytes:.1} {}", UNITS[unit])
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Mirrors slower than this fraction of the median transfer rate are shown as slow
+const SLOW_RATIO: f64 = 0.5;
+
+/// Terminal color of a ranked mirror: red if failed, yellow if slow, green otherwise
+fn row_color(transfer_rate: Option<f64>, median_rate: Option<f64>) -> Color {
+    match (transfer_rate, median_rate) {
+        (None, _) => Color::Red,
+        (Some(rate), Some(median)) if rate < SLOW_RATIO * median => Color::Yellow,
+        _ => Color::Green,
+    }
+}
+
+pub trait ToReport {
+    /// Convert to ranked table of mirrors
+    fn to_report(&self, format: ReportFormat) -> String {
+        self.to_colored_report(format, false)
+    }
+
+    /// Convert to ranked table of mirrors, Markdown rows are colored by [`row_color`] if `color`
+    fn to_colored_report(&self, format: ReportFormat, color: bool) -> String;
+
+    /// Write report to file
+    fn to_report_file(&self, path: &Path, format: ReportFormat, overwrite: Overwrite)
+        -> Result<()>;
+}
+
+impl ToReport for Mirrors {
+    fn to_colored_report(&self, format: ReportFormat, color: bool) -> String {
+        let mut report = String::new();
+        match format {
+            ReportFormat::Markdown => {
+                let rows: Vec<[String; 5]> = rows(self)
+                    .into_iter()
+                    .map(|row| row.map(|c| escape_markdown(&c)))
+                    .collect();
+
+                // Pad cells so that columns line up in terminal
+                let mut widths: [usize; 5] = COLUMNS.map(str::len);
+                for row in &rows {
+                    for (width, cell) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(cell.chars().count());
+                    }
+                }
+                let align = |cells: [&str; 5]| -> String {
+                    cells
+                        .iter()
+                        .enumerate()
+                        .map(|(i, cell)| {
+                            if RIGHT_ALIGNED[i] {
+                                format!("{cell:>width$}", width = widths[i])
+                            } else {
+                                format!("{cell:<width$}", width = widths[i])
+                            }
+                        })
+                        .collect::<Vec<String>>()
+                        .join(" | ")
+                };
+
+                let _ = writeln!(report, "| {} |", align(COLUMNS));
+                let separator: Vec<String> = widths
+                    .iter()
+                    .zip(RIGHT_ALIGNED)
+                    .map(|(width, right)| {
+                        if right {
+                            format!("{}:", "-".repeat(width + 1))
+                        } else {
+                            "-".repeat(width + 2)
+                        }
+                    })
+                    .collect();
+                let _ = writeln!(report, "|{}|", separator.join("|"));
+
+                let rates: Vec<f64> = self.iter().filter_map(|m| m.transfer_rate).collect();
+                let median_rate: Option<f64> = samples::aggregate(&rates, Aggregate::Median);
+                for (mirror, row) in self.iter().zip(&rows) {
+                    let line: String = align(row.each_ref().map(String::as_str));
+                    let line: String =
+                        paint(&line, row_color(mirror.transfer_rate, median_rate), color);
+                    let _ = writeln!(report, "| {line} |");
+                }
+            }
+            ReportFormat::Html => {
+                let _ = writeln!(report, "<table>");
+                let _ = writeln!(report, "  <thead>");
+                let _ = writeln!(
+                    report,
+                    "    <tr>{}</tr>",
+                    COLUMNS
+                        .iter()
+                        .map(|c| format!("<th>{}</th>", escape_html(c)))
+                        .collect::<String>()
+                );
+                let _ = writeln!(report, "  </thead>");
+                let _ = writeln!(report, "  <tbody>");
+                for [rank, url, country, rate, score] in rows(self) {
+                    let url: String = escape_html(&url);
+                    let _ = writeln!(
+                        report,
+                        "    <tr><td>{rank}</td><td><a href=\"{url}\">{url}</a></td><td>{}</td><td>{rate}</td><td>{score}</td></tr>",
+                        escape_html(&country)
+                    );
+                }
+                let _ = writeln!(report, "  </tbody>");
+                let _ = writeln!(report, "</table>");
+            }
+        }
+        report
+    }
+
+    fn to_report_file(
+        &self,
+        path: &Path,
+        format: ReportFormat,
+        overwrite: Overwrite,
+    ) -> Result<()> {
+        output::save(path, self.to_report(format).as_bytes(), overwrite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror::Mirror;
+
+    fn mirrors() -> Mirrors {
+        let mut a = Mirror::default();
+        a.url = "https://a.example/archlinux/".to_string();
+        a.country = "Thailand".to_string();
+        a.transfer_rate = Some(3.5 * 1024.0 * 1024.0);
+        a.score = Some(0.5);
+
+        let mut b = Mirror::default();
+        b.url = "https://b.example/arch|linux/".to_string();
+        b.country = "Bosnia & Herzegovina".to_string();
+
+        [a, b].into_iter().collect()
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let report = mirrors().to_report(ReportFormat::Markdown);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "| # | Mirror                         | Country              | Transfer Rate | Score |"
+        );
+        assert_eq!(
+            lines[1],
+            "|--:|--------------------------------|----------------------|--------------:|------:|"
+        );
+        assert_eq!(
+            lines[2],
+            "| 1 | https://a.example/archlinux/   | Thailand             |     3.5 MiB/s |  0.50 |"
+        );
+        assert_eq!(
+            lines[3],
+            "| 2 | https://b.example/arch\\|linux/ | Bosnia & Herzegovina |             - |     - |"
+        );
+
+        // Failed mirror in red
+        let report = mirrors().to_colored_report(ReportFormat::Markdown, true);
+        assert!(report.contains("| \x1b[32m1 | "));
+        assert!(report.contains("| \x1b[31m2 | "));
+        assert_eq!(row_color(Some(1.0), Some(3.0)), Color::Yellow);
+        assert_eq!(row_color(Some(2.0), Some(3.0)), Color::Green);
+    }
+
+    #[test]
+    fn test_html_report() {
+        let report = mirrors().to_report(ReportFormat::Html);
+        assert!(report.starts_with("<table>\n"));
+        assert!(report.ends_with("</table>\n"));
+        assert!(report.contains(
+            "<tr><td>1</td><td><a href=\"https://a.example/archlinux/\">https://a.example/archlinux/</a></td><td>Thailand</td><td>3.5 MiB/s</td><td>0.50</td></tr>"
+        ));
+        assert!(report.contains("<td>Bosnia &amp; Herzegovina</td>"));
+    }
+
+    #[test]
+    fn test_report_format_from_path() {
+        assert_eq!(
+            ReportFormat::from_path(Path::new("/srv/www/mirrors.HTML")),
+            ReportFormat::Html
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("mirrors.md")),
+            ReportFormat::Markdown
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("-")),
+            ReportFormat::Markdown
+        );
+        assert_eq!(human_rate(512.0), "512.0 B/s");
+        assert_eq!(human_rate(1536.0), "1.5 KiB/s");
+    }
+}
@@ -0,0 +1,106 @@
+/// How repeated measurements of a mirror are combined
+#[derive(clap::ValueEnum, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Aggregate {
+    #[default]
+    Mean,
+    Median,
+    Min,
+}
+
+/// Combine samples into a single value
+pub fn aggregate(samples: &[f64], method: Aggregate) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    match method {
+        Aggregate::Mean => Some(samples.iter().sum::<f64>() / samples.len() as f64),
+        Aggregate::Median => median(samples),
+        Aggregate::Min => samples.iter().copied().reduce(f64::min),
+    }
+}
+
+pub fn median(samples: &[f64]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let mut sorted: Vec<f64> = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        Some((sorted[mid - 1] + sorted[mid]) / 2.0)
+    } else {
+        Some(sorted[mid])
+    }
+}
+
+/// Samples within `max_mads` median absolute deviations from the median.
+///
+/// With less than 3 samples, or if most samples are equal (no deviation), all samples are kept.
+pub fn reject_outliers(samples: &[f64], max_mads: f64) -> Vec<f64> {
+    let Some(median) = median(samples).filter(|_| samples.len() >= 3) else {
+        return samples.to_vec();
+    };
+    let deviations: Vec<f64> = samples.iter().map(|s| (s - median).abs()).collect();
+    let mad: f64 = self::median(&deviations).unwrap_or_default();
+    if mad == 0.0 {
+        return samples.to_vec();
+    }
+    samples
+        .iter()
+        .zip(&deviations)
+        .filter(|(_, deviation)| **deviation <= max_mads * mad)
+        .map(|(sample, _)| *sample)
+        .collect()
+}
+
+/// Sample standard deviation, None if there are less than 2 samples
+pub fn stddev(samples: &[f64]) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+
+    let n = samples.len() as f64;
+    let mean: f64 = samples.iter().sum::<f64>() / n;
+    let variance: f64 = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    Some(variance.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let samples = [4.0, 1.0, 3.0, 8.0];
+        assert_eq!(aggregate(&samples, Aggregate::Mean), Some(4.0));
+        assert_eq!(aggregate(&samples, Aggregate::Median), Some(3.5));
+        assert_eq!(aggregate(&samples, Aggregate::Min), Some(1.0));
+        assert_eq!(aggregate(&[5.0, 1.0, 3.0], Aggregate::Median), Some(3.0));
+        assert_eq!(aggregate(&[], Aggregate::Mean), None);
+    }
+
+    #[test]
+    fn test_reject_outliers() {
+        // Median 10, MAD 1
+        let samples = [10.0, 9.0, 11.0, 10.5, 1.0];
+        assert_eq!(reject_outliers(&samples, 3.0), vec![10.0, 9.0, 11.0, 10.5]);
+        assert_eq!(reject_outliers(&samples, 10.0), samples.to_vec());
+
+        assert_eq!(reject_outliers(&[10.0, 1.0], 1.0), vec![10.0, 1.0]);
+        assert_eq!(
+            reject_outliers(&[5.0, 5.0, 5.0, 1.0], 3.0),
+            vec![5.0, 5.0, 5.0, 1.0]
+        );
+        assert!(reject_outliers(&[], 3.0).is_empty());
+    }
+
+    #[test]
+    fn test_stddev() {
+        assert_eq!(stddev(&[1.0]), None);
+        assert_eq!(stddev(&[2.0, 2.0, 2.0]), Some(0.0));
+        let sd = stddev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+        assert!((sd - 2.138089935299395).abs() < 1e-12, "stddev = {sd}");
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+use url::Url;
+
+use crate::mirror::Mirror;
+
+/// Order in which mirrors are benchmarked.
+///
+/// Mirrors are interleaved by country, and by provider within each country,
+/// so that concurrently running measurements hit as many different
+/// countries/providers as possible instead of bursts of the same region.
+/// Returns indices into `mirrors`.
+pub fn interleave(mirrors: &[Mirror]) -> Vec<usize> {
+    let by_country: Vec<Vec<usize>> = group_by(0..mirrors.len(), |&i| {
+        mirrors[i].country_code.to_lowercase()
+    });
+
+    let by_country: Vec<Vec<usize>> = by_country
+        .into_iter()
+        .map(|group| round_robin(group_by(group, |&i| provider(&mirrors[i]))))
+        .collect();
+
+    round_robin(by_country)
+}
+
+/// Pick `n` mirrors in order of random `key`, returns indices into `mirrors`.
+///
+/// With `by_country`, mirrors are taken from each country in turn,
+/// so that countries with many mirrors do not crowd out the others.
+pub fn sample<K, F>(mirrors: &[Mirror], n: usize, by_country: bool, key: F) -> Vec<usize>
+where
+    K: Ord,
+    F: Fn(&Mirror) -> K,
+{
+    let mut shuffled: Vec<usize> = (0..mirrors.len()).collect();
+    shuffled.sort_by_key(|&i| key(&mirrors[i]));
+
+    let mut picked: Vec<usize> = if by_country {
+        round_robin(group_by(shuffled, |&i| {
+            mirrors[i].country_code.to_lowercase()
+        }))
+    } else {
+        shuffled
+    };
+    picked.truncate(n);
+    picked
+}
+
+/// Random seed from the randomly seeded hasher of standard library
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Group items by key, keeping the order of first occurrence
+fn group_by<I, K, F>(items: I, key: F) -> Vec<Vec<usize>>
+where
+    I: IntoIterator<Item = usize>,
+    K: Eq + std::hash::Hash,
+    F: Fn(&usize) -> K,
+{
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut index: HashMap<K, usize> = HashMap::new();
+    for item in items {
+        let slot = *index.entry(key(&item)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[slot].push(item);
+    }
+    groups
+}
+
+/// Take one item from each group in turn until all groups are exhausted
+fn round_robin(groups: Vec<Vec<usize>>) -> Vec<usize> {
+    let total: usize = groups.iter().map(Vec::len).sum();
+    let mut iters: Vec<std::vec::IntoIter<usize>> =
+        groups.into_iter().map(Vec::into_iter).collect();
+    let mut order: Vec<usize> = Vec::with_capacity(total);
+    while order.len() < total {
+        for it in iters.iter_mut() {
+            if let Some(i) = it.next() {
+                order.push(i);
+            }
+        }
+    }
+    order
+}
+
+/// Approximate provider of a mirror from its host name,
+/// e.g. `mirror.example.com` and `archlinux.example.com` belong to `example.com`.
+pub fn provider(mirror: &Mirror) -> String {
+    let host: String = match Url::parse(&mirror.url) {
+        Ok(url) => url.host_str().unwrap_or_default().to_lowercase(),
+        Err(_) => return mirror.url.to_lowercase(),
+    };
+
+    let labels: Vec<&str> = host.rsplit('.').collect();
+    let take: usize = match labels.as_slice() {
+        // Second-level domain under country code, e.g. example.co.uk, example.com.hk
+        [tld, sld, ..] if tld.len() == 2 && sld.len() <= 3 => 3,
+        _ => 2,
+    };
+    let mut provider: Vec<&str> = labels.into_iter().take(take).collect();
+    provider.reverse();
+    provider.join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn mirror(url: &str, country_code: &str) -> Mirror {
+        let mut mirror = Mirror::default();
+        mirror.url = url.to_string();
+        mirror.country_code = country_code.to_string();
+        mirror
+    }
+
+    #[test]
+    fn test_provider() {
+        assert_eq!(
+            provider(&mirror("https://mirror.example.com/arch/", "")),
+            "example.com"
+        );
+        assert_eq!(
+            provider(&mirror("https://mirror.xtom.com.hk/arch/", "")),
+            "xtom.com.hk"
+        );
+        assert_eq!(
+            provider(&mirror("https://example.de/arch/", "")),
+            "example.de"
+        );
+    }
+
+    #[test]
+    fn test_interleave() {
+        let mirrors = vec![
+            mirror("https://a1.provider-a.de/", "DE"),
+            mirror("https://a2.provider-a.de/", "DE"),
+            mirror("https://b1.provider-b.de/", "DE"),
+            mirror("https://c1.provider-c.fr/", "FR"),
+            mirror("https://c2.provider-c.fr/", "FR"),
+            mirror("https://d1.provider-d.us/", "US"),
+        ];
+
+        assert_eq!(interleave(&mirrors), vec![0, 3, 5, 2, 4, 1]);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mirrors = vec![
+            mirror("https://a.de/", "DE"),
+            mirror("https://b.de/", "DE"),
+            mirror("https://c.de/", "DE"),
+            mirror("https://d.th/", "TH"),
+            mirror("https://e.jp/", "JP"),
+        ];
+        let reversed = |m: &Mirror| std::cmp::Reverse(m.url.clone());
+
+        assert_eq!(sample(&mirrors, 3, false, reversed), vec![4, 3, 2]);
+        assert_eq!(sample(&mirrors, 3, true, reversed), vec![4, 3, 2]);
+        assert_eq!(
+            sample(&mirrors, 3, true, |m: &Mirror| m.url.clone()),
+            vec![0, 3, 4]
+        );
+        assert_eq!(sample(&mirrors, 10, true, reversed).len(), 5);
+    }
+
+    #[test]
+    fn test_interleave_keeps_all_mirrors() {
+        let mirrors_status_raw = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrors_status.json"
+        ));
+        let mirrors: serde_json::Value =
+            serde_json::from_str(mirrors_status_raw).expect("Deserialized mirror status");
+        let mirrors: Vec<Mirror> =
+            serde_json::from_value(mirrors["urls"].clone()).expect("Deserialized mirrors");
+
+        let mut order = interleave(&mirrors);
+        assert_eq!(order.len(), mirrors.len());
+        order.sort();
+        assert_eq!(order, (0..mirrors.len()).collect::<Vec<usize>>());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt::Write, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use tracing::warn;
+
+use crate::{
+    args::{Arguments, ShowArgs},
+    clock::ReferenceClock,
+    exclude::{ExcludeKind, ExcludedMirrors},
+    exit::Failure,
+    http,
+    mirror::{Benchmark, BenchmarkOptions, Mirror, Mirrors, MirrorsStatus},
+};
+
+/// Run `show` subcommand
+pub fn run(arguments: &Arguments, args: &ShowArgs) -> Result<()> {
+    let agent: ureq::Agent = http::agent(arguments.max_redirects);
+    let (status, source) =
+        MirrorsStatus::from_sources(&agent, &arguments.source_url).context(Failure::StatusFetch)?;
+
+    // Same matching as a domain of exclude file
+    let mut domain = ExcludedMirrors::new();
+    domain.add(ExcludeKind::try_from(args.domain.as_str())?);
+    let clock: ReferenceClock =
+        status.reference_clock(Duration::from_secs(arguments.clock_skew_tolerance));
+    let mut mirrors: Mirrors = status
+        .mirrors()
+        .iter()
+        .filter(|mirror| domain.is_include(mirror))
+        .cloned()
+        .map(|mut mirror| {
+            mirror.update_age(&clock);
+            mirror
+        })
+        .collect();
+    if mirrors.is_empty() {
+        bail!("No mirror of `{}` in `{source}`", args.domain);
+    }
+
+    if !args.no_benchmark {
+        let mut options = BenchmarkOptions::new(arguments.target_db(), agent);
+        options.samples = arguments.samples;
+        options.transfer_limit = arguments.transfer_limit;
+        options.layout = arguments.layout();
+        options.other_dbs = arguments.target_dbs[1..].to_vec();
+        options.db_aggregate = arguments.target_db_aggregate;
+        for mirror in mirrors.iter_mut() {
+            if let Err(err) = mirror
+                .measure_duration(&options)
+                .context("Failed to measure transfer rate")
+            {
+                warn!("{err:#}");
+            }
+        }
+    }
+
+    let details: Vec<String> = mirrors.iter().map(format_details).collect();
+    print!("{}", details.join("\n"));
+    Ok(())
+}
+
+/// Aligned `label: value` lines of a mirror
+fn format_details(mirror: &Mirror) -> String {
+    let details: Vec<(&str, String)> = mirror.details();
+    let width: usize = details
+        .iter()
+        .map(|(label, _)| label.len())
+        .max()
+        .unwrap_or(0);
+    let mut text = String::new();
+    for (label, value) in details {
+        let _ = writeln!(
+            text,
+            "{:<width$} {value}",
+            format!("{label}:"),
+            width = width + 1
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_details() {
+        let mut mirror = Mirror::from_url("https://mirror.example/archlinux");
+        mirror.country = "Thailand".to_string();
+        mirror.country_code = "TH".to_string();
+        mirror.transfer_rate = Some(2048.0);
+
+        assert_eq!(
+            format_details(&mirror),
+            "\
+            URL:           https://mirror.example/archlinux/\n\
+            Protocol:      https\n\
+            Country:       Thailand (TH)\n\
+            Active:        yes\n\
+            Completion:    0.0%\n\
+            ISOs:          no\n\
+            IPv4:          no\n\
+            IPv6:          no\n\
+            Transfer rate: 2.0 KiB/s\n\
+            "
+        );
+    }
+}
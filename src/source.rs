@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context, Result};
+use tracing::warn;
+use url::Url;
+
+use crate::http;
+
+/// Where mirrors status is read from
+#[derive(Debug, PartialEq, Eq)]
+pub enum Source {
+    /// `http://` or `https://` URL
+    Http(String),
+
+    /// Local path or `file://` URL
+    File(PathBuf),
+
+    /// `-`, e.g. piped from another downloader
+    Stdin,
+}
+
+impl Source {
+    pub fn parse(source: &str) -> Result<Self> {
+        if source == "-" {
+            return Ok(Source::Stdin);
+        }
+
+        match Url::parse(source) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                Ok(Source::Http(source.to_string()))
+            }
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .map(Source::File)
+                .map_err(|_| anyhow!("Invalid file URL `{source}`")),
+            // Relative paths are not URLs
+            Err(url::ParseError::RelativeUrlWithoutBase) => Ok(Source::File(source.into())),
+            Ok(url) => Err(anyhow!(
+                "Unsupported scheme `{}` of `{source}`",
+                url.scheme()
+            )),
+            Err(err) => Err(err).with_context(|| format!("Invalid source `{source}`")),
+        }
+    }
+
+    /// Whether mirrors status is downloaded, rather than read locally
+    pub fn is_remote(&self) -> bool {
+        matches!(self, Source::Http(_))
+    }
+
+    /// Open body of mirrors status
+    pub fn open(&self, agent: &ureq::Agent) -> Result<Box<dyn Read + Send>> {
+        match self {
+            Source::Http(url) => {
+                let response = agent
+                    .get(url)
+                    .call()
+                    .with_context(|| format!("Failed to fetch `{url}`"))?;
+                Ok(Box::new(BufReader::new(http::Counted(
+                    response.into_reader(),
+                ))))
+            }
+            Source::File(path) => {
+                let file = File::open(path)
+                    .with_context(|| format!("Could not open `{}`", path.display()))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+            Source::Stdin => Ok(Box::new(BufReader::new(io::stdin()))),
+        }
+    }
+}
+
+/// Open the first source that can be opened, trying each in order
+pub fn open_first<'a>(
+    agent: &ureq::Agent,
+    sources: &'a [String],
+) -> Result<(Box<dyn Read + Send>, &'a str)> {
+    first_ok(sources, |source| Source::parse(source)?.open(agent))
+}
+
+/// Result of the first source for which `f` succeeds, together with the source.
+/// Failures are logged, the last one is returned if every source fails.
+pub fn first_ok<T, F>(sources: &[String], mut f: F) -> Result<(T, &str)>
+where
+    F: FnMut(&str) -> Result<T>,
+{
+    let mut last_err: Option<anyhow::Error> = None;
+    for source in sources {
+        match f(source) {
+            Ok(value) => return Ok((value, source)),
+            Err(err) => {
+                if sources.len() > 1 {
+                    warn!("{err:#}, try next source");
+                }
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("No mirrors status source")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(
+            Source::parse("https://archlinux.org/mirrors/status/json/").unwrap(),
+            Source::Http("https://archlinux.org/mirrors/status/json/".to_string())
+        );
+        assert_eq!(
+            Source::parse("file:///var/cache/mirrors_status.json").unwrap(),
+            Source::File(PathBuf::from("/var/cache/mirrors_status.json"))
+        );
+        assert_eq!(
+            Source::parse("tests/mirrors_status.json").unwrap(),
+            Source::File(PathBuf::from("tests/mirrors_status.json"))
+        );
+        assert_eq!(Source::parse("-").unwrap(), Source::Stdin);
+        assert!(Source::parse("ftp://archlinux.org/status.json").is_err());
+    }
+
+    #[test]
+    fn test_first_ok() {
+        let sources: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut tried: Vec<String> = Vec::new();
+        let (value, source) = first_ok(&sources, |s| {
+            tried.push(s.to_string());
+            match s {
+                "b" => Ok(2),
+                _ => Err(anyhow!("`{s}` is down")),
+            }
+        })
+        .unwrap();
+        assert_eq!((value, source), (2, "b"));
+        assert_eq!(tried, vec!["a", "b"]);
+
+        let err = first_ok(&sources, |s| -> Result<()> {
+            Err(anyhow!("`{s}` is down"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "`c` is down");
+    }
+}
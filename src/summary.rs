@@ -0,0 +1,106 @@
+use std::{fmt::Write, time::Duration};
+
+use crate::{
+    color::{paint, Color},
+    report,
+};
+
+/// Counts of a run, printed to STDERR after evaluation.
+///
+/// Counts which do not apply to the run, e.g. mirrors status counts when ranking `--input-mirrorlist`, are None.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Summary {
+    /// Mirrors in mirrors status
+    pub fetched: Option<usize>,
+
+    /// Mirrors passing primary filter (synced)
+    pub synced: Option<usize>,
+
+    /// Synced mirrors dropped by exclude list
+    pub excluded: Option<usize>,
+
+    pub benchmarked: Option<usize>,
+
+    /// Benchmarked mirrors without transfer rate
+    pub failed: Option<usize>,
+
+    /// Mirrors written to mirror list
+    pub selected: usize,
+
+    pub elapsed: Duration,
+
+    /// Bytes of mirrors status and database files
+    pub downloaded: u64,
+}
+
+impl Summary {
+    /// Aligned table, with selected count in green and failed count in red if `color`
+    pub fn to_table(&self, color: bool) -> String {
+        let count = |n: Option<usize>| n.map_or_else(|| "-".to_string(), |n| n.to_string());
+        let rows: [(&str, String); 8] = [
+            ("Fetched", count(self.fetched)),
+            ("Synced", count(self.synced)),
+            ("Excluded", count(self.excluded)),
+            ("Benchmarked", count(self.benchmarked)),
+            ("Failed", count(self.failed)),
+            ("Selected", self.selected.to_string()),
+            ("Wall time", format!("{:.1} s", self.elapsed.as_secs_f64())),
+            ("Downloaded", report::human_bytes(self.downloaded as f64)),
+        ];
+
+        let width: usize = rows.iter().map(|(_, value)| value.len()).max().unwrap_or(0);
+        let mut table = String::new();
+        for (label, value) in rows {
+            let value: String = format!("{value:>width$}");
+            let value: String = match label {
+                "Selected" => paint(&value, Color::Green, color),
+                "Failed" if self.failed.is_some_and(|n| n > 0) => paint(&value, Color::Red, color),
+                _ => value,
+            };
+            let _ = writeln!(table, "{label:<12} {value}");
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_table() {
+        let summary = Summary {
+            fetched: Some(1024),
+            synced: Some(600),
+            excluded: Some(2),
+            benchmarked: Some(100),
+            failed: Some(7),
+            selected: 10,
+            elapsed: Duration::from_millis(42_250),
+            downloaded: 3 * 1024 * 1024,
+        };
+        assert_eq!(
+            summary.to_table(false),
+            "\
+            Fetched         1024\n\
+            Synced           600\n\
+            Excluded           2\n\
+            Benchmarked      100\n\
+            Failed             7\n\
+            Selected          10\n\
+            Wall time     42.2 s\n\
+            Downloaded   3.0 MiB\n\
+            "
+        );
+
+        assert!(summary
+            .to_table(true)
+            .contains("Failed       \x1b[31m      7\x1b[0m\n"));
+
+        let summary = Summary {
+            selected: 3,
+            ..Default::default()
+        };
+        assert!(summary.to_table(false).starts_with("Fetched          -\n"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{mirror::Mirror, report};
+
+/// Mirror list template.
+///
+/// The first line containing `{url}` is written once per mirror, with `{url}`, `{country}`,
+/// `{country_code}`, `{score}`, `{transfer_rate}` and `{weighted_score}` replaced.
+/// Lines before it are the header and lines after it are the footer,
+/// with `{source}` and `{generated_at}` replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    header: String,
+    server: String,
+    footer: String,
+}
+
+impl Template {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        fs::read_to_string(path)
+            .with_context(|| format!("Could not read template `{}`", path.display()))?
+            .parse()
+            .with_context(|| format!("Invalid template `{}`", path.display()))
+    }
+
+    /// Header and footer with run information
+    pub fn render_frame(&self, source_url: &str, generated_at: &str) -> (String, String) {
+        let render = |s: &str| {
+            s.replace("{source}", source_url)
+                .replace("{generated_at}", generated_at)
+        };
+        (render(&self.header), render(&self.footer))
+    }
+
+    /// Server line of a mirror
+    pub fn render_server(&self, mirror: &Mirror) -> String {
+        render_line(&self.server, mirror)
+    }
+}
+
+/// `line` with placeholders of server line replaced by values of `mirror`
+pub fn render_line(line: &str, mirror: &Mirror) -> String {
+    let optional = |value: Option<f64>| value.map(|v| format!("{v:.2}")).unwrap_or_default();
+    line.replace("{url}", &mirror.url)
+        .replace("{country}", &mirror.country)
+        .replace("{country_code}", &mirror.country_code)
+        .replace("{score}", &optional(mirror.score))
+        .replace(
+            "{transfer_rate}",
+            &mirror
+                .transfer_rate
+                .map(report::human_rate)
+                .unwrap_or_default(),
+        )
+        .replace("{weighted_score}", &optional(mirror.weighted_score()))
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(start) = s
+            .split_inclusive('\n')
+            .position(|line| line.contains("{url}"))
+        else {
+            bail!("No line with `{{url}}` placeholder");
+        };
+
+        let lines: Vec<&str> = s.split_inclusive('\n').collect();
+        Ok(Self {
+            header: lines[..start].concat(),
+            server: lines[start].trim_end_matches(['\r', '\n']).to_string(),
+            footer: lines[start + 1..].concat(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let template: Template = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirrorlist.template"
+        ))
+        .parse()
+        .unwrap();
+
+        let (header, footer) =
+            template.render_frame("https://archlinux.org/mirrors/status/json/", "now");
+        assert_eq!(
+            header,
+            "# Generated at now from https://archlinux.org/mirrors/status/json/\n"
+        );
+        assert_eq!(footer, "# End of mirror list\n");
+
+        let mut mirror = Mirror::default();
+        mirror.url = "https://mirror.example/archlinux/".to_string();
+        mirror.country_code = "TH".to_string();
+        mirror.score = Some(1.234);
+        assert_eq!(
+            template.render_server(&mirror),
+            "Server = https://mirror.example/archlinux/$repo/$arch # TH 1.23"
+        );
+
+        assert!("Server = $repo/os/$arch\n".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rustls::{pki_types::ServerName, ClientConnection, ProtocolVersion};
+use url::Url;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::http::{self, IpFamily};
+
+/// Timeout of connecting and handshaking
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS version supported by rustls
+#[derive(clap::ValueEnum, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "TLSv1.2",
+            TlsVersion::Tls13 => "TLSv1.3",
+        }
+    }
+
+    fn from_protocol(version: ProtocolVersion) -> Option<Self> {
+        match version {
+            ProtocolVersion::TLSv1_2 => Some(TlsVersion::Tls12),
+            ProtocolVersion::TLSv1_3 => Some(TlsVersion::Tls13),
+            _ => None,
+        }
+    }
+}
+
+/// What HTTPS endpoint of a mirror negotiates
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    /// Highest TLS version both sides support
+    pub version: TlsVersion,
+
+    /// Application protocol chosen by server (`h2` or `http/1.1`), None without ALPN
+    pub alpn: Option<String>,
+
+    pub handshake: Duration,
+
+    /// Expiry of mirror's own certificate
+    pub cert_expiry: Option<DateTime<Utc>>,
+}
+
+/// Handshake with host of `url` over `family`, if any, offering HTTP/2 and HTTP/1.1,
+/// None if `url` is not HTTPS.
+///
+/// Benchmark itself is always HTTP/1.1, this only finds what the mirror supports.
+pub fn probe(url: &Url, family: Option<IpFamily>) -> Result<Option<TlsInfo>> {
+    if url.scheme() != "https" {
+        return Ok(None);
+    }
+    let host: &str = url.host_str().context("No host in URL")?;
+    let port: u16 = url.port_or_known_default().unwrap_or(443);
+    let addr: SocketAddr = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve `{host}`"))?
+        .find(|addr| family.is_none_or(|family| family.matches(addr)))
+        .with_context(|| format!("No address of `{host}`"))?;
+
+    let mut socket = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)
+        .with_context(|| format!("Failed to connect `{addr}`"))?;
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    socket.set_write_timeout(Some(PROBE_TIMEOUT))?;
+
+    let mut config: rustls::ClientConfig = (*http::tls_config()).clone();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())?;
+    let mut conn = ClientConnection::new(Arc::new(config), name)?;
+
+    let start = Instant::now();
+    while conn.is_handshaking() {
+        conn.complete_io(&mut socket)
+            .with_context(|| format!("TLS handshake with `{host}` failed"))?;
+    }
+    let handshake: Duration = start.elapsed();
+
+    Ok(Some(TlsInfo {
+        version: conn
+            .protocol_version()
+            .and_then(TlsVersion::from_protocol)
+            .context("Unknown TLS version")?,
+        alpn: conn
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        handshake,
+        cert_expiry: conn
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|cert| cert_expiry(cert)),
+    }))
+}
+
+/// `notAfter` of DER-encoded certificate
+fn cert_expiry(der: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+}
+
+/// Why certificate of mirror is rejected, e.g. expired or issued for another name,
+/// None if probe failed for other reasons
+pub fn certificate_error(err: &anyhow::Error) -> Option<String> {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .filter_map(|err| err.get_ref()?.downcast_ref::<rustls::Error>())
+        .chain(
+            err.chain()
+                .filter_map(|cause| cause.downcast_ref::<rustls::Error>()),
+        )
+        .find(|err| matches!(err, rustls::Error::InvalidCertificate(_)))
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_plain_http() {
+        let url = Url::parse("http://127.0.0.1:1/archlinux/").unwrap();
+        assert_eq!(probe(&url, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_cert_expiry() {
+        let der: &[u8] = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/mirror_cert.der"
+        ));
+        assert_eq!(
+            cert_expiry(der),
+            Some("2030-01-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(cert_expiry(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn test_certificate_error() {
+        let invalid = rustls::Error::InvalidCertificate(rustls::CertificateError::NotValidForName);
+        let err = anyhow::Error::new(io::Error::new(io::ErrorKind::InvalidData, invalid))
+            .context("TLS handshake failed");
+        assert_eq!(
+            certificate_error(&err).as_deref(),
+            Some("invalid peer certificate: NotValidForName")
+        );
+
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(certificate_error(&refused), None);
+    }
+
+    #[test]
+    fn test_tls_version() {
+        assert_eq!(
+            TlsVersion::from_protocol(ProtocolVersion::TLSv1_3),
+            Some(TlsVersion::Tls13)
+        );
+        assert_eq!(TlsVersion::from_protocol(ProtocolVersion::TLSv1_1), None);
+        assert_eq!(TlsVersion::Tls12.name(), "TLSv1.2");
+        assert!(TlsVersion::Tls12 < TlsVersion::Tls13);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    args::TrendsArgs,
+    history::History,
+    paths,
+    samples::{self, Aggregate},
+};
+
+/// Transfer rates of a mirror in order of runs, oldest first.
+/// None is a failed measurement.
+type Series = BTreeMap<String, Vec<Option<f64>>>;
+
+#[derive(Debug, PartialEq)]
+pub struct Trend {
+    pub url: String,
+    pub runs: usize,
+    pub failures: usize,
+    pub mean: f64,
+    pub stddev: Option<f64>,
+
+    /// Mean of recent runs, None if there are no earlier runs to compare with
+    pub recent_mean: Option<f64>,
+
+    /// Change of recent mean relative to earlier mean, in percent
+    pub change: Option<f64>,
+}
+
+impl Trend {
+    pub fn is_regressed(&self, threshold: f64) -> bool {
+        self.change.is_some_and(|change| change < -threshold)
+    }
+}
+
+/// Run `trends` subcommand
+pub fn run(args: &TrendsArgs) -> Result<()> {
+    let series: Series = match (&args.history_db, &args.stats_dir) {
+        (Some(path), _) => History::open(&path.clone().unwrap_or_else(paths::history_db))?
+            .transfer_rates()?
+            .into_iter()
+            .fold(Series::new(), |mut series, (url, rate)| {
+                series.entry(url).or_default().push(rate);
+                series
+            }),
+        (None, Some(dir)) => load_stats_dir(dir)?,
+        (None, None) => bail!("Either `--history-db` or `--stats-dir` is required"),
+    };
+
+    let trends: Vec<Trend> = analyze(&series, args.recent);
+    if trends.is_empty() {
+        bail!("No measurements found");
+    }
+    print!("{}", report(&trends, args.threshold));
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct StatsRow {
+    url: String,
+    transfer_rate: Option<f64>,
+}
+
+/// Load statistics CSV files of a directory, one file per run ordered by file name
+fn load_stats_dir(dir: &Path) -> Result<Series> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Could not read directory `{}`", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+        .collect();
+    files.sort();
+
+    let mut series = Series::new();
+    for file in files {
+        let mut rdr = csv::Reader::from_path(&file)
+            .with_context(|| format!("Could not open `{}`", file.display()))?;
+        for row in rdr.deserialize::<StatsRow>() {
+            let row = row.with_context(|| format!("Invalid stats file `{}`", file.display()))?;
+            series.entry(row.url).or_default().push(row.transfer_rate);
+        }
+    }
+    Ok(series)
+}
+
+/// Compare mean transfer rate of the latest `recent` runs of each mirror against earlier runs
+pub fn analyze(series: &Series, recent: usize) -> Vec<Trend> {
+    let mut trends: Vec<Trend> = series
+        .iter()
+        .filter_map(|(url, rates)| {
+            let measured: Vec<f64> = rates.iter().flatten().copied().collect();
+            let mean: f64 = samples::aggregate(&measured, Aggregate::Mean)?;
+
+            let split: usize = measured.len().saturating_sub(recent);
+            let (earlier, latest) = measured.split_at(split);
+            let earlier_mean: Option<f64> = samples::aggregate(earlier, Aggregate::Mean);
+            let recent_mean: Option<f64> =
+                earlier_mean.and_then(|_| samples::aggregate(latest, Aggregate::Mean));
+            let change: Option<f64> = match (earlier_mean, recent_mean) {
+                (Some(e), Some(r)) if e > 0.0 => Some((r - e) / e * 100.0),
+                _ => None,
+            };
+
+            Some(Trend {
+                url: url.clone(),
+                runs: rates.len(),
+                failures: rates.len() - measured.len(),
+                mean,
+                stddev: samples::stddev(&measured),
+                recent_mean,
+                change,
+            })
+        })
+        .collect();
+    trends.sort_by(|a, b| b.mean.total_cmp(&a.mean));
+    trends
+}
+
+/// Format trends as a table, transfer rates are in bytes per second
+pub fn report(trends: &[Trend], threshold: f64) -> String {
+    let optional = |value: Option<f64>, precision: usize| match value {
+        Some(v) => format!("{v:.precision$}"),
+        None => "-".to_string(),
+    };
+
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "{:>5} {:>8} {:>12} {:>12} {:>12} {:>8}  URL",
+        "RUNS", "FAILURES", "MEAN", "STDDEV", "RECENT", "CHANGE%"
+    );
+    for t in trends {
+        let _ = writeln!(
+            table,
+            "{:>5} {:>8} {:>12.0} {:>12} {:>12} {:>8}  {}{}",
+            t.runs,
+            t.failures,
+            t.mean,
+            optional(t.stddev, 0),
+            optional(t.recent_mean, 0),
+            optional(t.change, 1),
+            t.url,
+            if t.is_regressed(threshold) {
+                "  [REGRESSED]"
+            } else {
+                ""
+            }
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let mut series = Series::new();
+        series.insert(
+            "https://steady.example/".to_string(),
+            vec![Some(100.0), Some(100.0), Some(100.0), Some(100.0)],
+        );
+        series.insert(
+            "https://degrading.example/".to_string(),
+            vec![Some(200.0), Some(200.0), None, Some(100.0), Some(100.0)],
+        );
+        series.insert("https://broken.example/".to_string(), vec![None, None]);
+
+        let trends = analyze(&series, 2);
+        assert_eq!(trends.len(), 2);
+
+        let degrading = &trends[0];
+        assert_eq!(degrading.url, "https://degrading.example/");
+        assert_eq!(degrading.runs, 5);
+        assert_eq!(degrading.failures, 1);
+        assert_eq!(degrading.mean, 150.0);
+        assert_eq!(degrading.recent_mean, Some(100.0));
+        assert_eq!(degrading.change, Some(-50.0));
+        assert!(degrading.is_regressed(20.0));
+
+        let steady = &trends[1];
+        assert_eq!(steady.change, Some(0.0));
+        assert!(!steady.is_regressed(20.0));
+
+        // Not enough runs to compare
+        let trends = analyze(&series, 10);
+        assert!(trends.iter().all(|t| t.change.is_none()));
+    }
+
+    #[test]
+    fn test_load_stats_dir() {
+        let series = load_stats_dir(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/stats"
+        )))
+        .unwrap();
+        assert_eq!(
+            series["https://mirror.a/archlinux/"],
+            vec![Some(1000.0), Some(800.0)]
+        );
+        assert_eq!(
+            series["https://mirror.b/archlinux/"],
+            vec![None, Some(500.0)]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let trends = vec![Trend {
+            url: "https://degrading.example/".to_string(),
+            runs: 5,
+            failures: 1,
+            mean: 150.0,
+            stddev: Some(57.7),
+            recent_mean: Some(100.0),
+            change: Some(-50.0),
+        }];
+        let report = report(&trends, 20.0);
+        assert_eq!(report.lines().count(), 2);
+        assert!(report.lines().nth(1).unwrap().ends_with("[REGRESSED]"));
+    }
+}
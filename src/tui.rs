@@ -0,0 +1,460 @@
+use std::{
+    cmp::Ordering,
+    io::{self, Stderr},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Cell, Row, Table, TableState},
+    Frame, Terminal,
+};
+use rayon::prelude::*;
+
+use crate::{
+    mirror::{Benchmark, BenchmarkOptions, Mirror, Mirrors},
+    report,
+};
+
+/// How often keys and benchmark results are checked
+const TICK: Duration = Duration::from_millis(100);
+
+/// Benchmark progress of a mirror
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    Pending,
+    Running,
+    Done,
+}
+
+/// Message from benchmark thread
+enum Update {
+    Started(usize),
+    Finished(usize, Box<Mirror>),
+}
+
+/// Column mirrors are sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SortBy {
+    #[default]
+    TransferRate,
+    Score,
+    Delay,
+    Country,
+    Url,
+}
+
+impl SortBy {
+    fn next(self) -> Self {
+        match self {
+            SortBy::TransferRate => SortBy::Score,
+            SortBy::Score => SortBy::Delay,
+            SortBy::Delay => SortBy::Country,
+            SortBy::Country => SortBy::Url,
+            SortBy::Url => SortBy::TransferRate,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            SortBy::TransferRate => "Transfer Rate",
+            SortBy::Score => "Score",
+            SortBy::Delay => "Delay",
+            SortBy::Country => "Country",
+            SortBy::Url => "Mirror",
+        }
+    }
+
+    /// Best first: fastest rate, lowest score and delay, then alphabetical.
+    /// Missing values are always last.
+    fn compare(self, a: &Mirror, b: &Mirror) -> Ordering {
+        fn last<T: PartialOrd>(a: Option<T>, b: Option<T>, best_first: Ordering) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    let order = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+                    if best_first == Ordering::Greater {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+
+        match self {
+            SortBy::TransferRate => last(a.transfer_rate, b.transfer_rate, Ordering::Greater),
+            SortBy::Score => last(a.score, b.score, Ordering::Less),
+            SortBy::Delay => last(a.delay, b.delay, Ordering::Less),
+            SortBy::Country => a.country.cmp(&b.country),
+            SortBy::Url => a.url.cmp(&b.url),
+        }
+    }
+}
+
+/// State of interactive ranking
+struct App {
+    mirrors: Vec<Mirror>,
+    progress: Vec<Progress>,
+    enabled: Vec<bool>,
+
+    /// Indices of `mirrors` in display order
+    order: Vec<usize>,
+    sort_by: SortBy,
+    reversed: bool,
+    table: TableState,
+}
+
+impl App {
+    fn new(mirrors: Vec<Mirror>) -> Self {
+        let n: usize = mirrors.len();
+        Self {
+            mirrors,
+            progress: vec![Progress::Pending; n],
+            enabled: vec![true; n],
+            order: (0..n).collect(),
+            sort_by: SortBy::default(),
+            reversed: false,
+            table: TableState::default().with_selected(Some(0)),
+        }
+    }
+
+    /// Sort rows, keeping the selected mirror selected
+    fn sort(&mut self) {
+        let selected: Option<usize> = self
+            .table
+            .selected()
+            .and_then(|row| self.order.get(row).copied());
+        let mirrors: &[Mirror] = &self.mirrors;
+        let (sort_by, reversed) = (self.sort_by, self.reversed);
+        self.order.sort_by(|&a, &b| {
+            let order = sort_by.compare(&mirrors[a], &mirrors[b]);
+            if reversed {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        if let Some(selected) = selected {
+            self.table
+                .select(self.order.iter().position(|&i| i == selected));
+        }
+    }
+
+    fn update(&mut self, update: Update) {
+        match update {
+            Update::Started(i) => self.progress[i] = Progress::Running,
+            Update::Finished(i, mirror) => {
+                self.mirrors[i] = *mirror;
+                self.progress[i] = Progress::Done;
+                self.sort();
+            }
+        }
+    }
+
+    fn toggle(&mut self) {
+        if let Some(&i) = self.table.selected().and_then(|row| self.order.get(row)) {
+            self.enabled[i] = !self.enabled[i];
+        }
+    }
+
+    fn done(&self) -> usize {
+        self.progress
+            .iter()
+            .filter(|&&p| p == Progress::Done)
+            .count()
+    }
+
+    /// Benchmarked mirrors in input order, and enabled ones in display order.
+    /// Mirrors not benchmarked yet, or failed, are never picked.
+    fn into_result(self) -> (Mirrors, Mirrors) {
+        let picked: Mirrors = self
+            .order
+            .iter()
+            .filter(|&&i| {
+                self.enabled[i]
+                    && self.progress[i] == Progress::Done
+                    && self.mirrors[i].transfer_rate.is_some()
+            })
+            .map(|&i| self.mirrors[i].clone())
+            .collect();
+        (self.mirrors.into_iter().collect(), picked)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, help_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let rows: Vec<Row> = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(rank, &i)| {
+                let mirror: &Mirror = &self.mirrors[i];
+                let (rate, style): (String, Style) = match self.progress[i] {
+                    Progress::Pending => (String::new(), Style::default()),
+                    Progress::Running => ("testing…".to_string(), Style::default()),
+                    Progress::Done => match mirror.transfer_rate {
+                        Some(rate) => (report::human_rate(rate), Style::default()),
+                        None => ("failed".to_string(), Style::default().fg(Color::Red)),
+                    },
+                };
+                let style: Style = if self.enabled[i] {
+                    style
+                } else {
+                    style.add_modifier(Modifier::DIM | Modifier::CROSSED_OUT)
+                };
+                Row::new(vec![
+                    Cell::from(if self.enabled[i] { "[x]" } else { "[ ]" }),
+                    Cell::from(format!("{:>3}", rank + 1)),
+                    Cell::from(mirror.url.clone()),
+                    Cell::from(mirror.country.clone()),
+                    Cell::from(format!("{rate:>12}")),
+                    Cell::from(
+                        mirror
+                            .score
+                            .map(|score| format!("{score:>6.2}"))
+                            .unwrap_or_default(),
+                    ),
+                    Cell::from(
+                        mirror
+                            .delay
+                            .map(|delay| format!("{delay:>6}"))
+                            .unwrap_or_default(),
+                    ),
+                ])
+                .style(style)
+            })
+            .collect();
+
+        let header = Row::new(vec![
+            "",
+            "#",
+            "Mirror",
+            "Country",
+            "Transfer Rate",
+            "Score",
+            "Delay",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+        let title: String = format!(
+            " Benchmarked {}/{} mirrors, sorted by {}{} ",
+            self.done(),
+            self.mirrors.len(),
+            self.sort_by.title(),
+            if self.reversed { " (reversed)" } else { "" }
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Fill(1),
+                Constraint::Length(20),
+                Constraint::Length(13),
+                Constraint::Length(6),
+                Constraint::Length(6),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+
+        frame.render_widget(
+            Line::from(
+                "↑/↓ move  space toggle  s sort column  r reverse  enter write list  q quit",
+            ),
+            help_area,
+        );
+    }
+}
+
+/// Benchmark `mirrors` showing live progress, then let user sort and toggle mirrors.
+///
+/// Returns all benchmarked mirrors and those picked by user in display order.
+pub fn run(mirrors: &Mirrors, options: &BenchmarkOptions) -> Result<(Mirrors, Mirrors)> {
+    let mut terminal = init().context("Could not start interactive mode")?;
+    let cancel = AtomicBool::new(false);
+    let result: Result<Option<(Mirrors, Mirrors)>> = thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel::<Update>();
+        scope.spawn(|| benchmark(mirrors, options, tx, &cancel));
+
+        let result = event_loop(&mut terminal, App::new(mirrors.to_vec()), &rx);
+
+        // Skip mirrors not yet started, scope waits for running ones
+        cancel.store(true, AtomicOrdering::Relaxed);
+        result
+    });
+    restore(&mut terminal)?;
+
+    match result? {
+        Some(result) => Ok(result),
+        None => bail!("Interactive ranking was cancelled"),
+    }
+}
+
+/// Measure mirrors in parallel, reporting each start and result
+fn benchmark(
+    mirrors: &Mirrors,
+    options: &BenchmarkOptions,
+    tx: Sender<Update>,
+    cancel: &AtomicBool,
+) {
+    mirrors
+        .par_iter()
+        .enumerate()
+        .for_each_with(tx, |tx, (i, mirror)| {
+            if cancel.load(AtomicOrdering::Relaxed) {
+                return;
+            }
+            let _ = tx.send(Update::Started(i));
+            let mut mirror: Mirror = mirror.clone();
+            let _ = mirror.measure_duration(options);
+            let _ = tx.send(Update::Finished(i, Box::new(mirror)));
+        });
+}
+
+/// Draw until user writes the list (Some) or quits (None)
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+    mut app: App,
+    rx: &Receiver<Update>,
+) -> Result<Option<(Mirrors, Mirrors)>> {
+    loop {
+        while let Ok(update) = rx.try_recv() {
+            app.update(update);
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+            KeyCode::Enter => return Ok(Some(app.into_result())),
+            KeyCode::Up | KeyCode::Char('k') => app.table.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => app.table.select_next(),
+            KeyCode::Char(' ') => app.toggle(),
+            KeyCode::Char('s') => {
+                app.sort_by = app.sort_by.next();
+                app.sort();
+            }
+            KeyCode::Char('r') => {
+                app.reversed = !app.reversed;
+                app.sort();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Draw on STDERR, so that mirror list can still be written to STDOUT
+fn init() -> io::Result<Terminal<CrosstermBackend<Stderr>>> {
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(io::stderr()))
+}
+
+fn restore(terminal: &mut Terminal<CrosstermBackend<Stderr>>) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app() {
+        let mut app = App::new(vec![
+            Mirror::from_url("https://a.example/"),
+            Mirror::from_url("https://b.example/"),
+            Mirror::from_url("https://c.example/"),
+        ]);
+        app.update(Update::Started(1));
+        assert_eq!(app.progress[1], Progress::Running);
+        app.update(Update::Finished(
+            1,
+            Box::new(Mirror::with_transfer_rate(
+                "https://b.example/",
+                Some(2000.0),
+            )),
+        ));
+        app.update(Update::Finished(
+            2,
+            Box::new(Mirror::with_transfer_rate(
+                "https://c.example/",
+                Some(1000.0),
+            )),
+        ));
+        assert_eq!(app.done(), 2);
+
+        // Fastest first, not yet measured last
+        assert_eq!(app.order, vec![1, 2, 0]);
+
+        // Toggle off fastest
+        app.table.select(Some(0));
+        app.toggle();
+        let (measured, picked) = app.into_result();
+        assert_eq!(measured.len(), 3);
+        let urls: Vec<&str> = picked.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://c.example/"]);
+    }
+
+    #[test]
+    fn test_selection_follows_mirror() {
+        let mut app = App::new(vec![
+            Mirror::from_url("https://a.example/"),
+            Mirror::from_url("https://b.example/"),
+        ]);
+        assert_eq!(app.table.selected(), Some(0));
+
+        // Selected mirror moves down when the other one is measured
+        app.update(Update::Finished(
+            1,
+            Box::new(Mirror::with_transfer_rate(
+                "https://b.example/",
+                Some(1000.0),
+            )),
+        ));
+        assert_eq!(app.order, vec![1, 0]);
+        assert_eq!(app.table.selected(), Some(1));
+
+        app.toggle();
+        assert_eq!(app.enabled, vec![false, true]);
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let a = Mirror::with_transfer_rate("https://a.example/", Some(1.0));
+        let b = Mirror::from_url("https://b.example/");
+        assert_eq!(SortBy::TransferRate.compare(&a, &b), Ordering::Less);
+        assert_eq!(SortBy::Url.compare(&b, &a), Ordering::Greater);
+        assert_eq!(SortBy::Url.next(), SortBy::TransferRate);
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use tracing::info;
+
+use crate::{
+    args::InstallUnitsArgs,
+    cmdline,
+    output::{self, Overwrite},
+};
+
+const SERVICE_NAME: &str = "pacman-mirrorup.service";
+const TIMER_NAME: &str = "pacman-mirrorup.timer";
+
+/// Units shipped with the package, used as templates
+const SERVICE: &str = include_str!("../pacman-mirrorup.service");
+const TIMER: &str = include_str!("../pacman-mirrorup.timer");
+
+/// Run `install-units` subcommand, `mirrorup_args` are options of the service
+pub fn run(args: &InstallUnitsArgs, mirrorup_args: &[String], force: bool) -> Result<()> {
+    let overwrite: Overwrite = if force {
+        Overwrite::Always
+    } else {
+        Overwrite::Never
+    };
+
+    let units: [(PathBuf, String); 2] = [
+        (args.dir.join(SERVICE_NAME), service(mirrorup_args)?),
+        (args.dir.join(TIMER_NAME), timer(&args.interval)?),
+    ];
+    for (path, _) in &units {
+        if !force && path.exists() {
+            bail!("`{}` is exist.", path.display());
+        }
+    }
+    for (path, unit) in &units {
+        save(path, unit, overwrite)?;
+    }
+
+    if !output::is_dry_run() {
+        info!("Run `systemctl daemon-reload && systemctl enable --now {TIMER_NAME}` to activate");
+    }
+    Ok(())
+}
+
+fn save(path: &Path, unit: &str, overwrite: Overwrite) -> Result<()> {
+    output::save(path, unit.as_bytes(), overwrite)
+        .with_context(|| format!("Could not write unit `{}`", path.display()))
+}
+
+/// Service unit running pacman-mirrorup with `mirrorup_args`,
+/// shipped options are kept if none is given
+fn service(mirrorup_args: &[String]) -> Result<String> {
+    if mirrorup_args.is_empty() {
+        return Ok(SERVICE.to_string());
+    }
+
+    // Split on whitespace by `$MIRRORUP_ARGS`, `%` and `$` are escaped from systemd
+    // specifier and variable expansion
+    let args: String = cmdline::join(mirrorup_args, "systemd unit")?
+        .replace('%', "%%")
+        .replace('$', "$$");
+    let environment: String = format!("Environment='MIRRORUP_ARGS={args}'");
+    Ok(replace_line(
+        SERVICE,
+        "Environment='MIRRORUP_ARGS=",
+        &environment,
+    ))
+}
+
+/// Timer unit triggering the service on `interval`, a systemd calendar event like `weekly`
+fn timer(interval: &str) -> Result<String> {
+    if interval.trim().is_empty() || interval.contains('\n') {
+        bail!("Invalid interval `{interval}`");
+    }
+    Ok(replace_line(
+        TIMER,
+        "OnCalendar=",
+        &format!("OnCalendar={}", interval.trim()),
+    ))
+}
+
+/// Replace every line starting with `prefix`
+fn replace_line(unit: &str, prefix: &str, line: &str) -> String {
+    unit.lines()
+        .map(|l| if l.starts_with(prefix) { line } else { l })
+        .map(|l| format!("{l}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_service() {
+        let unit = service(&args(&[
+            "--output-file",
+            "/etc/pacman.d/mirrorlist",
+            "--in-place",
+            "--exclude",
+            "bad.example",
+            "--min-improvement",
+            "20%",
+        ]))
+        .unwrap();
+        assert!(unit.contains(
+            "\nEnvironment='MIRRORUP_ARGS=--output-file /etc/pacman.d/mirrorlist --in-place --exclude bad.example --min-improvement 20%%'\n"
+        ));
+        assert!(unit.contains(
+            "\nExecStart=/usr/bin/pacman-mirrorup --log-target journald $MIRRORUP_ARGS\n"
+        ));
+        assert_eq!(unit.matches("MIRRORUP_ARGS=").count(), 1);
+
+        assert_eq!(service(&[]).unwrap(), SERVICE);
+        assert!(service(&args(&["--exclude-from", "/etc/my mirrors"])).is_err());
+    }
+
+    #[test]
+    fn test_timer() {
+        let unit = timer("daily").unwrap();
+        assert!(unit.contains("\nOnCalendar=daily\n"));
+        assert!(!unit.contains("weekly"));
+        assert!(unit.contains("\nPersistent=true\n"));
+        assert!(timer("").is_err());
+    }
+}